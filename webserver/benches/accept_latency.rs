@@ -0,0 +1,88 @@
+//! Compares connection latency of the old sleep-polling accept loop with the
+//! `poll(2)`-based `Acceptor`.
+//!
+//! Run with `cargo bench --bench accept_latency`.
+
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    thread,
+    time::{Duration, Instant},
+};
+
+use webserver::acceptor::{Accepted, Acceptor};
+
+const CONNECTIONS: usize = 50;
+const RESPONSE: &[u8] = b"HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n";
+
+/// The accept loop as it was: non-blocking accept plus a 100 ms nap.
+fn sleep_polling_server(listener: TcpListener) {
+    listener.set_nonblocking(true).unwrap();
+    let mut served = 0;
+    while served < CONNECTIONS {
+        match listener.accept() {
+            Ok((mut stream, _)) => {
+                stream.set_nonblocking(false).unwrap();
+                stream.write_all(RESPONSE).unwrap();
+                served += 1;
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(100));
+            }
+            Err(e) => panic!("accept failed: {e}"),
+        }
+    }
+}
+
+fn acceptor_server(listener: TcpListener) {
+    let (acceptor, _waker) = Acceptor::new(vec![listener]).unwrap();
+    let mut served = 0;
+    while served < CONNECTIONS {
+        if let Accepted::Connection { mut stream, .. } = acceptor.accept().unwrap() {
+            stream.write_all(RESPONSE).unwrap();
+            served += 1;
+        }
+    }
+}
+
+/// Connects `CONNECTIONS` times with irregular pauses and returns the time
+/// each response took to arrive, sorted.
+fn measure(address: SocketAddr) -> Vec<Duration> {
+    let mut latencies = Vec::with_capacity(CONNECTIONS);
+    for i in 0..CONNECTIONS {
+        // Spread arrivals over the polling interval.
+        thread::sleep(Duration::from_millis((i as u64 * 37) % 23));
+
+        let start = Instant::now();
+        let mut stream = TcpStream::connect(address).unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        latencies.push(start.elapsed());
+    }
+    latencies.sort();
+    latencies
+}
+
+fn run(name: &str, server: fn(TcpListener)) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let handle = thread::spawn(move || server(listener));
+
+    let latencies = measure(address);
+    handle.join().unwrap();
+
+    let mean = latencies.iter().sum::<Duration>() / latencies.len() as u32;
+    let percentile = |p: usize| latencies[(latencies.len() - 1) * p / 100];
+    println!(
+        "{name:<14} mean {:>9.3?}  p50 {:>9.3?}  p99 {:>9.3?}",
+        mean,
+        percentile(50),
+        percentile(99)
+    );
+}
+
+fn main() {
+    println!("{CONNECTIONS} connections each");
+    run("sleep-polling", sleep_polling_server);
+    run("acceptor", acceptor_server);
+}
@@ -0,0 +1,154 @@
+//! An accept loop that sleeps in `poll(2)` until a connection or a wakeup
+//! arrives, instead of polling non-blocking listeners on a timer.
+
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    os::{fd::AsRawFd, unix::net::UnixStream},
+    sync::Arc,
+};
+
+/// What [`Acceptor::accept`] returned.
+#[derive(Debug)]
+pub enum Accepted {
+    /// A new client on the listener at index `listener`.
+    Connection {
+        stream: TcpStream,
+        peer: SocketAddr,
+        listener: usize,
+    },
+    /// [`Waker::wake`] was called, e.g. because shutdown began.
+    Woken,
+}
+
+/// Waits on one or more listeners at once.
+pub struct Acceptor {
+    listeners: Vec<TcpListener>,
+    wakeup: UnixStream,
+}
+
+/// Interrupts a blocked [`Acceptor::accept`] from another thread.
+///
+/// This is the self-pipe trick: waking writes a byte to a socket the acceptor
+/// polls alongside its listeners.
+#[derive(Clone)]
+pub struct Waker {
+    sender: Arc<UnixStream>,
+}
+
+impl Acceptor {
+    /// Wraps `listeners` and returns the acceptor with its waker.
+    pub fn new(listeners: Vec<TcpListener>) -> io::Result<(Acceptor, Waker)> {
+        for listener in &listeners {
+            // accept() must not block if another thread won the race.
+            listener.set_nonblocking(true)?;
+        }
+        let (sender, wakeup) = UnixStream::pair()?;
+        sender.set_nonblocking(true)?;
+        wakeup.set_nonblocking(true)?;
+
+        let acceptor = Acceptor { listeners, wakeup };
+        let waker = Waker {
+            sender: Arc::new(sender),
+        };
+        Ok((acceptor, waker))
+    }
+
+    /// The listeners, in the order given to [`Acceptor::new`].
+    pub fn listeners(&self) -> &[TcpListener] {
+        &self.listeners
+    }
+
+    /// Blocks until a client connects or the waker fires.
+    ///
+    /// Accepted streams are switched back to blocking mode.
+    pub fn accept(&self) -> io::Result<Accepted> {
+        loop {
+            let mut fds: Vec<libc::pollfd> = self
+                .listeners
+                .iter()
+                .map(|listener| listener.as_raw_fd())
+                .chain(Some(self.wakeup.as_raw_fd()))
+                .map(|fd| libc::pollfd {
+                    fd,
+                    events: libc::POLLIN,
+                    revents: 0,
+                })
+                .collect();
+
+            // SAFETY: `fds` is a valid, exclusively borrowed array of
+            // `fds.len()` pollfd structs for the duration of the call.
+            let ready = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) };
+            if ready < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+
+            if fds.last().is_some_and(|wakeup| wakeup.revents != 0) {
+                self.drain_wakeups();
+                return Ok(Accepted::Woken);
+            }
+
+            for (index, fd) in fds.iter().enumerate().take(self.listeners.len()) {
+                if fd.revents == 0 {
+                    continue;
+                }
+                match self.listeners[index].accept() {
+                    Ok((stream, peer)) => {
+                        stream.set_nonblocking(false)?;
+                        return Ok(Accepted::Connection {
+                            stream,
+                            peer,
+                            listener: index,
+                        });
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => continue,
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+    }
+
+    fn drain_wakeups(&self) {
+        let mut buf = [0; 64];
+        while matches!((&self.wakeup).read(&mut buf), Ok(n) if n > 0) {}
+    }
+}
+
+impl Waker {
+    /// Makes the current or next [`Acceptor::accept`] return
+    /// [`Accepted::Woken`].
+    pub fn wake(&self) {
+        // A full buffer means a wakeup is already pending.
+        let _ = (&*self.sender).write(&[1]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn wakes_up_and_accepts() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (acceptor, waker) = Acceptor::new(vec![listener]).unwrap();
+
+        let remote = waker.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            remote.wake();
+        });
+        assert!(matches!(acceptor.accept().unwrap(), Accepted::Woken));
+
+        let _client = TcpStream::connect(address).unwrap();
+        match acceptor.accept().unwrap() {
+            Accepted::Connection { listener, .. } => assert_eq!(listener, 0),
+            Accepted::Woken => panic!("expected a connection"),
+        }
+    }
+}
@@ -0,0 +1,415 @@
+//! Access logging: one record per request, in Common/Combined Log Format or
+//! as JSON lines, written to stdout or a size-rotated file.
+
+use std::{
+    fmt::{self, Write as _},
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Mutex, PoisonError},
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{date::DateTime, log::error, request::Request, status::StatusCode};
+
+/// How each record is laid out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// `host - - [time] "request" status size`, then duration and
+    /// connection id.
+    Common,
+    /// Common plus the quoted `Referer` and `User-Agent`.
+    #[default]
+    Combined,
+    /// One JSON object per line.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "common" => Ok(LogFormat::Common),
+            "combined" => Ok(LogFormat::Combined),
+            "json" => Ok(LogFormat::Json),
+            _ => Err("expected common, combined or json".to_owned()),
+        }
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LogFormat::Common => "common",
+            LogFormat::Combined => "combined",
+            LogFormat::Json => "json",
+        })
+    }
+}
+
+/// Where records go.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum LogTarget {
+    /// Access logging is disabled.
+    Off,
+    #[default]
+    Stdout,
+    /// Appended to this file, which is rotated when it grows too large.
+    File(PathBuf),
+}
+
+impl From<&str> for LogTarget {
+    /// `off` and `stdout` (or `-`) are special, anything else is a path.
+    fn from(value: &str) -> Self {
+        match value {
+            "off" => LogTarget::Off,
+            "stdout" | "-" => LogTarget::Stdout,
+            path => LogTarget::File(PathBuf::from(path)),
+        }
+    }
+}
+
+/// When a log file is rotated and how many old ones are kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rotation {
+    /// Size in bytes after which the file is rotated; 0 never rotates.
+    pub max_size: u64,
+    /// Rotated files kept as `<path>.1` (newest) to `<path>.<keep>`.
+    pub keep: usize,
+}
+
+impl Default for Rotation {
+    fn default() -> Self {
+        Rotation {
+            max_size: 10 * 1024 * 1024,
+            keep: 5,
+        }
+    }
+}
+
+/// Everything logged about one request.
+#[derive(Debug, Clone, Copy)]
+pub struct AccessRecord<'a> {
+    pub connection_id: Uuid,
+    pub client: Option<SocketAddr>,
+    /// When the request started arriving.
+    pub time: SystemTime,
+    /// The request, or `None` if it could not be parsed.
+    pub request: Option<&'a Request>,
+    pub status: StatusCode,
+    /// Body bytes sent.
+    pub size: u64,
+    /// From the first byte of the request to the last of the response.
+    pub duration: Duration,
+}
+
+/// Writes [`AccessRecord`]s; shared by all connections.
+#[derive(Debug)]
+pub struct AccessLog {
+    format: LogFormat,
+    sink: Mutex<Sink>,
+}
+
+#[derive(Debug)]
+enum Sink {
+    Stdout,
+    File(RotatingFile),
+}
+
+impl AccessLog {
+    /// Logs to standard output.
+    pub fn stdout(format: LogFormat) -> AccessLog {
+        AccessLog {
+            format,
+            sink: Mutex::new(Sink::Stdout),
+        }
+    }
+
+    /// Appends to the file at `path`, creating it if needed.
+    pub fn file(
+        path: impl Into<PathBuf>,
+        format: LogFormat,
+        rotation: Rotation,
+    ) -> io::Result<AccessLog> {
+        Ok(AccessLog {
+            format,
+            sink: Mutex::new(Sink::File(RotatingFile::open(path.into(), rotation)?)),
+        })
+    }
+
+    /// Opens the log for `target`; `None` if logging is off.
+    pub fn open(
+        target: &LogTarget,
+        format: LogFormat,
+        rotation: Rotation,
+    ) -> io::Result<Option<AccessLog>> {
+        match target {
+            LogTarget::Off => Ok(None),
+            LogTarget::Stdout => Ok(Some(AccessLog::stdout(format))),
+            LogTarget::File(path) => AccessLog::file(path, format, rotation).map(Some),
+        }
+    }
+
+    /// Writes one record. Failures are reported on stderr, not to the
+    /// client.
+    pub fn log(&self, record: &AccessRecord<'_>) {
+        let mut line = self.format(record);
+        line.push('\n');
+
+        let mut sink = self.sink.lock().unwrap_or_else(PoisonError::into_inner);
+        let result = match &mut *sink {
+            Sink::Stdout => io::stdout().lock().write_all(line.as_bytes()),
+            Sink::File(file) => file.write_line(line.as_bytes()),
+        };
+        if let Err(e) = result {
+            error!("[{}] Cannot write access log: {e}", record.connection_id);
+        }
+    }
+
+    /// Formats `record` without the trailing newline.
+    pub fn format(&self, record: &AccessRecord<'_>) -> String {
+        match self.format {
+            LogFormat::Common => clf(record, false),
+            LogFormat::Combined => clf(record, true),
+            LogFormat::Json => json(record),
+        }
+    }
+}
+
+/// Common or Combined Log Format, followed by the duration in microseconds
+/// (Apache's `%D`) and the connection id.
+fn clf(record: &AccessRecord<'_>, combined: bool) -> String {
+    let mut line = String::new();
+    let client = record.client.map(|addr| addr.ip().to_string());
+    let _ = write!(
+        line,
+        "{} - - [{}] ",
+        client.as_deref().unwrap_or("-"),
+        DateTime::from_system_time(record.time).to_clf()
+    );
+
+    match record.request {
+        Some(request) => {
+            let request_line = format!(
+                "{} {} {}",
+                request.method(),
+                request.target(),
+                request.version()
+            );
+            push_quoted(&mut line, Some(&request_line));
+        }
+        None => push_quoted(&mut line, None),
+    }
+
+    let _ = write!(line, " {} ", record.status.as_u16());
+    match record.size {
+        0 => line.push('-'),
+        size => {
+            let _ = write!(line, "{size}");
+        }
+    }
+
+    if combined {
+        let header = |name| record.request.and_then(|request| request.header(name));
+        line.push(' ');
+        push_quoted(&mut line, header("Referer"));
+        line.push(' ');
+        push_quoted(&mut line, header("User-Agent"));
+    }
+
+    let _ = write!(
+        line,
+        " {} {}",
+        record.duration.as_micros(),
+        record.connection_id
+    );
+    line
+}
+
+/// Appends `value` in double quotes, escaping quotes, backslashes and
+/// control characters like Apache does. `None` becomes `"-"`.
+fn push_quoted(line: &mut String, value: Option<&str>) {
+    line.push('"');
+    for c in value.unwrap_or("-").chars() {
+        match c {
+            '"' | '\\' => {
+                line.push('\\');
+                line.push(c);
+            }
+            c if c.is_control() => {
+                let _ = write!(line, "\\x{:02x}", c as u32);
+            }
+            c => line.push(c),
+        }
+    }
+    line.push('"');
+}
+
+fn json(record: &AccessRecord<'_>) -> String {
+    let request = record.request;
+    let header = |name| request.and_then(|request| request.header(name));
+
+    serde_json::json!({
+        "time": DateTime::from_system_time(record.time).to_rfc3339(),
+        "connection_id": record.connection_id.to_string(),
+        "client": record.client.map(|addr| addr.to_string()),
+        "method": request.map(|request| request.method().as_str()),
+        "path": request.map(Request::path),
+        "query": request.and_then(Request::query),
+        "version": request.map(|request| request.version().as_str()),
+        "status": record.status.as_u16(),
+        "size": record.size,
+        "duration_ms": record.duration.as_secs_f64() * 1000.0,
+        "referer": header("Referer"),
+        "user_agent": header("User-Agent"),
+    })
+    .to_string()
+}
+
+/// A log file that is moved to `<path>.1` once it reaches
+/// [`Rotation::max_size`], shifting older files up by one.
+#[derive(Debug)]
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    rotation: Rotation,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, rotation: Rotation) -> io::Result<RotatingFile> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path,
+            file,
+            size,
+            rotation,
+        })
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        let max_size = self.rotation.max_size;
+        if max_size > 0 && self.size > 0 && self.size + line.len() as u64 > max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(line)?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let keep = self.rotation.keep;
+        if keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for n in (1..keep).rev() {
+                let from = numbered(&self.path, n);
+                if from.exists() {
+                    fs::rename(&from, numbered(&self.path, n + 1))?;
+                }
+            }
+            fs::rename(&self.path, numbered(&self.path, 1))?;
+        }
+        *self = RotatingFile::open(self.path.clone(), self.rotation)?;
+        Ok(())
+    }
+}
+
+/// `access.log` with `n = 2` becomes `access.log.2`.
+fn numbered(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{n}"));
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::ParseLimits;
+    use std::{env, io::Cursor, time::UNIX_EPOCH};
+
+    fn request(raw: &str) -> Request {
+        Request::parse(&mut Cursor::new(raw.as_bytes()), &ParseLimits::default()).unwrap()
+    }
+
+    fn record(request: Option<&Request>) -> AccessRecord<'_> {
+        AccessRecord {
+            connection_id: Uuid::nil(),
+            client: Some("192.0.2.7:51000".parse().unwrap()),
+            time: UNIX_EPOCH + Duration::from_secs(971_186_136),
+            request,
+            status: StatusCode::OK,
+            size: 2326,
+            duration: Duration::from_micros(1500),
+        }
+    }
+
+    #[test]
+    fn formats_combined_and_common() {
+        let request = request(
+            "GET /a.gif?x=1 HTTP/1.1\r\nHost: localhost\r\nReferer: http://example.com/\r\nUser-Agent: say \"hi\"\r\n\r\n",
+        );
+        let record = record(Some(&request));
+
+        assert_eq!(
+            AccessLog::stdout(LogFormat::Combined).format(&record),
+            "192.0.2.7 - - [10/Oct/2000:13:55:36 +0000] \"GET /a.gif?x=1 HTTP/1.1\" 200 2326 \
+             \"http://example.com/\" \"say \\\"hi\\\"\" 1500 00000000-0000-0000-0000-000000000000"
+        );
+        assert_eq!(
+            AccessLog::stdout(LogFormat::Common).format(&AccessRecord { size: 0, ..record }),
+            "192.0.2.7 - - [10/Oct/2000:13:55:36 +0000] \"GET /a.gif?x=1 HTTP/1.1\" 200 - \
+             1500 00000000-0000-0000-0000-000000000000"
+        );
+    }
+
+    #[test]
+    fn formats_json() {
+        let request = request("POST /form HTTP/1.0\r\n\r\n");
+        let line = AccessLog::stdout(LogFormat::Json).format(&record(Some(&request)));
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+
+        assert_eq!(value["time"], "2000-10-10T13:55:36.000Z");
+        assert_eq!(value["client"], "192.0.2.7:51000");
+        assert_eq!(value["method"], "POST");
+        assert_eq!(value["path"], "/form");
+        assert_eq!(value["status"], 200);
+        assert_eq!(value["duration_ms"], 1.5);
+        assert!(value["user_agent"].is_null());
+
+        let line = AccessLog::stdout(LogFormat::Json).format(&record(None));
+        assert!(line.contains("\"method\":null"));
+    }
+
+    #[test]
+    fn rotates_files() {
+        let dir = env::temp_dir().join(format!("webserver-log-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("access.log");
+        let rotation = Rotation {
+            max_size: 150,
+            keep: 2,
+        };
+        let log = AccessLog::file(&path, LogFormat::Common, rotation).unwrap();
+
+        for _ in 0..5 {
+            log.log(&record(None));
+        }
+
+        assert!(path.exists());
+        assert!(numbered(&path, 1).exists());
+        assert!(numbered(&path, 2).exists());
+        assert!(!numbered(&path, 3).exists());
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,151 @@
+//! Health checks for orchestrators and the admin listener.
+
+use std::{
+    sync::{Arc, PoisonError, atomic::Ordering},
+    time::Instant,
+};
+
+use serde_json::json;
+
+use crate::{
+    handler::Handler,
+    log::{self, info},
+    middleware::Middleware,
+    request::{Method, Request},
+    response::Response,
+    router::Router,
+    server::ShutdownHandle,
+    status::StatusCode,
+    worker::PoolStats,
+};
+
+/// Answers `GET /healthz` and `GET /readyz` before the inner layers see
+/// the request.
+///
+/// `/healthz` succeeds as long as the server answers at all. `/readyz`
+/// turns into `503 Service Unavailable` as soon as shutdown begins, so load
+/// balancers stop sending traffic while open connections drain.
+#[derive(Clone)]
+pub struct Health {
+    shutdown: ShutdownHandle,
+}
+
+impl Health {
+    /// Reports readiness from `shutdown`.
+    pub fn new(shutdown: ShutdownHandle) -> Health {
+        Health { shutdown }
+    }
+
+    /// The response for `path` if it is one of the probes.
+    fn probe(&self, path: &str) -> Option<Response> {
+        match path {
+            "/healthz" => Some(Response::text(StatusCode::OK, "ok\n")),
+            "/readyz" if self.shutdown.is_shutting_down() => Some(Response::text(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "shutting down\n",
+            )),
+            "/readyz" => Some(Response::text(StatusCode::OK, "ready\n")),
+            _ => None,
+        }
+    }
+}
+
+impl Middleware for Health {
+    fn handle(&self, request: &mut Request, next: &dyn Handler) -> Response {
+        if matches!(request.method(), Method::Get | Method::Head)
+            && let Some(response) = self.probe(request.path())
+        {
+            return response.with_header("Cache-Control", "no-store");
+        }
+        next.handle(request)
+    }
+}
+
+/// The routes of the admin listener:
+///
+/// - `GET /healthz`, `GET /readyz`: as with [`Health`]
+/// - `GET /config`: the settings in effect, as JSON
+/// - `GET /workers`: what each worker is doing, the queue and connections
+/// - `POST /shutdown`: starts a graceful shutdown, like Ctrl-C
+/// - `GET /log-level`, `PUT /log-level`: reads or replaces the [`log`] level
+///
+/// `config` is `None` for servers not built from a
+/// [`ServerConfig`](crate::ServerConfig).
+pub(crate) fn router(
+    shutdown: ShutdownHandle,
+    pool: Arc<PoolStats>,
+    config: Option<serde_json::Value>,
+) -> Router {
+    let health = Health::new(shutdown.clone());
+    let probe = move |request: &mut Request| {
+        health
+            .probe(request.path())
+            .unwrap_or_else(|| Response::error(StatusCode::NOT_FOUND))
+    };
+
+    let workers_shutdown = shutdown.clone();
+    Router::new()
+        .get("/healthz", probe.clone())
+        .get("/readyz", probe)
+        .get("/config", move |_: &mut Request| match &config {
+            Some(config) => Response::json(StatusCode::OK, config.to_string()),
+            None => Response::error(StatusCode::NOT_FOUND),
+        })
+        .get("/workers", move |_: &mut Request| {
+            Response::json(
+                StatusCode::OK,
+                workers(&pool, &workers_shutdown).to_string(),
+            )
+        })
+        .post("/shutdown", move |_: &mut Request| {
+            info!("Shutdown requested on the admin listener");
+            shutdown.shutdown();
+            Response::text(StatusCode::ACCEPTED, "shutting down\n")
+        })
+        .get("/log-level", |_: &mut Request| {
+            Response::text(StatusCode::OK, format!("{}\n", log::level()))
+        })
+        .put("/log-level", |request: &mut Request| {
+            let level = std::str::from_utf8(request.body())
+                .map_err(|e| e.to_string())
+                .and_then(str::parse::<log::Level>);
+            match level {
+                Ok(level) => {
+                    log::set_level(level);
+                    info!("Log level set to {level} on the admin listener");
+                    Response::text(StatusCode::OK, format!("{level}\n"))
+                }
+                Err(message) => Response::text(StatusCode::BAD_REQUEST, format!("{message}\n")),
+            }
+        })
+        .not_found(|_: &mut Request| Response::error(StatusCode::NOT_FOUND))
+}
+
+/// The state of the worker pool and the connections it serves.
+fn workers(pool: &PoolStats, shutdown: &ShutdownHandle) -> serde_json::Value {
+    let now = Instant::now();
+    let jobs = pool.jobs.lock().unwrap_or_else(PoisonError::into_inner);
+    let workers: Vec<_> = jobs
+        .iter()
+        .enumerate()
+        .map(|(id, job)| match job {
+            Some((job, since)) => json!({
+                "id": id,
+                "state": "busy",
+                "job": job.to_string(),
+                "busy_ms": now.duration_since(*since).as_millis() as u64,
+            }),
+            None => json!({ "id": id, "state": "idle" }),
+        })
+        .collect();
+    let (open, busy) = shutdown.connections();
+
+    json!({
+        "workers": workers,
+        "queued": pool.queued.load(Ordering::Relaxed),
+        "completed": pool.completed.load(Ordering::Relaxed),
+        "panicked": pool.panicked.load(Ordering::Relaxed),
+        "connections": { "open": open, "busy": busy },
+        "shutting_down": shutdown.is_shutting_down(),
+    })
+}
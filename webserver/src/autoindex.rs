@@ -0,0 +1,252 @@
+//! Directory listing pages for [`StaticFiles`](crate::StaticFiles).
+
+use std::{
+    cmp::Ordering,
+    fs, io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use serde_json::json;
+
+use crate::{
+    date::DateTime, mime, request::Request, response::Response, status::StatusCode,
+    url::percent_encode,
+};
+
+/// One file or directory in a listing.
+pub(crate) struct Entry {
+    pub(crate) path: PathBuf,
+    name: String,
+    is_dir: bool,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+impl Entry {
+    /// The media type without parameters, or `directory`.
+    fn kind(&self) -> &'static str {
+        if self.is_dir {
+            return "directory";
+        }
+        let content_type = mime::from_path(&self.path);
+        content_type.split(';').next().unwrap_or(content_type)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SortKey {
+    Name,
+    Size,
+    Modified,
+    Type,
+}
+
+impl SortKey {
+    const ALL: [SortKey; 4] = [
+        SortKey::Name,
+        SortKey::Size,
+        SortKey::Modified,
+        SortKey::Type,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            SortKey::Name => "name",
+            SortKey::Size => "size",
+            SortKey::Modified => "modified",
+            SortKey::Type => "type",
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            SortKey::Name => "Name",
+            SortKey::Size => "Size",
+            SortKey::Modified => "Modified",
+            SortKey::Type => "Type",
+        }
+    }
+
+    fn compare(self, a: &Entry, b: &Entry) -> Ordering {
+        let by_name = || {
+            a.name
+                .to_lowercase()
+                .cmp(&b.name.to_lowercase())
+                .then_with(|| a.name.cmp(&b.name))
+        };
+        match self {
+            SortKey::Name => by_name(),
+            SortKey::Size => a.size.cmp(&b.size).then_with(by_name),
+            SortKey::Modified => a.modified.cmp(&b.modified).then_with(by_name),
+            SortKey::Type => a.kind().cmp(b.kind()).then_with(by_name),
+        }
+    }
+}
+
+/// Reads the entries of `dir`, leaving out hidden ones (starting with `.`)
+/// and ones whose names are not UTF-8.
+pub(crate) fn entries(dir: &Path) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if name.starts_with('.') {
+            continue;
+        }
+        // Follows symlinks; dangling ones are left out.
+        let path = entry.path();
+        let Ok(metadata) = fs::metadata(&path) else {
+            continue;
+        };
+        entries.push(Entry {
+            path,
+            name,
+            is_dir: metadata.is_dir(),
+            size: if metadata.is_dir() { 0 } else { metadata.len() },
+            modified: metadata.modified().ok(),
+        });
+    }
+    Ok(entries)
+}
+
+/// Lists `entries` of the directory at the request path, as HTML or, with
+/// `?format=json`, as JSON.
+///
+/// `?sort=` picks `name` (the default), `size`, `modified` or `type`, and
+/// `?order=desc` reverses it. Directories always come first.
+pub(crate) fn response(request: &Request, mut entries: Vec<Entry>) -> Response {
+    let sort = request
+        .query_param("sort")
+        .and_then(|sort| SortKey::ALL.into_iter().find(|key| key.as_str() == sort))
+        .unwrap_or(SortKey::Name);
+    let descending = request.query_param("order").as_deref() == Some("desc");
+    entries.sort_by(|a, b| {
+        let order = sort.compare(a, b);
+        b.is_dir
+            .cmp(&a.is_dir)
+            .then(if descending { order.reverse() } else { order })
+    });
+
+    if request.query_param("format").as_deref() == Some("json") {
+        return json_listing(request.path(), &entries);
+    }
+    html_listing(request.path(), &entries, sort, descending)
+}
+
+fn json_listing(path: &str, entries: &[Entry]) -> Response {
+    let entries: Vec<_> = entries
+        .iter()
+        .map(|entry| {
+            json!({
+                "name": entry.name,
+                "type": entry.kind(),
+                "size": entry.size,
+                "modified": entry
+                    .modified
+                    .map(|time| DateTime::from_system_time(time).to_rfc3339()),
+            })
+        })
+        .collect();
+    let body = json!({ "path": path, "entries": entries });
+    Response::json(StatusCode::OK, body.to_string())
+}
+
+fn html_listing(path: &str, entries: &[Entry], sort: SortKey, descending: bool) -> Response {
+    let title = format!("Index of {}", escape_html(path));
+    let mut page = format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n  <head>\n    <meta charset=\"utf-8\">\n    \
+         <title>{title}</title>\n  </head>\n  <body>\n    <h1>{title}</h1>\n    <table>\n      <tr>\n"
+    );
+    for key in SortKey::ALL {
+        // The current column flips its order, the others start ascending.
+        let order = if key == sort && !descending {
+            "&amp;order=desc"
+        } else {
+            ""
+        };
+        page.push_str(&format!(
+            "        <th><a href=\"?sort={}{order}\">{}</a></th>\n",
+            key.as_str(),
+            key.title()
+        ));
+    }
+    page.push_str("      </tr>\n");
+    if path != "/" {
+        page.push_str(
+            "      <tr><td><a href=\"../\">../</a></td><td></td><td></td><td></td></tr>\n",
+        );
+    }
+    for entry in entries {
+        let slash = if entry.is_dir { "/" } else { "" };
+        let size = if entry.is_dir {
+            "-".to_owned()
+        } else {
+            format_size(entry.size)
+        };
+        let modified = entry.modified.map_or_else(String::new, |time| {
+            let time = DateTime::from_system_time(time);
+            format!(
+                "{}-{:02}-{:02} {:02}:{:02}",
+                time.year, time.month, time.day, time.hour, time.minute
+            )
+        });
+        page.push_str(&format!(
+            "      <tr><td><a href=\"{}{slash}\">{}{slash}</a></td><td>{size}</td>\
+             <td>{modified}</td><td>{}</td></tr>\n",
+            percent_encode(&entry.name),
+            escape_html(&entry.name),
+            entry.kind()
+        ));
+    }
+    page.push_str("    </table>\n  </body>\n</html>\n");
+    Response::html(StatusCode::OK, page)
+}
+
+/// `1536` becomes `1.5 KiB`.
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_sizes_and_escapes_names() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(5 * 1024 * 1024), "5.0 MiB");
+        assert_eq!(
+            escape_html("<a href='x'>&</a>"),
+            "&lt;a href=&#39;x&#39;&gt;&amp;&lt;/a&gt;"
+        );
+    }
+}
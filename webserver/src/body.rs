@@ -0,0 +1,160 @@
+//! Response bodies: in-memory bytes, files and streams.
+
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Read, Seek},
+};
+
+/// The body of a [`Response`](crate::Response).
+///
+/// Bytes and files have a known length and go out with `Content-Length`.
+/// Streams are sent with `Transfer-Encoding: chunked` to HTTP/1.1 clients,
+/// and until the connection closes to HTTP/1.0 ones, so they never have to
+/// fit in memory.
+#[derive(Default)]
+pub enum Body {
+    #[default]
+    Empty,
+    Bytes(Vec<u8>),
+    File {
+        file: File,
+        len: u64,
+    },
+    Stream(Box<dyn Read + Send>),
+}
+
+impl Body {
+    /// A body streaming the contents of `file` from its current position to
+    /// its end.
+    pub fn file(mut file: File) -> io::Result<Body> {
+        let len = file
+            .metadata()?
+            .len()
+            .saturating_sub(file.stream_position()?);
+        Ok(Body::File { file, len })
+    }
+
+    /// A body streaming whatever `reader` produces.
+    pub fn reader<R: Read + Send + 'static>(reader: R) -> Body {
+        Body::Stream(Box::new(reader))
+    }
+
+    /// A body streaming the pieces produced by `chunks`, e.g. rows of a
+    /// report generated on the fly.
+    pub fn chunks<I>(chunks: I) -> Body
+    where
+        I: Iterator<Item = Vec<u8>> + Send + 'static,
+    {
+        Body::reader(ChunkIter {
+            chunks,
+            current: io::Cursor::new(Vec::new()),
+        })
+    }
+
+    /// The length in bytes, or `None` for streams.
+    pub fn len(&self) -> Option<u64> {
+        match self {
+            Body::Empty => Some(0),
+            Body::Bytes(bytes) => Some(bytes.len() as u64),
+            Body::File { len, .. } => Some(*len),
+            Body::Stream(_) => None,
+        }
+    }
+
+    /// Returns `true` for a body known to be empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    /// The contents of an in-memory body, `None` for files and streams.
+    pub fn bytes(&self) -> Option<&[u8]> {
+        match self {
+            Body::Empty => Some(&[]),
+            Body::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /// Reads the whole body into memory.
+    pub fn into_bytes(self) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        match self {
+            Body::Empty => {}
+            Body::Bytes(contents) => bytes = contents,
+            Body::File { file, len } => {
+                file.take(len).read_to_end(&mut bytes)?;
+            }
+            Body::Stream(mut reader) => {
+                reader.read_to_end(&mut bytes)?;
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// Turns the body into a reader, whatever its kind.
+    pub fn into_reader(self) -> Box<dyn Read + Send> {
+        match self {
+            Body::Empty => Box::new(io::empty()),
+            Body::Bytes(bytes) => Box::new(io::Cursor::new(bytes)),
+            Body::File { file, len } => Box::new(file.take(len)),
+            Body::Stream(reader) => reader,
+        }
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Body::Empty => write!(f, "Empty"),
+            Body::Bytes(bytes) => write!(f, "Bytes({} bytes)", bytes.len()),
+            Body::File { len, .. } => write!(f, "File({len} bytes)"),
+            Body::Stream(_) => write!(f, "Stream"),
+        }
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Self {
+        Body::Bytes(bytes)
+    }
+}
+
+impl From<String> for Body {
+    fn from(text: String) -> Self {
+        Body::Bytes(text.into_bytes())
+    }
+}
+
+impl From<&str> for Body {
+    fn from(text: &str) -> Self {
+        Body::Bytes(text.as_bytes().to_vec())
+    }
+}
+
+impl From<&[u8]> for Body {
+    fn from(bytes: &[u8]) -> Self {
+        Body::Bytes(bytes.to_vec())
+    }
+}
+
+/// Adapts an iterator of byte chunks to `Read`.
+struct ChunkIter<I> {
+    chunks: I,
+    current: io::Cursor<Vec<u8>>,
+}
+
+impl<I: Iterator<Item = Vec<u8>>> Read for ChunkIter<I> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.current.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                return Ok(n);
+            }
+            match self.chunks.next() {
+                Some(chunk) => self.current = io::Cursor::new(chunk),
+                None => return Ok(0),
+            }
+        }
+    }
+}
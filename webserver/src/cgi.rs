@@ -0,0 +1,472 @@
+//! Running external programs as handlers through CGI (RFC 3875).
+//!
+//! The request is described to the program in meta-variables, and the
+//! program answers with a header block and a body. The same format is
+//! spoken to FastCGI responders by [`FastCgi`](crate::fastcgi::FastCgi).
+
+use std::{
+    env, fmt,
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    os::unix::process::CommandExt,
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    handler::Handler,
+    headers::Headers,
+    log::warn,
+    request::{self, Request},
+    response::Response,
+    server::strip_port,
+    status::StatusCode,
+};
+
+/// Longest piece of stderr logged as one line, in bytes.
+const MAX_LOG_LINE: u64 = 8 * 1024;
+
+/// Runs a CGI program for every request.
+///
+/// The program gets the RFC 3875 meta-variables (`REQUEST_METHOD`,
+/// `QUERY_STRING`, `PATH_INFO`, `HTTP_*`, ...), plus `PATH`, as its
+/// environment, runs in its own directory, and reads the request body from
+/// stdin. Its stdout is collected and sent as the response once it is done;
+/// each line it writes to stderr is logged. Programs taking longer than
+/// [`timeout`](Cgi::timeout) are killed and the client gets
+/// `504 Gateway Timeout`; broken output, or more than
+/// [`max_response_size`](Cgi::max_response_size), gives `502 Bad Gateway`.
+///
+/// # Examples
+///
+/// ```
+/// use webserver::{Cgi, Router};
+///
+/// let report = Cgi::new("/srv/cgi-bin/report.pl").script_name("/reports");
+/// let router = Router::new().any("/reports/*path", report);
+/// ```
+pub struct Cgi {
+    program: PathBuf,
+    script_name: Option<String>,
+    env: Vec<(String, String)>,
+    timeout: Duration,
+    max_response_size: usize,
+}
+
+impl Cgi {
+    /// Runs `program`.
+    pub fn new(program: impl Into<PathBuf>) -> Cgi {
+        Cgi {
+            program: program.into(),
+            script_name: None,
+            env: Vec::new(),
+            timeout: Duration::from_secs(30),
+            max_response_size: 10 * 1024 * 1024,
+        }
+    }
+
+    /// The path prefix the program is mounted at, e.g. `/reports`; the rest
+    /// of the path becomes `PATH_INFO`. Without one, `SCRIPT_NAME` is the
+    /// whole path.
+    pub fn script_name(mut self, prefix: impl Into<String>) -> Cgi {
+        self.script_name = Some(prefix.into().trim_end_matches('/').to_owned());
+        self
+    }
+
+    /// Adds the environment variable `name`, overriding a meta-variable of
+    /// the same name.
+    pub fn env(mut self, name: impl Into<String>, value: impl Into<String>) -> Cgi {
+        self.env.push((name.into(), value.into()));
+        self
+    }
+
+    /// How long the program may run. Defaults to 30 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> Cgi {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Most output, headers included, the program may write to stdout; it
+    /// is killed when it writes more. Defaults to 10 MiB.
+    pub fn max_response_size(mut self, bytes: usize) -> Cgi {
+        self.max_response_size = bytes;
+        self
+    }
+
+    fn run(&self, request: &Request) -> Result<Response, GatewayError> {
+        // Relative paths would otherwise be looked up in the new directory.
+        let program = self.program.canonicalize()?;
+        let mut command = Command::new(&program);
+        command
+            .env_clear()
+            .envs(env::var_os("PATH").map(|path| ("PATH", path)))
+            .envs(meta_variables(request, self.script_name.as_deref()))
+            .envs(self.env.iter().cloned())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            // Its own process group, so a timeout also kills the processes
+            // it started, which would otherwise keep stdout open.
+            .process_group(0);
+        if let Some(dir) = program.parent() {
+            command.current_dir(dir);
+        }
+        let mut child = command.spawn()?;
+        let started = Instant::now();
+
+        // Feeding stdin and draining stderr on their own threads keeps a
+        // program that writes before it reads from blocking on a full pipe.
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let body = request.body().to_vec();
+        thread::spawn(move || {
+            // Programs need not read the body.
+            let _ = stdin.write_all(&body);
+        });
+        let stderr = child.stderr.take().expect("stderr is piped");
+        let label = format!("[{}] {}", request.connection_id(), self.program.display());
+        thread::spawn(move || {
+            let mut stderr = BufReader::new(stderr);
+            let mut line = Vec::new();
+            // Longer lines are logged in pieces.
+            while let Ok(1..) = (&mut stderr)
+                .take(MAX_LOG_LINE)
+                .read_until(b'\n', &mut line)
+            {
+                warn!(
+                    "{label}: {}",
+                    String::from_utf8_lossy(&line).trim_end_matches(['\r', '\n'])
+                );
+                line.clear();
+            }
+        });
+
+        // The watchdog kills the program when it times out, or when told to
+        // because its output is too large.
+        let (done, finished) = mpsc::channel::<bool>();
+        let pid = child.id() as libc::pid_t;
+        let timeout = self.timeout;
+        let watchdog = thread::spawn(move || {
+            let outcome = finished.recv_timeout(timeout);
+            if matches!(outcome, Ok(true) | Err(RecvTimeoutError::Timeout)) {
+                // SAFETY: `kill` takes no pointers. The child is not reaped
+                // before the watchdog is joined, so its pid, which is also
+                // its process group id, cannot have been reused by now.
+                unsafe { libc::kill(-pid, libc::SIGKILL) };
+            }
+            outcome == Err(RecvTimeoutError::Timeout)
+        });
+        let mut output = Vec::new();
+        let read = child
+            .stdout
+            .take()
+            .expect("stdout is piped")
+            .take(self.max_response_size as u64 + 1)
+            .read_to_end(&mut output);
+        let too_large = output.len() > self.max_response_size;
+        let _ = done.send(too_large);
+        let timed_out = watchdog.join().unwrap_or(true);
+
+        // A program may close stdout and keep running.
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if timed_out || started.elapsed() >= self.timeout {
+                child.kill()?;
+                break child.wait()?;
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        if timed_out {
+            return Err(GatewayError::TimedOut);
+        }
+        if too_large {
+            return Err(GatewayError::TooLarge);
+        }
+        read?;
+        if !status.success() {
+            warn!(
+                "[{}] {} exited with {status}",
+                request.connection_id(),
+                self.program.display()
+            );
+        }
+        parse_response(&output)
+    }
+}
+
+impl Handler for Cgi {
+    fn handle(&self, request: &mut Request) -> Response {
+        match self.run(request) {
+            Ok(response) => response,
+            Err(e) => {
+                warn!(
+                    "[{}] CGI program {} failed: {e}",
+                    request.connection_id(),
+                    self.program.display()
+                );
+                e.response()
+            }
+        }
+    }
+}
+
+/// Why a CGI program or FastCGI responder did not produce a response.
+#[derive(Debug)]
+pub(crate) enum GatewayError {
+    Io(io::Error),
+    TimedOut,
+    Malformed(&'static str),
+    /// The response is larger than allowed.
+    TooLarge,
+    /// The responder is too busy to take the request.
+    Unavailable,
+}
+
+impl GatewayError {
+    pub(crate) fn response(&self) -> Response {
+        let status = match self {
+            GatewayError::TimedOut => StatusCode::GATEWAY_TIMEOUT,
+            GatewayError::Io(e)
+                if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock) =>
+            {
+                StatusCode::GATEWAY_TIMEOUT
+            }
+            GatewayError::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::BAD_GATEWAY,
+        };
+        Response::error(status)
+    }
+}
+
+impl fmt::Display for GatewayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GatewayError::Io(e) => write!(f, "{e}"),
+            GatewayError::TimedOut => write!(f, "timed out"),
+            GatewayError::Malformed(reason) => write!(f, "malformed response: {reason}"),
+            GatewayError::TooLarge => write!(f, "response too large"),
+            GatewayError::Unavailable => write!(f, "overloaded"),
+        }
+    }
+}
+
+impl From<io::Error> for GatewayError {
+    fn from(e: io::Error) -> Self {
+        GatewayError::Io(e)
+    }
+}
+
+/// The meta-variables of RFC 3875 section 4.1 describing `request`, with
+/// `script_name` split off the front of the path.
+pub(crate) fn meta_variables(
+    request: &Request,
+    script_name: Option<&str>,
+) -> Vec<(String, String)> {
+    let path = request.path();
+    let (script_name, path_info) = match script_name {
+        Some(prefix)
+            if path.starts_with(prefix)
+                && (path.len() == prefix.len() || path[prefix.len()..].starts_with('/')) =>
+        {
+            path.split_at(prefix.len())
+        }
+        _ => (path, ""),
+    };
+    let host = request.header("Host").unwrap_or("localhost");
+    let default_port = if request.is_secure() { "443" } else { "80" };
+    let server_name = strip_port(host);
+    let port = host[server_name.len()..]
+        .strip_prefix(':')
+        .unwrap_or(default_port);
+
+    let mut variables = vec![
+        ("GATEWAY_INTERFACE", "CGI/1.1".to_owned()),
+        (
+            "SERVER_SOFTWARE",
+            format!("webserver/{}", env!("CARGO_PKG_VERSION")),
+        ),
+        ("SERVER_PROTOCOL", request.version().to_string()),
+        ("SERVER_NAME", server_name.to_owned()),
+        ("SERVER_PORT", port.to_owned()),
+        ("REQUEST_METHOD", request.method().to_string()),
+        ("REQUEST_URI", request.target().to_owned()),
+        ("SCRIPT_NAME", script_name.to_owned()),
+        ("PATH_INFO", path_info.to_owned()),
+        (
+            "QUERY_STRING",
+            request.query().unwrap_or_default().to_owned(),
+        ),
+    ];
+    if let Some(peer) = request.peer_addr() {
+        variables.push(("REMOTE_ADDR", peer.ip().to_canonical().to_string()));
+        variables.push(("REMOTE_PORT", peer.port().to_string()));
+    }
+    if request.is_secure() {
+        variables.push(("HTTPS", "on".to_owned()));
+    }
+    if let Some((scheme, _)) = request
+        .header("Authorization")
+        .and_then(|value| value.trim().split_once(' '))
+    {
+        variables.push(("AUTH_TYPE", scheme.to_owned()));
+    }
+    if !request.body().is_empty() || request.content_length().is_some() {
+        variables.push(("CONTENT_LENGTH", request.body().len().to_string()));
+    }
+    if let Some(content_type) = request.header("Content-Type") {
+        variables.push(("CONTENT_TYPE", content_type.to_owned()));
+    }
+    let mut variables: Vec<(String, String)> = variables
+        .into_iter()
+        .map(|(name, value)| (name.to_owned(), value))
+        .collect();
+
+    for (name, _) in request.headers().iter() {
+        // Credentials stay with the server, `Proxy` would become the
+        // `HTTP_PROXY` many programs take as their proxy setting, and the
+        // content headers are already there.
+        if ["Authorization", "Proxy", "Content-Type", "Content-Length"]
+            .iter()
+            .any(|skip| name.eq_ignore_ascii_case(skip))
+        {
+            continue;
+        }
+        let variable = format!("HTTP_{}", name.to_ascii_uppercase().replace('-', "_"));
+        if variables.iter().any(|(existing, _)| *existing == variable) {
+            continue;
+        }
+        let value = request
+            .headers()
+            .get_all(name)
+            .collect::<Vec<_>>()
+            .join(", ");
+        variables.push((variable, value));
+    }
+    variables
+}
+
+/// Parses the output of a CGI program: header fields, a blank line and
+/// the body.
+///
+/// A `Status` field sets the status, otherwise it is `302 Found` with a
+/// `Location` and `200 OK` without. Local redirects are passed to the
+/// client like any other.
+pub(crate) fn parse_response(output: &[u8]) -> Result<Response, GatewayError> {
+    let end = [&b"\r\n\r\n"[..], b"\n\n"]
+        .iter()
+        .filter_map(|separator| {
+            find(output, separator).map(|start| (start, start + separator.len()))
+        })
+        .min()
+        .ok_or(GatewayError::Malformed("no end of headers"))?;
+    let head = std::str::from_utf8(&output[..end.0])
+        .map_err(|_| GatewayError::Malformed("head is not UTF-8"))?;
+
+    let mut status = None;
+    let mut headers = Headers::new();
+    for line in head.lines() {
+        let (name, value) = line
+            .split_once(':')
+            .filter(|(name, _)| !name.is_empty() && name.bytes().all(request::is_token_byte))
+            .ok_or(GatewayError::Malformed("malformed header line"))?;
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("Status") {
+            let code = value
+                .get(..3)
+                .and_then(|code| code.parse().ok())
+                .filter(|code| (100..=999).contains(code))
+                .ok_or(GatewayError::Malformed("malformed Status"))?;
+            status = Some(StatusCode::from_u16(code));
+        } else if !["Connection", "Transfer-Encoding", "Content-Length"]
+            .iter()
+            .any(|skip| name.eq_ignore_ascii_case(skip))
+        {
+            headers.append(name, value);
+        }
+    }
+
+    let status = match status {
+        Some(status) => status,
+        None if headers.contains("Location") => StatusCode::from_u16(302),
+        None if headers.contains("Content-Type") => StatusCode::OK,
+        None => {
+            return Err(GatewayError::Malformed(
+                "no Status, Location or Content-Type",
+            ));
+        }
+    };
+    let mut response = Response::new(status).with_body(output[end.1..].to_vec());
+    for (name, value) in headers.iter() {
+        response.headers_mut().append(name, value);
+    }
+    Ok(response)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::ParseLimits;
+    use std::io::Cursor;
+
+    #[test]
+    fn describes_requests_in_meta_variables() {
+        let raw = "POST /reports/2024/q3?format=csv HTTP/1.1\r\nHost: example.com:8080\r\n\
+                   Content-Type: text/plain\r\nX-Trace: a\r\nX-Trace: b\r\nProxy: evil\r\n\
+                   Authorization: Basic eDp5\r\nContent-Length: 3\r\n\r\nq=1";
+        let mut reader = Cursor::new(raw);
+        let mut request = Request::parse(&mut reader, &ParseLimits::default()).unwrap();
+        request.read_body(&mut reader, 16).unwrap();
+        let variables = meta_variables(&request, Some("/reports"));
+        let get = |name: &str| {
+            variables
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+        };
+
+        assert_eq!(get("SCRIPT_NAME"), Some("/reports"));
+        assert_eq!(get("PATH_INFO"), Some("/2024/q3"));
+        assert_eq!(get("QUERY_STRING"), Some("format=csv"));
+        assert_eq!(get("SERVER_NAME"), Some("example.com"));
+        assert_eq!(get("SERVER_PORT"), Some("8080"));
+        assert_eq!(get("CONTENT_LENGTH"), Some("3"));
+        assert_eq!(get("CONTENT_TYPE"), Some("text/plain"));
+        assert_eq!(get("AUTH_TYPE"), Some("Basic"));
+        assert_eq!(get("HTTP_X_TRACE"), Some("a, b"));
+        assert_eq!(get("HTTP_PROXY"), None);
+        assert_eq!(get("HTTP_AUTHORIZATION"), None);
+
+        let variables = meta_variables(&request, Some("/rep"));
+        assert!(variables.contains(&("PATH_INFO".to_owned(), String::new())));
+    }
+
+    #[test]
+    fn parses_program_output() {
+        let response =
+            parse_response(b"Status: 404 Gone\nContent-Type: text/plain\n\nnope").unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.body().bytes(), Some(&b"nope"[..]));
+
+        let response = parse_response(b"Location: /elsewhere\r\n\r\n").unwrap();
+        assert_eq!(response.status().as_u16(), 302);
+        assert_eq!(response.headers().get("Location"), Some("/elsewhere"));
+
+        for broken in [
+            &b"hello"[..],
+            b"X-Only: 1\n\n",
+            b"Status: abc\n\n",
+            b"no colon\n\n",
+        ] {
+            assert!(parse_response(broken).is_err());
+        }
+    }
+}
@@ -0,0 +1,205 @@
+//! `Transfer-Encoding: chunked` encoding and decoding.
+
+use std::io::{self, BufRead, ErrorKind, Read, Write};
+
+/// Longest chunk-size or trailer line we accept.
+const MAX_LINE: u64 = 8 * 1024;
+/// Most trailer fields we skip before giving up.
+const MAX_TRAILERS: usize = 100;
+
+/// Decodes a chunked message body read from `inner`.
+///
+/// Chunk extensions and trailer fields are read and discarded. Malformed
+/// framing is reported as [`ErrorKind::InvalidData`], a body cut short as
+/// [`ErrorKind::UnexpectedEof`].
+pub struct ChunkedReader<R> {
+    inner: R,
+    remaining: u64,
+    state: State,
+}
+
+enum State {
+    Size,
+    Data,
+    Done,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    /// Wraps a reader positioned at the first chunk-size line.
+    pub fn new(inner: R) -> ChunkedReader<R> {
+        ChunkedReader {
+            inner,
+            remaining: 0,
+            state: State::Size,
+        }
+    }
+
+    /// Returns the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn read_size(&mut self) -> io::Result<()> {
+        let line = read_line(&mut self.inner)?;
+        let size = line.split(';').next().unwrap_or_default().trim();
+        if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(invalid("malformed chunk size"));
+        }
+        let size = u64::from_str_radix(size, 16).map_err(|_| invalid("chunk size too large"))?;
+
+        if size > 0 {
+            self.remaining = size;
+            self.state = State::Data;
+            return Ok(());
+        }
+
+        for _ in 0..=MAX_TRAILERS {
+            if read_line(&mut self.inner)?.is_empty() {
+                self.state = State::Done;
+                return Ok(());
+            }
+        }
+        Err(invalid("too many trailer fields"))
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.state {
+                State::Done => return Ok(0),
+                State::Size => self.read_size()?,
+                State::Data if self.remaining == 0 => {
+                    if !read_line(&mut self.inner)?.is_empty() {
+                        return Err(invalid("missing CRLF after chunk data"));
+                    }
+                    self.state = State::Size;
+                }
+                State::Data => {
+                    if buf.is_empty() {
+                        return Ok(0);
+                    }
+                    let max = buf
+                        .len()
+                        .min(self.remaining.try_into().unwrap_or(usize::MAX));
+                    let n = self.inner.read(&mut buf[..max])?;
+                    if n == 0 {
+                        return Err(ErrorKind::UnexpectedEof.into());
+                    }
+                    self.remaining -= n as u64;
+                    return Ok(n);
+                }
+            }
+        }
+    }
+}
+
+/// Encodes everything written to it as chunks on `inner`.
+///
+/// Every `write` becomes one chunk, so flushing after each keeps latency low
+/// for event streams. Call [`finish`](ChunkedWriter::finish) to send the
+/// terminating zero-length chunk.
+pub struct ChunkedWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    /// Wraps the writer the chunks go to.
+    pub fn new(inner: W) -> ChunkedWriter<W> {
+        ChunkedWriter { inner }
+    }
+
+    /// Writes the last chunk and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.write_all(b"0\r\n\r\n")?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // An empty chunk would end the body early.
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        write!(self.inner, "{:x}\r\n", buf.len())?;
+        self.inner.write_all(buf)?;
+        self.inner.write_all(b"\r\n")?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<String> {
+    let mut line = Vec::new();
+    reader
+        .by_ref()
+        .take(MAX_LINE)
+        .read_until(b'\n', &mut line)?;
+
+    if line.last() != Some(&b'\n') {
+        return Err(if line.len() as u64 == MAX_LINE {
+            invalid("chunk line too long")
+        } else {
+            ErrorKind::UnexpectedEof.into()
+        });
+    }
+    let line = String::from_utf8(line).map_err(|_| invalid("chunk line is not valid UTF-8"))?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_owned())
+}
+
+fn invalid(message: &'static str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_chunks_and_skips_trailers() {
+        let raw = b"4;ext=1\r\nWiki\r\n6\r\npedia \r\nE\r\nin \r\n\r\nchunks.\r\n0\r\nExpires: never\r\n\r\nNEXT";
+        let mut reader = ChunkedReader::new(&raw[..]);
+
+        let mut body = String::new();
+        reader.read_to_string(&mut body).unwrap();
+
+        assert_eq!(body, "Wikipedia in \r\n\r\nchunks.");
+        assert_eq!(reader.into_inner(), b"NEXT");
+    }
+
+    #[test]
+    fn round_trips_through_the_writer() {
+        let mut writer = ChunkedWriter::new(Vec::new());
+        writer.write_all(b"hello ").unwrap();
+        writer.write_all(b"").unwrap();
+        writer.write_all(b"world").unwrap();
+        let encoded = writer.finish().unwrap();
+        assert_eq!(encoded, b"6\r\nhello \r\n5\r\nworld\r\n0\r\n\r\n");
+
+        let mut decoded = String::new();
+        ChunkedReader::new(&encoded[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "hello world");
+    }
+
+    #[test]
+    fn rejects_bad_framing() {
+        let mut body = Vec::new();
+
+        let err = ChunkedReader::new(&b"zz\r\n"[..])
+            .read_to_end(&mut body)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let err = ChunkedReader::new(&b"5\r\nab"[..])
+            .read_to_end(&mut body)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+}
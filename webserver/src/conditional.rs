@@ -0,0 +1,244 @@
+//! Validators, conditional requests (RFC 9110 section 13) and byte ranges
+//! (section 14).
+
+use std::{
+    fs::Metadata,
+    ops::Range,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{date, encoding::Encoding, request::Request};
+
+/// The `ETag` and `Last-Modified` of a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Validators {
+    pub(crate) etag: String,
+    /// Modification time cut to whole seconds, as HTTP dates have no more.
+    pub(crate) last_modified: Option<SystemTime>,
+}
+
+impl Validators {
+    /// Derives the validators from the size and modification time.
+    pub(crate) fn from_metadata(metadata: &Metadata) -> Validators {
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok());
+        let nanos = modified.map_or(0, |since_epoch| since_epoch.as_nanos());
+        Validators {
+            etag: format!("\"{nanos:x}-{:x}\"", metadata.len()),
+            last_modified: modified
+                .map(|since_epoch| UNIX_EPOCH + Duration::from_secs(since_epoch.as_secs())),
+        }
+    }
+
+    pub(crate) fn last_modified_header(&self) -> Option<String> {
+        self.last_modified.map(date::http_date)
+    }
+
+    /// Evaluates `If-None-Match`, or `If-Modified-Since` when there is no
+    /// such header, for a `GET` or `HEAD` request. `true` means the client's
+    /// copy is current and gets `304 Not Modified`.
+    pub(crate) fn not_modified(&self, request: &Request) -> bool {
+        if let Some(if_none_match) = request.header("If-None-Match") {
+            return if_none_match.trim() == "*"
+                || entity_tags(if_none_match).any(|tag| weak_eq(tag, &self.etag));
+        }
+        match (
+            self.last_modified,
+            request
+                .header("If-Modified-Since")
+                .and_then(date::parse_http_date),
+        ) {
+            (Some(modified), Some(since)) => modified <= since,
+            _ => false,
+        }
+    }
+
+    /// Evaluates `If-Range`: a range is only served if the client's partial
+    /// copy is of the current representation.
+    fn range_applies(&self, request: &Request) -> bool {
+        let Some(if_range) = request.header("If-Range").map(str::trim) else {
+            return true;
+        };
+        if if_range.starts_with('"') || if_range.starts_with("W/") {
+            return strong_eq(if_range, &self.etag);
+        }
+        // A date only validates when it is exactly our Last-Modified.
+        self.last_modified.is_some() && date::parse_http_date(if_range) == self.last_modified
+    }
+}
+
+/// What to send for a `Range` request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ByteRange {
+    /// No usable range; send the whole representation.
+    Full,
+    /// Send these bytes with `206 Partial Content`.
+    Partial(Range<u64>),
+    /// No range overlaps the representation; send `416`.
+    Unsatisfiable,
+}
+
+/// Decides which bytes of a `len` byte representation to send.
+///
+/// Only single ranges are served. Requests for several ranges get the full
+/// representation, which RFC 9110 allows, instead of `multipart/byteranges`.
+pub(crate) fn byte_range(request: &Request, validators: &Validators, len: u64) -> ByteRange {
+    let Some(range) = request.header("Range") else {
+        return ByteRange::Full;
+    };
+    if !validators.range_applies(request) {
+        return ByteRange::Full;
+    }
+    let Some(spec) = range.trim().strip_prefix("bytes=") else {
+        return ByteRange::Full;
+    };
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+    let Some((first, last)) = spec.trim().split_once('-') else {
+        return ByteRange::Full;
+    };
+
+    let range = match (first.trim(), last.trim()) {
+        // `bytes=-500`: the last 500 bytes.
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(0) => return ByteRange::Unsatisfiable,
+            Ok(suffix) => len.saturating_sub(suffix)..len,
+            Err(_) => return ByteRange::Full,
+        },
+        // `bytes=100-` or `bytes=100-199`
+        (first, last) => {
+            let Ok(first) = first.parse::<u64>() else {
+                return ByteRange::Full;
+            };
+            let end = match last {
+                "" => len,
+                last => match last.parse::<u64>() {
+                    Ok(last) if last >= first => last.saturating_add(1).min(len),
+                    _ => return ByteRange::Full,
+                },
+            };
+            first..end
+        }
+    };
+
+    if range.start >= len {
+        ByteRange::Unsatisfiable
+    } else {
+        ByteRange::Partial(range)
+    }
+}
+
+/// Splits a list such as `"a", W/"b"` into its entity tags.
+fn entity_tags(list: &str) -> impl Iterator<Item = &str> {
+    list.split(',').map(str::trim).filter(|tag| !tag.is_empty())
+}
+
+/// Weak comparison: the `W/` prefix is ignored. A tag the compression
+/// middleware suffixed with its coding still matches the file it came from.
+fn weak_eq(tag: &str, etag: &str) -> bool {
+    let tag = tag.strip_prefix("W/").unwrap_or(tag);
+    let etag = etag.strip_prefix("W/").unwrap_or(etag);
+    tag == etag
+        || Encoding::ALL.iter().any(|encoding| {
+            tag.strip_suffix(&format!("-{}\"", encoding.as_str()))
+                .is_some_and(|stem| etag.strip_suffix('"') == Some(stem))
+        })
+}
+
+/// Strong comparison: both tags must be strong and identical.
+fn strong_eq(tag: &str, etag: &str) -> bool {
+    !tag.starts_with("W/") && !etag.starts_with("W/") && tag == etag
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::ParseLimits;
+    use std::io::Cursor;
+
+    fn request(headers: &str) -> Request {
+        let raw = format!("GET /file HTTP/1.1\r\nHost: localhost\r\n{headers}\r\n");
+        Request::parse(&mut Cursor::new(raw.as_bytes()), &ParseLimits::default()).unwrap()
+    }
+
+    fn validators() -> Validators {
+        Validators {
+            etag: "\"abc-10\"".to_owned(),
+            last_modified: Some(UNIX_EPOCH + Duration::from_secs(784_111_777)),
+        }
+    }
+
+    #[test]
+    fn evaluates_if_none_match_before_if_modified_since() {
+        let validators = validators();
+        let not_modified = |headers| validators.not_modified(&request(headers));
+
+        assert!(not_modified("If-None-Match: \"x\", W/\"abc-10\"\r\n"));
+        assert!(not_modified("If-None-Match: \"abc-10-gzip\"\r\n"));
+        assert!(not_modified("If-None-Match: *\r\n"));
+        assert!(!not_modified(
+            "If-None-Match: \"other\"\r\nIf-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\n"
+        ));
+        assert!(not_modified(
+            "If-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\n"
+        ));
+        assert!(!not_modified(
+            "If-Modified-Since: Sun, 06 Nov 1994 08:49:36 GMT\r\n"
+        ));
+        assert!(!not_modified("If-Modified-Since: garbage\r\n"));
+        assert!(!not_modified(""));
+    }
+
+    #[test]
+    fn parses_single_byte_ranges() {
+        let validators = validators();
+        let range = |headers| byte_range(&request(headers), &validators, 1000);
+
+        assert_eq!(range(""), ByteRange::Full);
+        assert_eq!(range("Range: bytes=0-99\r\n"), ByteRange::Partial(0..100));
+        assert_eq!(
+            range("Range: bytes=900-\r\n"),
+            ByteRange::Partial(900..1000)
+        );
+        assert_eq!(
+            range("Range: bytes=-100\r\n"),
+            ByteRange::Partial(900..1000)
+        );
+        assert_eq!(range("Range: bytes=-5000\r\n"), ByteRange::Partial(0..1000));
+        assert_eq!(
+            range("Range: bytes=990-2000\r\n"),
+            ByteRange::Partial(990..1000)
+        );
+        assert_eq!(range("Range: bytes=1000-\r\n"), ByteRange::Unsatisfiable);
+        assert_eq!(range("Range: bytes=-0\r\n"), ByteRange::Unsatisfiable);
+        assert_eq!(range("Range: bytes=5-1\r\n"), ByteRange::Full);
+        assert_eq!(range("Range: bytes=0-1,5-6\r\n"), ByteRange::Full);
+        assert_eq!(range("Range: lines=1-2\r\n"), ByteRange::Full);
+    }
+
+    #[test]
+    fn if_range_needs_a_current_validator() {
+        let validators = validators();
+        let range = |headers| byte_range(&request(headers), &validators, 1000);
+
+        assert_eq!(
+            range("Range: bytes=0-9\r\nIf-Range: \"abc-10\"\r\n"),
+            ByteRange::Partial(0..10)
+        );
+        assert_eq!(
+            range("Range: bytes=0-9\r\nIf-Range: W/\"abc-10\"\r\n"),
+            ByteRange::Full
+        );
+        assert_eq!(
+            range("Range: bytes=0-9\r\nIf-Range: Sun, 06 Nov 1994 08:49:37 GMT\r\n"),
+            ByteRange::Partial(0..10)
+        );
+        assert_eq!(
+            range("Range: bytes=0-9\r\nIf-Range: Mon, 07 Nov 1994 08:49:37 GMT\r\n"),
+            ByteRange::Full
+        );
+    }
+}
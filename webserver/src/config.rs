@@ -0,0 +1,1326 @@
+//! Server settings loaded from a TOML file, environment variables and
+//! command-line flags.
+//!
+//! Every setting can come from three places. Later sources win:
+//!
+//! 1. the TOML file given by `--config` or `WEBSERVER_CONFIG`, or
+//!    `webserver.toml` in the working directory if it exists,
+//! 2. environment variables named `WEBSERVER_<KEY>`, e.g. `WEBSERVER_WORKERS=8`,
+//! 3. command-line flags named `--<key>` with dashes, e.g. `--workers 8`.
+//!
+//! Settings left out everywhere keep the defaults listed in [`USAGE`].
+//! Per-name TLS certificates (`[[tls_sni]]`), `Cache-Control` rules
+//! (`[[cache_control]]`), proxied prefixes (`[[proxy]]`), CGI programs
+//! (`[[cgi]]`), FastCGI responders (`[[fastcgi]]`) and virtual hosts
+//! (`[[virtual_host]]`) can only be set in the file.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    env, fmt, fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    access_log::{AccessLog, LogFormat, LogTarget, Rotation},
+    cgi::Cgi,
+    connection::ConnectionConfig,
+    fastcgi::FastCgi,
+    handler::Handler,
+    log::Level,
+    middleware::{
+        BasicAuth, Builtin, Chain, Compression, Cors, ErrorPages, RateLimit, RequestId, Timing,
+    },
+    proxy::Proxy,
+    request::{Method, ParseLimits},
+    router::Router,
+    static_files::StaticFiles,
+    virtual_hosts::VirtualHosts,
+};
+
+/// Help text for the command line.
+pub const USAGE: &str = "\
+Usage: webserver [OPTIONS]
+
+Options (each also settable as WEBSERVER_<KEY> or in the TOML file as <key>):
+  --config <PATH>                       TOML file to read   [default: webserver.toml]
+  --bind <ADDR>                         listen address      [default: [::]:7878]
+  --workers <N>                         worker threads      [default: 5]
+  --document-root <DIR>                 static files root   [default: assets]
+  --precompressed <BOOL>                serve .br/.gz files [default: false]
+  --error-pages <DIR>                   <status>.html pages for errors
+  --autoindex <LIST>                    directories to list, comma-separated
+                                        patterns like /pub,/pub/**
+  --proxy-connect-timeout-secs <SECS>   upstream connect    [default: 5]
+  --proxy-timeout-secs <SECS>           upstream read/write [default: 30]
+  --cgi-timeout-secs <SECS>             CGI/FastCGI answer  [default: 30]
+  --cgi-max-response-size <BYTES>       CGI/FastCGI output  [default: 10485760]
+  --read-timeout-ms <MS>                deadline poll slice [default: 10]
+  --header-timeout-secs <SECS>          whole request head  [default: 10]
+  --body-timeout-secs <SECS>            whole request body  [default: 60]
+  --write-timeout-secs <SECS>           each response write [default: 30]
+  --max-connections <N>                 open at once, 0 for no limit
+                                                            [default: 1024]
+  --keep-alive-timeout-secs <SECS>      idle connection     [default: 5]
+  --max-requests-per-connection <N>     keep-alive limit    [default: 100]
+  --max-headers <N>                     header fields       [default: 100]
+  --max-header-line <BYTES>             one header line     [default: 8192]
+  --max-uri-length <BYTES>              request-target      [default: 8192]
+  --max-body-size <BYTES>               request body        [default: 10485760]
+  --shutdown-timeout-secs <SECS>        drain deadline      [default: 30]
+  --access-log <stdout|off|PATH>        access log target   [default: stdout]
+  --access-log-format <FORMAT>          record layout       [default: combined]
+  --access-log-max-size <BYTES>         rotate log file at  [default: 10485760]
+  --access-log-keep <N>                 rotated files kept  [default: 5]
+  --tls-bind <ADDR>                     HTTPS listen address (needs the tls feature)
+  --tls-cert <PATH>                     default certificate chain, PEM
+  --tls-key <PATH>                      default private key, PEM
+  --redirect-to-https <BOOL>            redirect plain HTTP [default: false]
+  --middleware <LIST>                   comma-separated, outermost first:
+                                        request_id, timing, cors, basic_auth,
+                                        compression, rate_limit
+  --cors-allow-origins <LIST>           comma-separated origins, or *
+  --basic-auth-realm <REALM>            realm for basic_auth [default: webserver]
+  --basic-auth-file <PATH>              name:password lines for basic_auth
+  --rate-limit-per-sec <N>              requests per client IP [default: 10]
+  --rate-limit-burst <N>                bucket size for rate_limit [default: 20]
+  --metrics <BOOL>                      Prometheus endpoint [default: false]
+  --metrics-path <PATH>                 where it is served  [default: /metrics]
+  --health-checks <BOOL>                /healthz and /readyz [default: false]
+  --admin-bind <ADDR>                   admin listen address; loopback only
+                                        without --admin-auth-file
+  --admin-auth-file <PATH>              name:password lines for the admin
+                                        listener
+  --log-level <LEVEL>                   off, error, warn, info or debug
+                                                            [default: info]
+  -h, --help                            print this help
+";
+
+const ENV_PREFIX: &str = "WEBSERVER_";
+const DEFAULT_CONFIG_FILE: &str = "webserver.toml";
+
+/// Validated settings for the whole server.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Address the listener binds to.
+    pub bind: SocketAddr,
+    /// Number of threads in the pool.
+    pub workers: usize,
+    /// Directory static files are served from.
+    pub document_root: PathBuf,
+    /// Serve `.br`/`.gz` siblings of static files to clients accepting them.
+    pub precompressed: bool,
+    /// Directories listed when they have no index file; see
+    /// [`StaticFiles::autoindex`].
+    pub autoindex: Vec<String>,
+    /// `Cache-Control` headers for static files, first match wins.
+    pub cache_control: Vec<CacheRule>,
+    /// Path prefixes forwarded to upstream servers.
+    pub proxy: Vec<ProxyRoute>,
+    /// Path prefixes answered by CGI programs.
+    pub cgi: Vec<CgiRoute>,
+    /// Path prefixes answered by FastCGI responders.
+    pub fastcgi: Vec<FastCgiRoute>,
+    /// Directory of `<status>.html` pages replacing error bodies.
+    pub error_pages: Option<PathBuf>,
+    /// Sites served for particular host names; other names get the
+    /// settings above.
+    pub virtual_host: Vec<VirtualHost>,
+    /// How long connecting to an upstream may take.
+    pub proxy_connect_timeout: Duration,
+    /// How long an upstream may take for each read or write.
+    pub proxy_timeout: Duration,
+    /// How long a CGI program may run, or a FastCGI responder take to
+    /// answer.
+    pub cgi_timeout: Duration,
+    /// Most output a CGI program or FastCGI responder may send, in bytes.
+    pub cgi_max_response_size: usize,
+    /// How often a read waiting for a request checks its deadlines.
+    pub read_timeout: Duration,
+    /// Time allowed for a whole request head.
+    pub header_timeout: Duration,
+    /// Time allowed for a whole request body.
+    pub body_timeout: Duration,
+    /// Time allowed for each write to a client.
+    pub write_timeout: Duration,
+    /// Connections open at once; more are refused with `503`. 0 means no limit.
+    pub max_connections: usize,
+    /// How long idle keep-alive connections are kept.
+    pub keep_alive_timeout: Duration,
+    /// Requests served on one connection before it is closed.
+    pub max_requests_per_connection: usize,
+    /// Maximum number of request header fields.
+    pub max_headers: usize,
+    /// Longest accepted header line, in bytes.
+    pub max_header_line: usize,
+    /// Longest accepted request-target, in bytes.
+    pub max_uri_length: usize,
+    /// Largest accepted request body, in bytes.
+    pub max_body_size: usize,
+    /// How long a graceful shutdown waits for requests in flight.
+    pub shutdown_timeout: Duration,
+    /// Where the access log goes.
+    pub access_log: LogTarget,
+    /// Layout of access log records.
+    pub access_log_format: LogFormat,
+    /// When a file access log is rotated.
+    pub access_log_rotation: Rotation,
+    /// Address of the HTTPS listener; `None` serves plain HTTP only.
+    pub tls_bind: Option<SocketAddr>,
+    /// Certificate chain used when no SNI entry matches.
+    pub tls_cert: Option<PathBuf>,
+    /// Private key for [`tls_cert`](ServerConfig::tls_cert).
+    pub tls_key: Option<PathBuf>,
+    /// Certificates chosen by the server name the client asks for.
+    pub tls_sni: Vec<SniCertificate>,
+    /// Answer requests on [`bind`](ServerConfig::bind) with a redirect to
+    /// the HTTPS listener.
+    pub redirect_to_https: bool,
+    /// Built-in middleware to wrap the handler in, outermost first.
+    pub middleware: Vec<Builtin>,
+    /// Origins the `cors` middleware allows; `*` allows any.
+    pub cors_allow_origins: Vec<String>,
+    /// Realm the `basic_auth` middleware reports.
+    pub basic_auth_realm: String,
+    /// File of `name:password` lines for the `basic_auth` middleware.
+    pub basic_auth_file: Option<PathBuf>,
+    /// Requests per second each client IP may make under `rate_limit`.
+    pub rate_limit_per_sec: f64,
+    /// Requests a client IP may make in a burst under `rate_limit`.
+    pub rate_limit_burst: u32,
+    /// Collect request, connection and worker pool metrics.
+    pub metrics: bool,
+    /// Request path the metrics are served on.
+    pub metrics_path: String,
+    /// Answer `/healthz` and `/readyz` on [`bind`](ServerConfig::bind).
+    pub health_checks: bool,
+    /// Address of the admin listener; `None` disables it.
+    pub admin_bind: Option<SocketAddr>,
+    /// File of `name:password` lines allowed on the admin listener. Without
+    /// one, the listener must be bound to a loopback address.
+    pub admin_auth_file: Option<PathBuf>,
+    /// Initial level of the server's diagnostics.
+    pub log_level: Level,
+}
+
+/// A `Cache-Control` value for static files matching a pattern; see
+/// [`StaticFiles::cache_control`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CacheRule {
+    /// E.g. `*.html` or `/assets/**`.
+    pub pattern: String,
+    /// E.g. `max-age=3600`.
+    pub value: String,
+}
+
+/// Requests under a path prefix forwarded to upstream servers; see
+/// [`Proxy`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProxyRoute {
+    /// E.g. `/api`, matching `/api` and every path below it.
+    pub prefix: String,
+    /// E.g. `127.0.0.1:9000`, taking turns.
+    pub upstreams: Vec<SocketAddr>,
+    /// Forward `/api/users` as `/users`.
+    #[serde(default)]
+    pub strip_prefix: bool,
+}
+
+/// Requests under a path prefix answered by a CGI program; see [`Cgi`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CgiRoute {
+    /// E.g. `/reports`; the path below it becomes `PATH_INFO`.
+    pub prefix: String,
+    /// The executable to run.
+    pub program: PathBuf,
+    /// Extra environment variables.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+}
+
+/// Requests under a path prefix answered by a FastCGI responder; see
+/// [`FastCgi`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FastCgiRoute {
+    /// E.g. `/app`; the path below it becomes `PATH_INFO`.
+    pub prefix: String,
+    /// The Unix socket the responder listens on.
+    pub socket: PathBuf,
+    /// Extra parameters, e.g. `SCRIPT_FILENAME`.
+    #[serde(default)]
+    pub params: BTreeMap<String, String>,
+}
+
+/// A site with its own files, proxied prefixes and error pages, served for
+/// requests to some host names; see [`VirtualHosts`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct VirtualHost {
+    /// E.g. `example.com`, or `*.example.com` for every subdomain.
+    pub names: Vec<String>,
+    /// Directory static files are served from.
+    pub document_root: PathBuf,
+    /// Directories listed when they have no index file.
+    #[serde(default)]
+    pub autoindex: Vec<String>,
+    /// Directory of `<status>.html` pages replacing error bodies.
+    #[serde(default)]
+    pub error_pages: Option<PathBuf>,
+    /// Path prefixes forwarded to upstream servers.
+    #[serde(default)]
+    pub proxy: Vec<ProxyRoute>,
+    /// Path prefixes answered by CGI programs.
+    #[serde(default)]
+    pub cgi: Vec<CgiRoute>,
+    /// Path prefixes answered by FastCGI responders.
+    #[serde(default)]
+    pub fastcgi: Vec<FastCgiRoute>,
+}
+
+/// A certificate served to clients asking for `server_name`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SniCertificate {
+    /// A host name, or `*.example.com` for any direct subdomain.
+    pub server_name: String,
+    /// Certificate chain, PEM.
+    pub cert: PathBuf,
+    /// Private key, PEM.
+    pub key: PathBuf,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind: SocketAddr::from(([0u16; 8], 7878)),
+            workers: 5,
+            document_root: PathBuf::from("assets"),
+            precompressed: false,
+            autoindex: Vec::new(),
+            cache_control: Vec::new(),
+            proxy: Vec::new(),
+            cgi: Vec::new(),
+            fastcgi: Vec::new(),
+            error_pages: None,
+            virtual_host: Vec::new(),
+            proxy_connect_timeout: Duration::from_secs(5),
+            proxy_timeout: Duration::from_secs(30),
+            cgi_timeout: Duration::from_secs(30),
+            cgi_max_response_size: 10 * 1024 * 1024,
+            read_timeout: Duration::from_millis(10),
+            header_timeout: Duration::from_secs(10),
+            body_timeout: Duration::from_secs(60),
+            write_timeout: Duration::from_secs(30),
+            max_connections: 1024,
+            keep_alive_timeout: Duration::from_secs(5),
+            max_requests_per_connection: 100,
+            max_headers: 100,
+            max_header_line: 8 * 1024,
+            max_uri_length: 8 * 1024,
+            max_body_size: 10 * 1024 * 1024,
+            shutdown_timeout: Duration::from_secs(30),
+            access_log: LogTarget::Stdout,
+            access_log_format: LogFormat::Combined,
+            access_log_rotation: Rotation::default(),
+            tls_bind: None,
+            tls_cert: None,
+            tls_key: None,
+            tls_sni: Vec::new(),
+            redirect_to_https: false,
+            middleware: Vec::new(),
+            cors_allow_origins: Vec::new(),
+            basic_auth_realm: "webserver".to_owned(),
+            basic_auth_file: None,
+            rate_limit_per_sec: 10.0,
+            rate_limit_burst: 20,
+            metrics: false,
+            metrics_path: "/metrics".to_owned(),
+            health_checks: false,
+            admin_bind: None,
+            admin_auth_file: None,
+            log_level: Level::Info,
+        }
+    }
+}
+
+/// Why the configuration could not be loaded.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// `--help` was given; print [`USAGE`] and exit.
+    HelpRequested,
+    /// The config file could not be read.
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    /// The config file is not valid TOML or has unknown keys.
+    Parse { path: PathBuf, message: String },
+    /// A flag that does not exist.
+    UnknownFlag(String),
+    /// A flag given without its value.
+    MissingValue(String),
+    /// A value that cannot be parsed or is out of range.
+    InvalidValue {
+        key: String,
+        source: &'static str,
+        message: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::HelpRequested => write!(f, "help requested"),
+            ConfigError::Io { path, error } => {
+                write!(f, "cannot read config file {}: {error}", path.display())
+            }
+            ConfigError::Parse { path, message } => {
+                write!(f, "invalid config file {}: {message}", path.display())
+            }
+            ConfigError::UnknownFlag(flag) => write!(f, "unknown option {flag}, see --help"),
+            ConfigError::MissingValue(flag) => write!(f, "option {flag} needs a value"),
+            ConfigError::InvalidValue {
+                key,
+                source,
+                message,
+            } => write!(f, "invalid value for {key} ({source}): {message}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Settings read from one source; `None` means "not given here".
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PartialConfig {
+    bind: Option<SocketAddr>,
+    workers: Option<usize>,
+    document_root: Option<PathBuf>,
+    precompressed: Option<bool>,
+    autoindex: Option<Vec<String>>,
+    cache_control: Option<Vec<CacheRule>>,
+    proxy: Option<Vec<ProxyRoute>>,
+    cgi: Option<Vec<CgiRoute>>,
+    fastcgi: Option<Vec<FastCgiRoute>>,
+    error_pages: Option<PathBuf>,
+    virtual_host: Option<Vec<VirtualHost>>,
+    proxy_connect_timeout_secs: Option<u64>,
+    proxy_timeout_secs: Option<u64>,
+    cgi_timeout_secs: Option<u64>,
+    cgi_max_response_size: Option<usize>,
+    read_timeout_ms: Option<u64>,
+    header_timeout_secs: Option<u64>,
+    body_timeout_secs: Option<u64>,
+    write_timeout_secs: Option<u64>,
+    max_connections: Option<usize>,
+    keep_alive_timeout_secs: Option<u64>,
+    max_requests_per_connection: Option<usize>,
+    max_headers: Option<usize>,
+    max_header_line: Option<usize>,
+    max_uri_length: Option<usize>,
+    max_body_size: Option<usize>,
+    shutdown_timeout_secs: Option<u64>,
+    access_log: Option<String>,
+    access_log_format: Option<LogFormat>,
+    access_log_max_size: Option<u64>,
+    access_log_keep: Option<usize>,
+    tls_bind: Option<SocketAddr>,
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+    tls_sni: Option<Vec<SniCertificate>>,
+    redirect_to_https: Option<bool>,
+    middleware: Option<Vec<Builtin>>,
+    cors_allow_origins: Option<Vec<String>>,
+    basic_auth_realm: Option<String>,
+    basic_auth_file: Option<PathBuf>,
+    rate_limit_per_sec: Option<f64>,
+    rate_limit_burst: Option<u32>,
+    metrics: Option<bool>,
+    metrics_path: Option<String>,
+    health_checks: Option<bool>,
+    admin_bind: Option<SocketAddr>,
+    admin_auth_file: Option<PathBuf>,
+    log_level: Option<Level>,
+}
+
+impl PartialConfig {
+    /// Sets `key` from its textual form. Returns `false` for unknown keys.
+    fn set(&mut self, key: &str, value: &str, source: &'static str) -> Result<bool, ConfigError> {
+        match key {
+            "bind" => self.bind = Some(parse(key, value, source)?),
+            "workers" => self.workers = Some(parse(key, value, source)?),
+            "document_root" => self.document_root = Some(PathBuf::from(value)),
+            "precompressed" => self.precompressed = Some(parse(key, value, source)?),
+            "autoindex" => self.autoindex = Some(parse_list(key, value, source)?),
+            "error_pages" => self.error_pages = Some(PathBuf::from(value)),
+            "proxy_connect_timeout_secs" => {
+                self.proxy_connect_timeout_secs = Some(parse(key, value, source)?)
+            }
+            "proxy_timeout_secs" => self.proxy_timeout_secs = Some(parse(key, value, source)?),
+            "cgi_timeout_secs" => self.cgi_timeout_secs = Some(parse(key, value, source)?),
+            "cgi_max_response_size" => {
+                self.cgi_max_response_size = Some(parse(key, value, source)?)
+            }
+            "read_timeout_ms" => self.read_timeout_ms = Some(parse(key, value, source)?),
+            "header_timeout_secs" => self.header_timeout_secs = Some(parse(key, value, source)?),
+            "body_timeout_secs" => self.body_timeout_secs = Some(parse(key, value, source)?),
+            "write_timeout_secs" => self.write_timeout_secs = Some(parse(key, value, source)?),
+            "max_connections" => self.max_connections = Some(parse(key, value, source)?),
+            "keep_alive_timeout_secs" => {
+                self.keep_alive_timeout_secs = Some(parse(key, value, source)?)
+            }
+            "max_requests_per_connection" => {
+                self.max_requests_per_connection = Some(parse(key, value, source)?)
+            }
+            "max_headers" => self.max_headers = Some(parse(key, value, source)?),
+            "max_header_line" => self.max_header_line = Some(parse(key, value, source)?),
+            "max_uri_length" => self.max_uri_length = Some(parse(key, value, source)?),
+            "max_body_size" => self.max_body_size = Some(parse(key, value, source)?),
+            "shutdown_timeout_secs" => {
+                self.shutdown_timeout_secs = Some(parse(key, value, source)?)
+            }
+            "access_log" => self.access_log = Some(value.to_owned()),
+            "access_log_format" => self.access_log_format = Some(parse(key, value, source)?),
+            "access_log_max_size" => self.access_log_max_size = Some(parse(key, value, source)?),
+            "access_log_keep" => self.access_log_keep = Some(parse(key, value, source)?),
+            "tls_bind" => self.tls_bind = Some(parse(key, value, source)?),
+            "tls_cert" => self.tls_cert = Some(PathBuf::from(value)),
+            "tls_key" => self.tls_key = Some(PathBuf::from(value)),
+            "redirect_to_https" => self.redirect_to_https = Some(parse(key, value, source)?),
+            "middleware" => self.middleware = Some(parse_list(key, value, source)?),
+            "cors_allow_origins" => self.cors_allow_origins = Some(parse_list(key, value, source)?),
+            "basic_auth_realm" => self.basic_auth_realm = Some(value.to_owned()),
+            "basic_auth_file" => self.basic_auth_file = Some(PathBuf::from(value)),
+            "rate_limit_per_sec" => self.rate_limit_per_sec = Some(parse(key, value, source)?),
+            "rate_limit_burst" => self.rate_limit_burst = Some(parse(key, value, source)?),
+            "metrics" => self.metrics = Some(parse(key, value, source)?),
+            "metrics_path" => self.metrics_path = Some(value.to_owned()),
+            "health_checks" => self.health_checks = Some(parse(key, value, source)?),
+            "admin_bind" => self.admin_bind = Some(parse(key, value, source)?),
+            "admin_auth_file" => self.admin_auth_file = Some(PathBuf::from(value)),
+            "log_level" => self.log_level = Some(parse(key, value, source)?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Applies these settings on top of `config`.
+    fn apply_to(self, config: &mut ServerConfig) {
+        if let Some(bind) = self.bind {
+            config.bind = bind;
+        }
+        if let Some(workers) = self.workers {
+            config.workers = workers;
+        }
+        if let Some(document_root) = self.document_root {
+            config.document_root = document_root;
+        }
+        if let Some(precompressed) = self.precompressed {
+            config.precompressed = precompressed;
+        }
+        if let Some(autoindex) = self.autoindex {
+            config.autoindex = autoindex;
+        }
+        if let Some(cache_control) = self.cache_control {
+            config.cache_control = cache_control;
+        }
+        if let Some(proxy) = self.proxy {
+            config.proxy = proxy;
+        }
+        if let Some(cgi) = self.cgi {
+            config.cgi = cgi;
+        }
+        if let Some(fastcgi) = self.fastcgi {
+            config.fastcgi = fastcgi;
+        }
+        if let Some(error_pages) = self.error_pages {
+            config.error_pages = Some(error_pages);
+        }
+        if let Some(virtual_host) = self.virtual_host {
+            config.virtual_host = virtual_host;
+        }
+        if let Some(secs) = self.proxy_connect_timeout_secs {
+            config.proxy_connect_timeout = Duration::from_secs(secs);
+        }
+        if let Some(secs) = self.proxy_timeout_secs {
+            config.proxy_timeout = Duration::from_secs(secs);
+        }
+        if let Some(secs) = self.cgi_timeout_secs {
+            config.cgi_timeout = Duration::from_secs(secs);
+        }
+        if let Some(max) = self.cgi_max_response_size {
+            config.cgi_max_response_size = max;
+        }
+        if let Some(ms) = self.read_timeout_ms {
+            config.read_timeout = Duration::from_millis(ms);
+        }
+        if let Some(secs) = self.header_timeout_secs {
+            config.header_timeout = Duration::from_secs(secs);
+        }
+        if let Some(secs) = self.body_timeout_secs {
+            config.body_timeout = Duration::from_secs(secs);
+        }
+        if let Some(secs) = self.write_timeout_secs {
+            config.write_timeout = Duration::from_secs(secs);
+        }
+        if let Some(max_connections) = self.max_connections {
+            config.max_connections = max_connections;
+        }
+        if let Some(secs) = self.keep_alive_timeout_secs {
+            config.keep_alive_timeout = Duration::from_secs(secs);
+        }
+        if let Some(max) = self.max_requests_per_connection {
+            config.max_requests_per_connection = max;
+        }
+        if let Some(max) = self.max_headers {
+            config.max_headers = max;
+        }
+        if let Some(max) = self.max_header_line {
+            config.max_header_line = max;
+        }
+        if let Some(max) = self.max_uri_length {
+            config.max_uri_length = max;
+        }
+        if let Some(max) = self.max_body_size {
+            config.max_body_size = max;
+        }
+        if let Some(secs) = self.shutdown_timeout_secs {
+            config.shutdown_timeout = Duration::from_secs(secs);
+        }
+        if let Some(target) = self.access_log {
+            config.access_log = LogTarget::from(target.as_str());
+        }
+        if let Some(format) = self.access_log_format {
+            config.access_log_format = format;
+        }
+        if let Some(max_size) = self.access_log_max_size {
+            config.access_log_rotation.max_size = max_size;
+        }
+        if let Some(keep) = self.access_log_keep {
+            config.access_log_rotation.keep = keep;
+        }
+        if let Some(tls_bind) = self.tls_bind {
+            config.tls_bind = Some(tls_bind);
+        }
+        if let Some(tls_cert) = self.tls_cert {
+            config.tls_cert = Some(tls_cert);
+        }
+        if let Some(tls_key) = self.tls_key {
+            config.tls_key = Some(tls_key);
+        }
+        if let Some(tls_sni) = self.tls_sni {
+            config.tls_sni = tls_sni;
+        }
+        if let Some(redirect) = self.redirect_to_https {
+            config.redirect_to_https = redirect;
+        }
+        if let Some(middleware) = self.middleware {
+            config.middleware = middleware;
+        }
+        if let Some(origins) = self.cors_allow_origins {
+            config.cors_allow_origins = origins;
+        }
+        if let Some(realm) = self.basic_auth_realm {
+            config.basic_auth_realm = realm;
+        }
+        if let Some(path) = self.basic_auth_file {
+            config.basic_auth_file = Some(path);
+        }
+        if let Some(rate) = self.rate_limit_per_sec {
+            config.rate_limit_per_sec = rate;
+        }
+        if let Some(burst) = self.rate_limit_burst {
+            config.rate_limit_burst = burst;
+        }
+        if let Some(metrics) = self.metrics {
+            config.metrics = metrics;
+        }
+        if let Some(path) = self.metrics_path {
+            config.metrics_path = path;
+        }
+        if let Some(health_checks) = self.health_checks {
+            config.health_checks = health_checks;
+        }
+        if let Some(admin_bind) = self.admin_bind {
+            config.admin_bind = Some(admin_bind);
+        }
+        if let Some(path) = self.admin_auth_file {
+            config.admin_auth_file = Some(path);
+        }
+        if let Some(level) = self.log_level {
+            config.log_level = level;
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Loads the configuration from the process arguments and environment.
+    pub fn load() -> Result<ServerConfig, ConfigError> {
+        ServerConfig::load_from(env::args().skip(1), |name| env::var(name).ok())
+    }
+
+    /// Loads the configuration from explicit `args` (without the program
+    /// name) and an environment lookup function.
+    pub fn load_from<I, E>(args: I, env_var: E) -> Result<ServerConfig, ConfigError>
+    where
+        I: IntoIterator<Item = String>,
+        E: Fn(&str) -> Option<String>,
+    {
+        let (config_path, cli) = parse_args(args)?;
+        let env = parse_env(&env_var)?;
+
+        let config_path = config_path
+            .or_else(|| env_var("WEBSERVER_CONFIG").filter(|path| !path.is_empty()))
+            .map(|path| (PathBuf::from(path), true))
+            .unwrap_or_else(|| (PathBuf::from(DEFAULT_CONFIG_FILE), false));
+
+        let mut config = ServerConfig::default();
+        if let Some(file) = read_file(&config_path.0, config_path.1)? {
+            file.apply_to(&mut config);
+        }
+        env.apply_to(&mut config);
+        cli.apply_to(&mut config);
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks that the settings make sense together.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |key: &str, message: &str| {
+            Err(ConfigError::InvalidValue {
+                key: key.to_owned(),
+                source: "validation",
+                message: message.to_owned(),
+            })
+        };
+
+        if self.workers == 0 {
+            return invalid("workers", "must be at least 1");
+        }
+        let check_prefix = |key: &str, prefix: &str| {
+            if !prefix.starts_with('/') {
+                return invalid(key, "prefix must start with '/'");
+            }
+            if prefix.contains([':', '*']) {
+                return invalid(key, "prefix must not contain ':' or '*'");
+            }
+            Ok(())
+        };
+        let virtual_proxies = self.virtual_host.iter().flat_map(|host| &host.proxy);
+        for route in self.proxy.iter().chain(virtual_proxies) {
+            check_prefix("proxy", &route.prefix)?;
+            if route.upstreams.is_empty() {
+                return invalid("proxy", "needs at least one upstream");
+            }
+        }
+        let virtual_cgi = self.virtual_host.iter().flat_map(|host| &host.cgi);
+        for route in self.cgi.iter().chain(virtual_cgi) {
+            check_prefix("cgi", &route.prefix)?;
+            if !route.program.is_file() {
+                return invalid("cgi", &format!("{} is not a file", route.program.display()));
+            }
+        }
+        let virtual_fastcgi = self.virtual_host.iter().flat_map(|host| &host.fastcgi);
+        for route in self.fastcgi.iter().chain(virtual_fastcgi) {
+            check_prefix("fastcgi", &route.prefix)?;
+        }
+        if self.proxy_connect_timeout.is_zero() {
+            return invalid("proxy_connect_timeout_secs", "must be greater than 0");
+        }
+        if self.proxy_timeout.is_zero() {
+            return invalid("proxy_timeout_secs", "must be greater than 0");
+        }
+        if self.cgi_timeout.is_zero() {
+            return invalid("cgi_timeout_secs", "must be greater than 0");
+        }
+        if self.read_timeout.is_zero() {
+            return invalid("read_timeout_ms", "must be greater than 0");
+        }
+        if self.header_timeout.is_zero() {
+            return invalid("header_timeout_secs", "must be greater than 0");
+        }
+        if self.body_timeout.is_zero() {
+            return invalid("body_timeout_secs", "must be greater than 0");
+        }
+        if self.write_timeout.is_zero() {
+            return invalid("write_timeout_secs", "must be greater than 0");
+        }
+        if self.keep_alive_timeout.is_zero() {
+            return invalid("keep_alive_timeout_secs", "must be greater than 0");
+        }
+        if self.max_requests_per_connection == 0 {
+            return invalid("max_requests_per_connection", "must be at least 1");
+        }
+        if self.max_headers == 0 {
+            return invalid("max_headers", "must be at least 1");
+        }
+        if self.max_header_line < 64 {
+            return invalid("max_header_line", "must be at least 64 bytes");
+        }
+        if self.max_uri_length < 16 {
+            return invalid("max_uri_length", "must be at least 16 bytes");
+        }
+        if !self.document_root.is_dir() {
+            return invalid(
+                "document_root",
+                &format!("{} is not a directory", self.document_root.display()),
+            );
+        }
+        if let Some(dir) = &self.error_pages
+            && !dir.is_dir()
+        {
+            return invalid(
+                "error_pages",
+                &format!("{} is not a directory", dir.display()),
+            );
+        }
+        let mut names = HashSet::new();
+        for host in &self.virtual_host {
+            if host.names.is_empty() {
+                return invalid("virtual_host", "needs at least one name");
+            }
+            for name in &host.names {
+                if !is_host_pattern(name) {
+                    return invalid("virtual_host", &format!("{name:?} is not a host name"));
+                }
+                if !names.insert(name.to_ascii_lowercase()) {
+                    return invalid("virtual_host", &format!("{name:?} is listed twice"));
+                }
+            }
+            for dir in [Some(&host.document_root), host.error_pages.as_ref()]
+                .into_iter()
+                .flatten()
+            {
+                if !dir.is_dir() {
+                    return invalid(
+                        "virtual_host",
+                        &format!("{} is not a directory", dir.display()),
+                    );
+                }
+            }
+        }
+        if self.tls_cert.is_some() != self.tls_key.is_some() {
+            return invalid("tls_cert", "tls_cert and tls_key must be given together");
+        }
+        if let Some(tls_bind) = self.tls_bind {
+            if !cfg!(feature = "tls") {
+                return invalid("tls_bind", "this build has no TLS support");
+            }
+            if self.tls_cert.is_none() && self.tls_sni.is_empty() {
+                return invalid("tls_bind", "needs tls_cert and tls_key or [[tls_sni]]");
+            }
+            if tls_bind == self.bind {
+                return invalid("tls_bind", "must differ from bind");
+            }
+        } else if self.redirect_to_https {
+            return invalid("redirect_to_https", "needs tls_bind");
+        }
+        if self.middleware.contains(&Builtin::BasicAuth) && self.basic_auth_file.is_none() {
+            return invalid("middleware", "basic_auth needs basic_auth_file");
+        }
+        if !(self.rate_limit_per_sec.is_finite() && self.rate_limit_per_sec > 0.0) {
+            return invalid("rate_limit_per_sec", "must be greater than 0");
+        }
+        if self.rate_limit_burst == 0 {
+            return invalid("rate_limit_burst", "must be at least 1");
+        }
+        if !self.metrics_path.starts_with('/') {
+            return invalid("metrics_path", "must start with /");
+        }
+        if let Some(admin_bind) = self.admin_bind
+            && (admin_bind == self.bind || Some(admin_bind) == self.tls_bind)
+        {
+            return invalid("admin_bind", "must differ from bind and tls_bind");
+        }
+        if let Some(admin_bind) = self.admin_bind
+            && !admin_bind.ip().is_loopback()
+            && self.admin_auth_file.is_none()
+        {
+            return invalid(
+                "admin_bind",
+                "must be a loopback address without admin_auth_file",
+            );
+        }
+        Ok(())
+    }
+
+    /// The per-connection settings derived from this configuration.
+    pub fn connection_config(&self) -> ConnectionConfig {
+        ConnectionConfig {
+            read_timeout: self.read_timeout,
+            header_timeout: self.header_timeout,
+            body_timeout: self.body_timeout,
+            write_timeout: self.write_timeout,
+            keep_alive_timeout: self.keep_alive_timeout,
+            max_requests: self.max_requests_per_connection,
+            limits: ParseLimits {
+                max_target_len: self.max_uri_length,
+                max_headers: self.max_headers,
+                max_header_line: self.max_header_line,
+            },
+            max_body_size: self.max_body_size,
+            access_log: None,
+            metrics: None,
+        }
+    }
+
+    /// Loads the certificates for the HTTPS listener.
+    #[cfg(feature = "tls")]
+    pub fn tls_config(&self) -> Result<std::sync::Arc<rustls::ServerConfig>, crate::tls::TlsError> {
+        let default = self.tls_cert.as_deref().zip(self.tls_key.as_deref());
+        crate::tls::server_config(default, &self.tls_sni)
+    }
+
+    /// A handler for the files in [`document_root`](ServerConfig::document_root).
+    pub fn static_files(&self) -> StaticFiles {
+        self.static_files_in(&self.document_root, &self.autoindex)
+    }
+
+    fn static_files_in(&self, document_root: &Path, autoindex: &[String]) -> StaticFiles {
+        let files = self.cache_control.iter().fold(
+            StaticFiles::new(document_root).precompressed(self.precompressed),
+            |files, rule| files.cache_control(&rule.pattern, &rule.value),
+        );
+        autoindex
+            .iter()
+            .fold(files, |files, pattern| files.autoindex(pattern))
+    }
+
+    /// The routes: each [`proxy`](ServerConfig::proxy),
+    /// [`cgi`](ServerConfig::cgi) and [`fastcgi`](ServerConfig::fastcgi)
+    /// prefix, then the static files for `GET` requests.
+    pub fn router(&self) -> Router {
+        self.site_router(self.static_files(), &self.proxy, &self.cgi, &self.fastcgi)
+    }
+
+    /// The [`router`](ServerConfig::router) for requests to other names,
+    /// and one for each [`virtual_host`](ServerConfig::virtual_host), each
+    /// with its error pages.
+    pub fn virtual_hosts(&self) -> VirtualHosts {
+        let default = with_error_pages(self.router(), self.error_pages.as_deref());
+        self.virtual_host
+            .iter()
+            .fold(VirtualHosts::new().default_host(default), |hosts, host| {
+                let files = self.static_files_in(&host.document_root, &host.autoindex);
+                let router = self.site_router(files, &host.proxy, &host.cgi, &host.fastcgi);
+                let site = Arc::new(with_error_pages(router, host.error_pages.as_deref()));
+                host.names
+                    .iter()
+                    .fold(hosts, |hosts, name| hosts.host(name, Arc::clone(&site)))
+            })
+    }
+
+    fn site_router(
+        &self,
+        files: StaticFiles,
+        proxy: &[ProxyRoute],
+        cgi: &[CgiRoute],
+        fastcgi: &[FastCgiRoute],
+    ) -> Router {
+        let router = proxy.iter().fold(Router::new(), |router, route| {
+            let prefix = route.prefix.trim_end_matches('/');
+            let mut proxy = Proxy::new(route.upstreams.iter().copied())
+                .connect_timeout(self.proxy_connect_timeout)
+                .timeout(self.proxy_timeout);
+            if route.strip_prefix {
+                proxy = proxy.strip_prefix(prefix);
+            }
+            router.any(&format!("{prefix}/*path"), proxy)
+        });
+        let router = cgi.iter().fold(router, |router, route| {
+            let prefix = route.prefix.trim_end_matches('/');
+            let program = route.env.iter().fold(
+                Cgi::new(&route.program)
+                    .script_name(prefix)
+                    .timeout(self.cgi_timeout)
+                    .max_response_size(self.cgi_max_response_size),
+                |program, (name, value)| program.env(name, value),
+            );
+            router.any(&format!("{prefix}/*path"), program)
+        });
+        let router = fastcgi.iter().fold(router, |router, route| {
+            let prefix = route.prefix.trim_end_matches('/');
+            let responder = route.params.iter().fold(
+                FastCgi::new(&route.socket)
+                    .script_name(prefix)
+                    .timeout(self.cgi_timeout)
+                    .max_response_size(self.cgi_max_response_size),
+                |responder, (name, value)| responder.param(name, value),
+            );
+            router.any(&format!("{prefix}/*path"), responder)
+        });
+        router.route(Method::Get, "/*path", files)
+    }
+
+    /// Wraps `handler` in the configured [`middleware`](ServerConfig::middleware).
+    pub fn middleware_chain<H: Handler + 'static>(&self, handler: H) -> std::io::Result<Chain> {
+        let mut chain = Chain::new(handler);
+        for builtin in &self.middleware {
+            chain = match builtin {
+                Builtin::RequestId => chain.with(RequestId::new()),
+                Builtin::Timing => chain.with(Timing),
+                Builtin::Compression => chain.with(Compression::new()),
+                Builtin::RateLimit => chain.with(RateLimit::new(
+                    self.rate_limit_per_sec,
+                    self.rate_limit_burst,
+                )),
+                Builtin::Cors => chain.with(
+                    self.cors_allow_origins
+                        .iter()
+                        .fold(Cors::new(), |cors, origin| cors.allow_origin(origin)),
+                ),
+                Builtin::BasicAuth => {
+                    let Some(path) = &self.basic_auth_file else {
+                        return Err(std::io::Error::other("basic_auth needs basic_auth_file"));
+                    };
+                    chain.with(BasicAuth::from_file(&self.basic_auth_realm, path)?)
+                }
+            };
+        }
+        Ok(chain)
+    }
+
+    /// The settings as JSON, with the keys and units of the TOML file.
+    pub fn to_json(&self) -> serde_json::Value {
+        let access_log = match &self.access_log {
+            LogTarget::Off => "off".to_owned(),
+            LogTarget::Stdout => "stdout".to_owned(),
+            LogTarget::File(path) => path.display().to_string(),
+        };
+        json!({
+            "bind": self.bind.to_string(),
+            "workers": self.workers,
+            "document_root": self.document_root,
+            "precompressed": self.precompressed,
+            "autoindex": self.autoindex,
+            "cache_control": self.cache_control,
+            "proxy": self.proxy,
+            "cgi": self.cgi,
+            "fastcgi": self.fastcgi,
+            "error_pages": self.error_pages,
+            "virtual_host": self.virtual_host,
+            "proxy_connect_timeout_secs": self.proxy_connect_timeout.as_secs(),
+            "proxy_timeout_secs": self.proxy_timeout.as_secs(),
+            "cgi_timeout_secs": self.cgi_timeout.as_secs(),
+            "cgi_max_response_size": self.cgi_max_response_size,
+            "read_timeout_ms": self.read_timeout.as_millis() as u64,
+            "header_timeout_secs": self.header_timeout.as_secs(),
+            "body_timeout_secs": self.body_timeout.as_secs(),
+            "write_timeout_secs": self.write_timeout.as_secs(),
+            "max_connections": self.max_connections,
+            "keep_alive_timeout_secs": self.keep_alive_timeout.as_secs(),
+            "max_requests_per_connection": self.max_requests_per_connection,
+            "max_headers": self.max_headers,
+            "max_header_line": self.max_header_line,
+            "max_uri_length": self.max_uri_length,
+            "max_body_size": self.max_body_size,
+            "shutdown_timeout_secs": self.shutdown_timeout.as_secs(),
+            "access_log": access_log,
+            "access_log_format": self.access_log_format,
+            "access_log_max_size": self.access_log_rotation.max_size,
+            "access_log_keep": self.access_log_rotation.keep,
+            "tls_bind": self.tls_bind.map(|addr| addr.to_string()),
+            "tls_cert": self.tls_cert,
+            "tls_key": self.tls_key,
+            "tls_sni": self.tls_sni,
+            "redirect_to_https": self.redirect_to_https,
+            "middleware": self.middleware,
+            "cors_allow_origins": self.cors_allow_origins,
+            "basic_auth_realm": self.basic_auth_realm,
+            "basic_auth_file": self.basic_auth_file,
+            "rate_limit_per_sec": self.rate_limit_per_sec,
+            "rate_limit_burst": self.rate_limit_burst,
+            "metrics": self.metrics,
+            "metrics_path": self.metrics_path,
+            "health_checks": self.health_checks,
+            "admin_bind": self.admin_bind.map(|addr| addr.to_string()),
+            "admin_auth_file": self.admin_auth_file,
+            "log_level": self.log_level,
+        })
+    }
+
+    /// Opens the configured access log; `None` if it is off.
+    pub fn access_log(&self) -> std::io::Result<Option<AccessLog>> {
+        AccessLog::open(
+            &self.access_log,
+            self.access_log_format,
+            self.access_log_rotation,
+        )
+    }
+}
+
+/// Wraps a site's `router` in its error pages, if it has any.
+fn with_error_pages(router: Router, error_pages: Option<&Path>) -> Chain {
+    let chain = Chain::new(router);
+    match error_pages {
+        Some(dir) => chain.with(ErrorPages::new(dir)),
+        None => chain,
+    }
+}
+
+/// Checks a virtual host name: `example.com` or `*.example.com`, no port.
+fn is_host_pattern(name: &str) -> bool {
+    let host = name.strip_prefix("*.").unwrap_or(name);
+    !host.is_empty()
+        && host
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.')
+}
+
+/// Splits the command line into the `--config` path and the other settings.
+fn parse_args<I>(args: I) -> Result<(Option<String>, PartialConfig), ConfigError>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut config_path = None;
+    let mut partial = PartialConfig::default();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            return Err(ConfigError::HelpRequested);
+        }
+        let Some(flag) = arg.strip_prefix("--") else {
+            return Err(ConfigError::UnknownFlag(arg));
+        };
+        // Both `--workers 8` and `--workers=8` are accepted.
+        let (name, value) = match flag.split_once('=') {
+            Some((name, value)) => (name.to_owned(), value.to_owned()),
+            None => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| ConfigError::MissingValue(arg.clone()))?;
+                (flag.to_owned(), value)
+            }
+        };
+
+        if name == "config" {
+            config_path = Some(value);
+            continue;
+        }
+        let key = name.replace('-', "_");
+        if name.contains('_') || !partial.set(&key, &value, "command line")? {
+            return Err(ConfigError::UnknownFlag(format!("--{name}")));
+        }
+    }
+
+    Ok((config_path, partial))
+}
+
+fn parse_env<E: Fn(&str) -> Option<String>>(env_var: &E) -> Result<PartialConfig, ConfigError> {
+    const KEYS: &[&str] = &[
+        "bind",
+        "workers",
+        "document_root",
+        "precompressed",
+        "autoindex",
+        "error_pages",
+        "proxy_connect_timeout_secs",
+        "proxy_timeout_secs",
+        "cgi_timeout_secs",
+        "cgi_max_response_size",
+        "read_timeout_ms",
+        "header_timeout_secs",
+        "body_timeout_secs",
+        "write_timeout_secs",
+        "max_connections",
+        "keep_alive_timeout_secs",
+        "max_requests_per_connection",
+        "max_headers",
+        "max_header_line",
+        "max_uri_length",
+        "max_body_size",
+        "shutdown_timeout_secs",
+        "access_log",
+        "access_log_format",
+        "access_log_max_size",
+        "access_log_keep",
+        "tls_bind",
+        "tls_cert",
+        "tls_key",
+        "redirect_to_https",
+        "middleware",
+        "cors_allow_origins",
+        "basic_auth_realm",
+        "basic_auth_file",
+        "rate_limit_per_sec",
+        "rate_limit_burst",
+        "metrics",
+        "metrics_path",
+        "health_checks",
+        "admin_bind",
+        "admin_auth_file",
+        "log_level",
+    ];
+
+    let mut partial = PartialConfig::default();
+    for key in KEYS {
+        let name = format!("{ENV_PREFIX}{}", key.to_ascii_uppercase());
+        if let Some(value) = env_var(&name).filter(|value| !value.is_empty()) {
+            partial.set(key, &value, "environment")?;
+        }
+    }
+    Ok(partial)
+}
+
+/// Reads the TOML file at `path`. A missing file is only an error when it
+/// was asked for explicitly.
+fn read_file(path: &Path, required: bool) -> Result<Option<PartialConfig>, ConfigError> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && !required => return Ok(None),
+        Err(error) => {
+            return Err(ConfigError::Io {
+                path: path.to_owned(),
+                error,
+            });
+        }
+    };
+
+    toml::from_str(&contents)
+        .map(Some)
+        .map_err(|e| ConfigError::Parse {
+            path: path.to_owned(),
+            message: e.to_string(),
+        })
+}
+
+/// Parses a comma-separated list, e.g. `request_id,timing`.
+fn parse_list<T>(key: &str, value: &str, source: &'static str) -> Result<Vec<T>, ConfigError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| parse(key, item, source))
+        .collect()
+}
+
+fn parse<T>(key: &str, value: &str, source: &'static str) -> Result<T, ConfigError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    value
+        .parse()
+        .map_err(|e: T::Err| ConfigError::InvalidValue {
+            key: key.to_owned(),
+            source,
+            message: format!("{value:?}: {e}"),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn later_sources_win() {
+        let dir = env::temp_dir().join(format!("webserver-config-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("server.toml");
+        fs::write(
+            &file,
+            "workers = 2\nmax_headers = 50\nkeep_alive_timeout_secs = 30\ndocument_root = \".\"\n\
+             [[cache_control]]\npattern = \"*.css\"\nvalue = \"max-age=60\"\n\
+             [[proxy]]\nprefix = \"/api\"\nupstreams = [\"127.0.0.1:9000\"]\n\
+             [[virtual_host]]\nnames = [\"*.example.com\"]\ndocument_root = \".\"\nautoindex = [\"/\"]\n\
+             [[virtual_host.proxy]]\nprefix = \"/api\"\nupstreams = [\"127.0.0.1:9001\"]\n\
+             [[virtual_host.fastcgi]]\nprefix = \"/app\"\nsocket = \"/run/app.sock\"\n\
+             params = { SCRIPT_FILENAME = \"/srv/app/index.php\" }\n",
+        )
+        .unwrap();
+
+        let env = |name: &str| match name {
+            "WEBSERVER_WORKERS" => Some("3".to_owned()),
+            "WEBSERVER_MAX_HEADERS" => Some("60".to_owned()),
+            _ => None,
+        };
+        let cli = args(&[
+            "--config",
+            file.to_str().unwrap(),
+            "--workers=4",
+            "--autoindex",
+            "/pub, /pub/**",
+        ]);
+
+        let config = ServerConfig::load_from(cli, env).unwrap();
+        assert_eq!(config.workers, 4);
+        assert_eq!(config.max_headers, 60);
+        assert_eq!(config.keep_alive_timeout, Duration::from_secs(30));
+        assert_eq!(config.bind.port(), 7878);
+        assert_eq!(
+            config.cache_control,
+            [CacheRule {
+                pattern: "*.css".to_owned(),
+                value: "max-age=60".to_owned(),
+            }]
+        );
+        assert_eq!(
+            config.proxy[0].upstreams,
+            ["127.0.0.1:9000".parse().unwrap()]
+        );
+        assert!(!config.proxy[0].strip_prefix);
+        assert_eq!(config.autoindex, ["/pub", "/pub/**"]);
+        assert_eq!(config.virtual_host[0].names, ["*.example.com"]);
+        assert_eq!(config.virtual_host[0].autoindex, ["/"]);
+        assert_eq!(config.virtual_host[0].error_pages, None);
+        assert_eq!(config.virtual_host[0].proxy[0].prefix, "/api");
+        assert_eq!(
+            config.virtual_host[0].fastcgi[0].params["SCRIPT_FILENAME"],
+            "/srv/app/index.php"
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reports_bad_values() {
+        let no_env = |_: &str| None;
+
+        let err = ServerConfig::load_from(args(&["--workers", "0"]), no_env).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid value for workers (validation): must be at least 1"
+        );
+
+        let err = ServerConfig::load_from(args(&["--bind", "nowhere"]), no_env).unwrap_err();
+        assert!(matches!(err, ConfigError::InvalidValue { ref key, .. } if key == "bind"));
+
+        let err = ServerConfig::load_from(args(&["--colour", "red"]), no_env).unwrap_err();
+        assert!(matches!(err, ConfigError::UnknownFlag(_)));
+
+        let err =
+            ServerConfig::load_from(args(&["--config", "/nonexistent.toml"]), no_env).unwrap_err();
+        assert!(matches!(err, ConfigError::Io { .. }));
+
+        let err =
+            ServerConfig::load_from(args(&["--redirect-to-https", "true"]), no_env).unwrap_err();
+        assert!(
+            matches!(err, ConfigError::InvalidValue { ref key, .. } if key == "redirect_to_https")
+        );
+
+        let err =
+            ServerConfig::load_from(args(&["--admin-bind", "0.0.0.0:7879"]), no_env).unwrap_err();
+        assert!(matches!(err, ConfigError::InvalidValue { ref key, .. } if key == "admin_bind"));
+    }
+}
@@ -0,0 +1,479 @@
+//! Serving the requests of one client connection.
+
+use std::{
+    borrow::BorrowMut,
+    fmt,
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    mem,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use uuid::Uuid;
+
+use crate::{
+    access_log::{AccessLog, AccessRecord},
+    chunked::ChunkedReader,
+    handler::Handler,
+    log::warn,
+    metrics::Metrics,
+    request::{BodyFraming, Method, ParseError, ParseLimits, Request, Version},
+    response::{Response, Upgrade},
+    server::Tracker,
+    status::StatusCode,
+    stream::Stream,
+};
+
+/// Settings for a single client connection.
+#[derive(Debug, Clone)]
+pub struct ConnectionConfig {
+    /// How long each read waits while a request is being received before
+    /// the header and body deadlines are checked again. A client pausing
+    /// for longer is not cut off before those deadlines.
+    pub read_timeout: Duration,
+    /// Time allowed for a whole request head to arrive, however steadily
+    /// the client trickles it in. Slower clients get `408 Request Timeout`.
+    pub header_timeout: Duration,
+    /// Time allowed for a whole request body to arrive.
+    pub body_timeout: Duration,
+    /// Time allowed for each write to the client, so one that stops reading
+    /// does not hold the worker forever.
+    pub write_timeout: Duration,
+    /// How long an idle connection waits for its next request.
+    pub keep_alive_timeout: Duration,
+    /// Requests served on one connection before it is closed.
+    pub max_requests: usize,
+    /// Limits applied to every request head.
+    pub limits: ParseLimits,
+    /// Largest request body accepted, in bytes, unless the handler
+    /// [streams it](Handler::streams_body).
+    pub max_body_size: usize,
+    /// Where each request is recorded; `None` disables access logging.
+    pub access_log: Option<Arc<AccessLog>>,
+    /// Counts open connections; requests are counted by its layer.
+    pub metrics: Option<Arc<Metrics>>,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        ConnectionConfig {
+            read_timeout: Duration::from_millis(10),
+            header_timeout: Duration::from_secs(10),
+            body_timeout: Duration::from_secs(60),
+            write_timeout: Duration::from_secs(30),
+            keep_alive_timeout: Duration::from_secs(5),
+            max_requests: 100,
+            limits: ParseLimits::default(),
+            max_body_size: 10 * 1024 * 1024,
+            access_log: None,
+            metrics: None,
+        }
+    }
+}
+
+/// Serves requests from `stream` until the client or the server closes it.
+///
+/// Requests are answered in the order they arrive, so pipelined requests
+/// already sitting in the buffer are handled without waiting. HTTP/1.1
+/// connections stay open unless a side sends `Connection: close`, HTTP/1.0
+/// ones only when the client asks for `Connection: keep-alive`. Idle
+/// connections are dropped after [`ConnectionConfig::keep_alive_timeout`].
+///
+/// A connection switched to another protocol, e.g. a
+/// [`WebSocket`](crate::websocket::WebSocket), is served on the calling
+/// thread until it closes.
+pub fn handle_connection<H: Handler + ?Sized>(
+    connection_id: Uuid,
+    stream: impl Into<Stream>,
+    handler: &H,
+    config: &ConnectionConfig,
+) {
+    if let Some(upgraded) = serve_connection(connection_id, stream.into(), handler, config, None) {
+        upgraded.run();
+    }
+}
+
+/// A connection that left HTTP after a `101 Switching Protocols`.
+pub(crate) struct Upgraded {
+    reader: BufReader<Stream>,
+    upgrade: Upgrade,
+}
+
+impl Upgraded {
+    /// Runs the new protocol until the connection closes.
+    pub(crate) fn run(self) {
+        (self.upgrade.0)(self.reader);
+    }
+}
+
+/// [`handle_connection`] reporting to the [`Server`](crate::Server)'s
+/// tracker, which decides when to stop during shutdown. An upgraded
+/// connection is returned instead of served, so the caller can move it off
+/// the worker pool.
+pub(crate) fn serve_connection<H: Handler + ?Sized>(
+    connection_id: Uuid,
+    stream: Stream,
+    handler: &H,
+    config: &ConnectionConfig,
+    tracker: Option<&Tracker>,
+) -> Option<Upgraded> {
+    let draining = || tracker.is_some_and(Tracker::is_draining);
+    let _connection = config.metrics.as_deref().map(Metrics::connection);
+    let client = stream.tcp().peer_addr().ok();
+    let log = |request: Option<&Request>, status, size, time, started: Instant| {
+        if let Some(access_log) = &config.access_log {
+            access_log.log(&AccessRecord {
+                connection_id,
+                client,
+                time,
+                request,
+                status,
+                size,
+                duration: started.elapsed(),
+            });
+        }
+    };
+    if let Err(e) = stream.tcp().set_write_timeout(Some(config.write_timeout)) {
+        warn!("[{connection_id}] Cannot set write timeout: {e}");
+        return None;
+    }
+    let mut reader = BufReader::new(stream);
+    let mut served = 0;
+
+    while served < config.max_requests {
+        // Once shutdown begins, don't wait for further keep-alive requests.
+        if served > 0 && draining() {
+            break;
+        }
+        if !wait_for_request(&mut reader, config) {
+            break;
+        }
+        let (time, started) = (SystemTime::now(), Instant::now());
+        if let Some(tracker) = tracker {
+            tracker.request_started(connection_id);
+        }
+        let (mut request, streamed) = match read_request(&mut reader, config, handler) {
+            Ok(read) => read,
+            Err(ParseError::ConnectionClosed) => break,
+            Err(e) => {
+                let Some(status) = e.status() else {
+                    warn!("[{connection_id}] No valid HTTP request received: {e}");
+                    break;
+                };
+                let response = Response::error(status).with_header("Connection", "close");
+                match response.write_to(reader.get_mut(), Method::Get, Version::Http11) {
+                    Ok(size) => log(None, status, size, time, started),
+                    Err(e) => warn!("[{connection_id}] Write error: {e}"),
+                }
+                lingering_close(&mut reader);
+                break;
+            }
+        };
+        served += 1;
+        request.peer_addr = client;
+        request.secure = reader.get_ref().is_secure();
+        request.connection_id = connection_id;
+        request.sequence = served;
+
+        // A streamed body is read from the connection by the handler, which
+        // hands it back afterwards.
+        let mut body_left = false;
+        let mut response = if streamed {
+            let framing = request.body_framing();
+            request.incoming = Incoming::new(reader, framing, config);
+            let response = handler.handle(&mut request);
+            (reader, body_left) = mem::take(&mut request.incoming).finish();
+            response
+        } else {
+            handler.handle(&mut request)
+        };
+        let upgrade = response
+            .take_upgrade()
+            .filter(|_| response.status() == StatusCode::SWITCHING_PROTOCOLS);
+
+        let keep_alive = wants_keep_alive(&request, &response)
+            && served < config.max_requests
+            && !draining()
+            && !body_left;
+        if upgrade.is_some() {
+            // The connection stays open for the new protocol.
+        } else if !keep_alive {
+            response.headers_mut().insert("Connection", "close");
+        } else if request.version() == Version::Http10 {
+            response.headers_mut().insert("Connection", "keep-alive");
+            response.headers_mut().insert(
+                "Keep-Alive",
+                format!(
+                    "timeout={}, max={}",
+                    config.keep_alive_timeout.as_secs(),
+                    config.max_requests - served
+                ),
+            );
+        }
+
+        let status = response.status();
+        match response.write_to(reader.get_mut(), request.method(), request.version()) {
+            Ok(size) => log(Some(&request), status, size, time, started),
+            Err(e) => {
+                warn!("[{connection_id}] Write error: {e}");
+                break;
+            }
+        }
+        if let Some(tracker) = tracker {
+            tracker.request_finished(connection_id);
+        }
+        if let Some(upgrade) = upgrade {
+            return Some(Upgraded { reader, upgrade });
+        }
+        if !keep_alive {
+            // The client may still be sending the body the handler left.
+            if body_left {
+                lingering_close(&mut reader);
+            }
+            break;
+        }
+    }
+
+    // Lets TLS clients tell a complete close from a truncated one.
+    if reader.get_ref().is_secure() {
+        let _ = reader.get_mut().shutdown_write();
+    }
+    None
+}
+
+/// Reads the next request head and its body, sending `100 Continue` first
+/// when the client waits for it. A body the handler streams is left unread;
+/// the flag tells.
+fn read_request<H: Handler + ?Sized>(
+    reader: &mut BufReader<Stream>,
+    config: &ConnectionConfig,
+    handler: &H,
+) -> Result<(Request, bool), ParseError> {
+    let mut head = Deadline::new(&mut *reader, config.header_timeout, config.read_timeout);
+    let mut request = Request::parse(&mut head, &config.limits).map_err(|e| head.check(e))?;
+    let streamed = request.body_framing() != BodyFraming::None && handler.streams_body(&request);
+
+    if request.expects_continue() {
+        // Refuse early, so the client does not send a body we'd discard.
+        if !streamed
+            && request
+                .content_length()
+                .is_some_and(|length| length > config.max_body_size as u64)
+        {
+            return Err(ParseError::PayloadTooLarge);
+        }
+        let interim = format!("HTTP/1.1 {}\r\n\r\n", StatusCode::CONTINUE);
+        reader.get_mut().write_all(interim.as_bytes())?;
+    }
+    if streamed {
+        return Ok((request, true));
+    }
+
+    let mut body = Deadline::new(reader, config.body_timeout, config.read_timeout);
+    request
+        .read_body(&mut body, config.max_body_size)
+        .map_err(|e| body.check(e))?;
+    Ok((request, false))
+}
+
+/// A request body left on the connection for a handler that
+/// [streams it](Handler::streams_body), read through
+/// [`Request::body_reader`]. Clones of the request don't get it.
+#[derive(Default)]
+pub(crate) struct Incoming(Option<IncomingBody>);
+
+enum IncomingBody {
+    Length(io::Take<Deadline<BufReader<Stream>>>),
+    Chunked {
+        reader: ChunkedReader<Deadline<BufReader<Stream>>>,
+        done: bool,
+    },
+}
+
+impl Incoming {
+    fn new(reader: BufReader<Stream>, framing: BodyFraming, config: &ConnectionConfig) -> Self {
+        let reader = Deadline::new(reader, config.body_timeout, config.read_timeout);
+        Incoming(Some(match framing {
+            BodyFraming::Chunked => IncomingBody::Chunked {
+                reader: ChunkedReader::new(reader),
+                done: false,
+            },
+            BodyFraming::Length(length) => IncomingBody::Length(reader.take(length)),
+            BodyFraming::None => IncomingBody::Length(reader.take(0)),
+        }))
+    }
+
+    /// Returns `true` if there is a body on the connection.
+    pub(crate) fn is_set(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Gives the connection back, and whether part of the body is still
+    /// unread.
+    fn finish(self) -> (BufReader<Stream>, bool) {
+        match self.0.expect("the body was left on the connection") {
+            IncomingBody::Length(reader) => {
+                let left = reader.limit() > 0;
+                (reader.into_inner().reader, left)
+            }
+            IncomingBody::Chunked { reader, done } => (reader.into_inner().reader, !done),
+        }
+    }
+}
+
+impl Read for Incoming {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = match &mut self.0 {
+            None => 0,
+            Some(IncomingBody::Length(reader)) => {
+                let n = reader.read(buf)?;
+                if n == 0 && !buf.is_empty() && reader.limit() > 0 {
+                    return Err(io::Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "body shorter than announced",
+                    ));
+                }
+                n
+            }
+            Some(IncomingBody::Chunked { reader, done }) => {
+                let n = reader.read(buf)?;
+                *done |= n == 0 && !buf.is_empty();
+                n
+            }
+        };
+        Ok(n)
+    }
+}
+
+impl Clone for Incoming {
+    fn clone(&self) -> Self {
+        Incoming(None)
+    }
+}
+
+impl fmt::Debug for Incoming {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Incoming").field(&self.is_set()).finish()
+    }
+}
+
+/// Reads from the connection until a deadline, waiting out pauses of the
+/// client in slices of the per-read timeout.
+struct Deadline<R> {
+    reader: R,
+    deadline: Instant,
+    read_timeout: Duration,
+}
+
+impl<R: BorrowMut<BufReader<Stream>>> Deadline<R> {
+    fn new(reader: R, total: Duration, read_timeout: Duration) -> Self {
+        Deadline {
+            reader,
+            deadline: Instant::now() + total,
+            read_timeout,
+        }
+    }
+
+    /// Turns a read error caused by the deadline into [`ParseError::Timeout`].
+    fn check(&self, error: ParseError) -> ParseError {
+        match error {
+            ParseError::Io(_) if Instant::now() >= self.deadline => ParseError::Timeout,
+            error => error,
+        }
+    }
+}
+
+impl<R: BorrowMut<BufReader<Stream>>> Read for Deadline<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: BorrowMut<BufReader<Stream>>> BufRead for Deadline<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let reader = self.reader.borrow_mut();
+        while reader.buffer().is_empty() {
+            let remaining = self.deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(io::Error::new(ErrorKind::TimedOut, "deadline passed"));
+            }
+            reader
+                .get_ref()
+                .tcp()
+                .set_read_timeout(Some(remaining.min(self.read_timeout)))?;
+            match reader.fill_buf() {
+                Ok(_) => break,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted
+                    ) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        reader.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.reader.borrow_mut().consume(amount);
+    }
+}
+
+/// Blocks until the next request starts arriving. Returns `false` if the
+/// client closed the connection or stayed idle too long.
+fn wait_for_request(reader: &mut BufReader<Stream>, config: &ConnectionConfig) -> bool {
+    // A pipelined request may already be buffered.
+    if !reader.buffer().is_empty() {
+        return true;
+    }
+    if reader
+        .get_ref()
+        .tcp()
+        .set_read_timeout(Some(config.keep_alive_timeout))
+        .is_err()
+    {
+        return false;
+    }
+    loop {
+        match reader.fill_buf() {
+            Ok(buf) => return !buf.is_empty(),
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(_) => return false,
+        }
+    }
+}
+
+/// Half-closes the socket and drains what the client is still sending, so
+/// an error response is not lost to a connection reset.
+fn lingering_close(reader: &mut BufReader<Stream>) {
+    if reader.get_mut().shutdown_write().is_err()
+        || reader
+            .get_ref()
+            .tcp()
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .is_err()
+    {
+        return;
+    }
+    let _ = io::copy(&mut reader.take(64 * 1024), &mut io::sink());
+}
+
+/// Applies the HTTP/1.0 and HTTP/1.1 persistence rules to both sides.
+fn wants_keep_alive(request: &Request, response: &Response) -> bool {
+    if response.headers().has_token("Connection", "close") {
+        return false;
+    }
+    // HTTP/1.0 has no chunking, a stream ends when the connection does.
+    if request.version() == Version::Http10 && response.body().len().is_none() {
+        return false;
+    }
+    let headers = request.headers();
+    match request.version() {
+        Version::Http11 => !headers.has_token("Connection", "close"),
+        Version::Http10 => headers.has_token("Connection", "keep-alive"),
+    }
+}
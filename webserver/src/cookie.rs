@@ -0,0 +1,323 @@
+//! Cookies (RFC 6265): reading `Cookie` headers and writing `Set-Cookie`.
+
+use std::{
+    fmt,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::date::{http_date, parse_http_date};
+
+/// Where a cookie is sent along with requests started by other sites.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    /// Only with requests from the same site.
+    Strict,
+    /// Also when following a link from another site.
+    Lax,
+    /// With every request; browsers require [`Cookie::secure`] for this.
+    None,
+}
+
+impl SameSite {
+    fn as_str(self) -> &'static str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+/// A cookie to set with a `Set-Cookie` header.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use webserver::{Response, StatusCode, cookie::{Cookie, SameSite}};
+///
+/// let cookie = Cookie::new("theme", "dark")
+///     .path("/")
+///     .max_age(Duration::from_secs(3600))
+///     .http_only()
+///     .same_site(SameSite::Lax);
+/// assert_eq!(
+///     cookie.to_string(),
+///     "theme=dark; Path=/; Max-Age=3600; HttpOnly; SameSite=Lax"
+/// );
+/// let response = Response::new(StatusCode::NO_CONTENT).with_cookie(&cookie);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    expires: Option<SystemTime>,
+    max_age: Option<Duration>,
+    http_only: bool,
+    secure: bool,
+    same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    /// Creates a session cookie, one the browser forgets when it closes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` is not a token or `value` has characters cookies
+    /// cannot carry, such as spaces, `;` or `"`; encode such values first.
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Cookie {
+        let (name, value) = (name.into(), value.into());
+        assert!(
+            !name.is_empty() && name.bytes().all(is_token_byte),
+            "invalid cookie name {name:?}"
+        );
+        assert!(
+            value.bytes().all(is_cookie_octet),
+            "invalid cookie value {value:?}"
+        );
+        Cookie {
+            name,
+            value,
+            path: None,
+            expires: None,
+            max_age: None,
+            http_only: false,
+            secure: false,
+            same_site: None,
+        }
+    }
+
+    /// A cookie that makes the browser delete the cookie `name` at `path`.
+    pub fn removal(name: impl Into<String>, path: impl Into<String>) -> Cookie {
+        Cookie::new(name, "")
+            .path(path)
+            .expires(UNIX_EPOCH)
+            .max_age(Duration::ZERO)
+    }
+
+    /// Only sends the cookie for request paths at or below `path`.
+    pub fn path(mut self, path: impl Into<String>) -> Cookie {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// Keeps the cookie until `time`.
+    pub fn expires(mut self, time: SystemTime) -> Cookie {
+        self.expires = Some(time);
+        self
+    }
+
+    /// Keeps the cookie for `age`; browsers prefer it over
+    /// [`expires`](Cookie::expires).
+    pub fn max_age(mut self, age: Duration) -> Cookie {
+        self.max_age = Some(age);
+        self
+    }
+
+    /// Hides the cookie from scripts.
+    pub fn http_only(mut self) -> Cookie {
+        self.http_only = true;
+        self
+    }
+
+    /// Only sends the cookie over HTTPS.
+    pub fn secure(mut self) -> Cookie {
+        self.secure = true;
+        self
+    }
+
+    /// Sets the `SameSite` attribute.
+    pub fn same_site(mut self, same_site: SameSite) -> Cookie {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    /// The cookie name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The cookie value.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// The `Path` attribute.
+    pub fn get_path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    /// The `Expires` attribute.
+    pub fn get_expires(&self) -> Option<SystemTime> {
+        self.expires
+    }
+
+    /// The `Max-Age` attribute.
+    pub fn get_max_age(&self) -> Option<Duration> {
+        self.max_age
+    }
+
+    /// Returns `true` if the cookie has the `HttpOnly` attribute.
+    pub fn is_http_only(&self) -> bool {
+        self.http_only
+    }
+
+    /// Returns `true` if the cookie has the `Secure` attribute.
+    pub fn is_secure(&self) -> bool {
+        self.secure
+    }
+
+    /// The `SameSite` attribute.
+    pub fn get_same_site(&self) -> Option<SameSite> {
+        self.same_site
+    }
+}
+
+/// Formats the cookie as a `Set-Cookie` value.
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={path}")?;
+        }
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", http_date(expires))?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if self.http_only {
+            f.write_str("; HttpOnly")?;
+        }
+        if self.secure {
+            f.write_str("; Secure")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+/// Why a `Set-Cookie` value could not be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidCookie;
+
+impl fmt::Display for InvalidCookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid Set-Cookie value")
+    }
+}
+
+impl std::error::Error for InvalidCookie {}
+
+/// Parses a `Set-Cookie` value. Unknown attributes and ones with invalid
+/// values are ignored, as browsers do.
+impl FromStr for Cookie {
+    type Err = InvalidCookie;
+
+    fn from_str(value: &str) -> Result<Cookie, InvalidCookie> {
+        let mut attributes = value.split(';');
+        let (name, value) = attributes
+            .next()
+            .and_then(|pair| pair.split_once('='))
+            .ok_or(InvalidCookie)?;
+        let (name, value) = (name.trim(), unquote(value.trim()));
+        if name.is_empty()
+            || !name.bytes().all(is_token_byte)
+            || !value.bytes().all(is_cookie_octet)
+        {
+            return Err(InvalidCookie);
+        }
+
+        let mut cookie = Cookie::new(name, value);
+        for attribute in attributes {
+            let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+            let value = value.trim();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "path" => cookie.path = Some(value.to_owned()),
+                "expires" => cookie.expires = parse_http_date(value).or(cookie.expires),
+                "max-age" => {
+                    // A zero or negative age means "expire now".
+                    if let Ok(secs) = value.parse::<i64>() {
+                        cookie.max_age = Some(Duration::from_secs(secs.max(0) as u64));
+                    }
+                }
+                "httponly" => cookie.http_only = true,
+                "secure" => cookie.secure = true,
+                "samesite" => {
+                    cookie.same_site = match value.to_ascii_lowercase().as_str() {
+                        "strict" => Some(SameSite::Strict),
+                        "lax" => Some(SameSite::Lax),
+                        "none" => Some(SameSite::None),
+                        _ => cookie.same_site,
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(cookie)
+    }
+}
+
+/// Splits a `Cookie` request header into `(name, value)` pairs, skipping
+/// malformed ones. Double quotes around values are removed.
+pub fn parse(header: &str) -> impl Iterator<Item = (&str, &str)> {
+    header.split(';').filter_map(|pair| {
+        let (name, value) = pair.split_once('=')?;
+        let name = name.trim();
+        (!name.is_empty()).then(|| (name, unquote(value.trim())))
+    })
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+fn is_token_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)
+}
+
+/// `cookie-octet` of RFC 6265: printable ASCII but `"`, `,`, `;` and `\`.
+fn is_cookie_octet(byte: u8) -> bool {
+    matches!(byte, 0x21 | 0x23..=0x2B | 0x2D..=0x3A | 0x3C..=0x5B | 0x5D..=0x7E)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_and_parses_set_cookie() {
+        let cookie = Cookie::removal("id", "/app")
+            .secure()
+            .same_site(SameSite::Strict);
+        let header = cookie.to_string();
+        assert_eq!(
+            header,
+            "id=; Path=/app; Expires=Thu, 01 Jan 1970 00:00:00 GMT; Max-Age=0; Secure; \
+             SameSite=Strict"
+        );
+        assert_eq!(header.parse::<Cookie>(), Ok(cookie));
+
+        let parsed: Cookie = "lang=\"en\"; max-age=-1; HTTPONLY; SameSite=bogus; Domain=x"
+            .parse()
+            .unwrap();
+        assert_eq!((parsed.name(), parsed.value()), ("lang", "en"));
+        assert_eq!(parsed.get_max_age(), Some(Duration::ZERO));
+        assert!(parsed.is_http_only() && !parsed.is_secure());
+        assert_eq!(parsed.get_same_site(), None);
+        assert_eq!("no-equals-sign".parse::<Cookie>(), Err(InvalidCookie));
+    }
+
+    #[test]
+    fn parses_cookie_headers() {
+        let pairs: Vec<_> = parse("a=1; b=\"two\";bad; =x; c=").collect();
+        assert_eq!(pairs, [("a", "1"), ("b", "two"), ("c", "")]);
+    }
+}
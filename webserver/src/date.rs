@@ -0,0 +1,194 @@
+//! Calendar dates in UTC for log and header timestamps.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// A point in time broken down into UTC calendar fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct DateTime {
+    pub(crate) year: i64,
+    /// 1 to 12.
+    pub(crate) month: u32,
+    /// 1 to 31.
+    pub(crate) day: u32,
+    pub(crate) hour: u32,
+    pub(crate) minute: u32,
+    pub(crate) second: u32,
+    pub(crate) millisecond: u32,
+    /// 0 for Sunday to 6 for Saturday.
+    pub(crate) weekday: u32,
+}
+
+impl DateTime {
+    /// Converts `time`; times before 1970 are clamped to the epoch.
+    pub(crate) fn from_system_time(time: SystemTime) -> DateTime {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let secs = since_epoch.as_secs() as i64;
+        let days = secs.div_euclid(86_400);
+        let (year, month, day) = civil_from_days(days);
+        let of_day = secs.rem_euclid(86_400) as u32;
+
+        DateTime {
+            year,
+            month,
+            day,
+            hour: of_day / 3600,
+            minute: of_day / 60 % 60,
+            second: of_day % 60,
+            millisecond: since_epoch.subsec_millis(),
+            // 1970-01-01 was a Thursday.
+            weekday: (days + 4).rem_euclid(7) as u32,
+        }
+    }
+
+    /// The abbreviated English month name.
+    pub(crate) fn month_name(&self) -> &'static str {
+        MONTHS[self.month as usize - 1]
+    }
+
+    /// Common Log Format, e.g. `10/Oct/2000:13:55:36 +0000`.
+    pub(crate) fn to_clf(self) -> String {
+        format!(
+            "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+            self.day,
+            self.month_name(),
+            self.year,
+            self.hour,
+            self.minute,
+            self.second
+        )
+    }
+
+    /// The IMF-fixdate format of HTTP headers, e.g.
+    /// `Tue, 10 Oct 2000 13:55:36 GMT`.
+    pub(crate) fn to_http_date(self) -> String {
+        format!(
+            "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+            WEEKDAYS[self.weekday as usize],
+            self.day,
+            self.month_name(),
+            self.year,
+            self.hour,
+            self.minute,
+            self.second
+        )
+    }
+
+    /// RFC 3339 with milliseconds, e.g. `2000-10-10T13:55:36.000Z`.
+    pub(crate) fn to_rfc3339(self) -> String {
+        format!(
+            "{}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second, self.millisecond
+        )
+    }
+}
+
+/// Formats `time` for headers such as `Last-Modified`.
+pub(crate) fn http_date(time: SystemTime) -> String {
+    DateTime::from_system_time(time).to_http_date()
+}
+
+/// Parses an HTTP-date in any of the three formats RFC 9110 requires
+/// recipients to accept: IMF-fixdate, RFC 850 and asctime.
+pub(crate) fn parse_http_date(value: &str) -> Option<SystemTime> {
+    let value = value.trim();
+    let (day, month, year, time) = match value.split_once(", ") {
+        // `Sun, 06 Nov 1994 08:49:37 GMT` or `Sunday, 06-Nov-94 08:49:37 GMT`
+        Some((_, rest)) => match rest.split([' ', '-']).collect::<Vec<_>>()[..] {
+            [day, month, year, time, "GMT"] => (day, month, year, time),
+            _ => return None,
+        },
+        // `Sun Nov  6 08:49:37 1994`
+        None => match value.split_whitespace().collect::<Vec<_>>()[..] {
+            [_, month, day, time, year] => (day, month, year, time),
+            _ => return None,
+        },
+    };
+
+    let day: u32 = day.parse().ok()?;
+    let month = MONTHS.iter().position(|name| *name == month)? as u32 + 1;
+    let mut year: i64 = year.parse().ok()?;
+    if year < 100 {
+        year += if year < 70 { 2000 } else { 1900 };
+    }
+    let mut clock = time.split(':').map(|part| part.parse::<u64>().ok());
+    let (Some(Some(hour)), Some(Some(minute)), Some(Some(second)), None) =
+        (clock.next(), clock.next(), clock.next(), clock.next())
+    else {
+        return None;
+    };
+    if !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    let days = u64::try_from(days_from_civil(year, month, day)).ok()?;
+    let secs = days * 86_400 + hour * 3600 + minute * 60 + second;
+    Some(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// Turns (year, month, day) into days since 1970-01-01, the inverse of
+/// [`civil_from_days`].
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let mp = i64::from((month + 9) % 12);
+    let day_of_year = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Turns days since 1970-01-01 into (year, month, day), after Howard
+/// Hinnant's `civil_from_days`.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn formats_known_dates() {
+        let time = UNIX_EPOCH + Duration::from_millis(971_186_136_250);
+        let date = DateTime::from_system_time(time);
+        assert_eq!(date.to_clf(), "10/Oct/2000:13:55:36 +0000");
+        assert_eq!(date.to_rfc3339(), "2000-10-10T13:55:36.250Z");
+
+        let leap_day = UNIX_EPOCH + Duration::from_secs(1_709_164_800);
+        assert_eq!(
+            DateTime::from_system_time(leap_day).to_rfc3339(),
+            "2024-02-29T00:00:00.000Z"
+        );
+        assert_eq!(date.to_http_date(), "Tue, 10 Oct 2000 13:55:36 GMT");
+    }
+
+    #[test]
+    fn parses_all_http_date_formats() {
+        let expected = Some(UNIX_EPOCH + Duration::from_secs(784_111_777));
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), expected);
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), expected);
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), expected);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 PST"), None);
+        assert_eq!(parse_http_date("yesterday"), None);
+
+        let now = UNIX_EPOCH + Duration::from_secs(1_760_000_000);
+        assert_eq!(parse_http_date(&http_date(now)), Some(now));
+    }
+}
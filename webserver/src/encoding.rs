@@ -0,0 +1,153 @@
+//! Content codings (`gzip`, `deflate`, `br`) and `Accept-Encoding`
+//! negotiation.
+
+use std::io::{self, Read, Write};
+
+use flate2::{
+    Compression,
+    read::{GzEncoder, ZlibEncoder},
+};
+
+/// Brotli quality for on-the-fly compression; 11 is too slow per request.
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW: u32 = 22;
+const BROTLI_BUFFER: usize = 16 * 1024;
+
+/// A content coding the server can produce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+    /// The zlib format, which is what HTTP calls `deflate`.
+    Deflate,
+}
+
+impl Encoding {
+    /// All codings, most preferred first.
+    pub const ALL: [Encoding; 3] = [Encoding::Brotli, Encoding::Gzip, Encoding::Deflate];
+
+    /// The `Content-Encoding` token.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    /// The file extension of precompressed siblings, if the coding has one.
+    pub fn extension(&self) -> Option<&'static str> {
+        match self {
+            Encoding::Brotli => Some("br"),
+            Encoding::Gzip => Some("gz"),
+            Encoding::Deflate => None,
+        }
+    }
+
+    /// Compresses `bytes` in one go.
+    pub fn encode(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        let mut encoded = Vec::new();
+        self.encoder(bytes).read_to_end(&mut encoded)?;
+        Ok(encoded)
+    }
+
+    /// Wraps `reader` so that reading from it yields compressed data.
+    pub fn encoder<'a, R: Read + Send + 'a>(&self, reader: R) -> Box<dyn Read + Send + 'a> {
+        match self {
+            Encoding::Brotli => Box::new(brotli::CompressorReader::new(
+                reader,
+                BROTLI_BUFFER,
+                BROTLI_QUALITY,
+                BROTLI_WINDOW,
+            )),
+            Encoding::Gzip => Box::new(GzEncoder::new(reader, Compression::default())),
+            Encoding::Deflate => Box::new(ZlibEncoder::new(reader, Compression::default())),
+        }
+    }
+
+    /// Undoes the coding; mostly useful in tests.
+    pub fn decode(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        let mut decoded = Vec::new();
+        match self {
+            Encoding::Brotli => {
+                brotli::Decompressor::new(bytes, BROTLI_BUFFER).read_to_end(&mut decoded)?;
+            }
+            Encoding::Gzip => {
+                flate2::write::GzDecoder::new(&mut decoded).write_all(bytes)?;
+            }
+            Encoding::Deflate => {
+                flate2::write::ZlibDecoder::new(&mut decoded).write_all(bytes)?;
+            }
+        }
+        Ok(decoded)
+    }
+}
+
+/// Picks the best of `available` for an `Accept-Encoding` header value.
+///
+/// The coding with the highest q-value wins, ties go to the order of
+/// `available`. `*` stands for codings not listed, `q=0` rules a coding out
+/// and `x-gzip` is treated as `gzip`. `None` means the identity coding.
+pub fn negotiate(accept_encoding: &str, available: &[Encoding]) -> Option<Encoding> {
+    let preferences: Vec<(String, f32)> = accept_encoding
+        .split(',')
+        .filter_map(|item| {
+            let mut params = item.split(';');
+            let coding = params.next()?.trim().to_ascii_lowercase();
+            if coding.is_empty() {
+                return None;
+            }
+            let q = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            Some((coding, q.clamp(0.0, 1.0)))
+        })
+        .collect();
+
+    let q_of = |encoding: Encoding| {
+        let named = preferences.iter().find(|(coding, _)| {
+            coding == encoding.as_str() || (encoding == Encoding::Gzip && coding == "x-gzip")
+        });
+        named
+            .or_else(|| preferences.iter().find(|(coding, _)| coding == "*"))
+            .map_or(0.0, |(_, q)| *q)
+    };
+
+    let mut best: Option<(Encoding, f32)> = None;
+    for &encoding in available {
+        let q = q_of(encoding);
+        if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((encoding, q));
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn honours_q_values() {
+        let all = &Encoding::ALL;
+        assert_eq!(negotiate("gzip, deflate, br", all), Some(Encoding::Brotli));
+        assert_eq!(negotiate("gzip;q=1.0, br;q=0.5", all), Some(Encoding::Gzip));
+        assert_eq!(negotiate("br;q=0, *", all), Some(Encoding::Gzip));
+        assert_eq!(negotiate("x-gzip", all), Some(Encoding::Gzip));
+        assert_eq!(negotiate("identity", all), None);
+        assert_eq!(negotiate("*;q=0", all), None);
+        assert_eq!(negotiate("", all), None);
+        assert_eq!(negotiate("deflate, br", &[Encoding::Gzip]), None);
+    }
+
+    #[test]
+    fn round_trips() {
+        let text = "hello hello hello hello hello ".repeat(20);
+        for encoding in Encoding::ALL {
+            let encoded = encoding.encode(text.as_bytes()).unwrap();
+            assert!(encoded.len() < text.len());
+            assert_eq!(encoding.decode(&encoded).unwrap(), text.as_bytes());
+        }
+    }
+}
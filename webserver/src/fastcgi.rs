@@ -0,0 +1,272 @@
+//! A FastCGI client for responders listening on a Unix socket, such as
+//! PHP-FPM.
+
+use std::{
+    io::{self, BufReader, Read, Write},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    cgi::{self, GatewayError},
+    handler::Handler,
+    log::warn,
+    request::Request,
+    response::Response,
+};
+
+const VERSION: u8 = 1;
+const BEGIN_REQUEST: u8 = 1;
+const END_REQUEST: u8 = 3;
+const PARAMS: u8 = 4;
+const STDIN: u8 = 5;
+const STDOUT: u8 = 6;
+const STDERR: u8 = 7;
+const RESPONDER: u16 = 1;
+/// `protocol_status` of an `END_REQUEST` record from a busy responder.
+const OVERLOADED: u8 = 2;
+/// The only request on each connection.
+const REQUEST_ID: u16 = 1;
+/// Most content one record can carry.
+const MAX_CONTENT: usize = u16::MAX as usize;
+
+/// Passes requests to a FastCGI responder and sends back what it answers.
+///
+/// The responder gets the same meta-variables a [`Cgi`](crate::Cgi) program
+/// would, plus the configured [`param`](FastCgi::param)s, e.g. the
+/// `SCRIPT_FILENAME` PHP-FPM needs. Every request uses a new connection.
+/// Whatever the responder writes to stderr is logged. Clients get
+/// `502 Bad Gateway` if the responder cannot be reached, answers nonsense
+/// or writes more than [`max_response_size`](FastCgi::max_response_size),
+/// `503 Service Unavailable` if it is overloaded and
+/// `504 Gateway Timeout` if it has not answered within the
+/// [`timeout`](FastCgi::timeout).
+///
+/// # Examples
+///
+/// ```
+/// use webserver::{FastCgi, Router};
+///
+/// let app = FastCgi::new("/run/php/php-fpm.sock")
+///     .param("SCRIPT_FILENAME", "/srv/app/index.php")
+///     .script_name("/app");
+/// let router = Router::new().any("/app/*path", app);
+/// ```
+pub struct FastCgi {
+    socket: PathBuf,
+    script_name: Option<String>,
+    params: Vec<(String, String)>,
+    timeout: Duration,
+    max_response_size: usize,
+}
+
+impl FastCgi {
+    /// Talks to the responder listening on the Unix socket `socket`.
+    pub fn new(socket: impl Into<PathBuf>) -> FastCgi {
+        FastCgi {
+            socket: socket.into(),
+            script_name: None,
+            params: Vec::new(),
+            timeout: Duration::from_secs(30),
+            max_response_size: 10 * 1024 * 1024,
+        }
+    }
+
+    /// The path prefix the responder is mounted at, e.g. `/app`; the rest
+    /// of the path becomes `PATH_INFO`. Without one, `SCRIPT_NAME` is the
+    /// whole path.
+    pub fn script_name(mut self, prefix: impl Into<String>) -> FastCgi {
+        self.script_name = Some(prefix.into().trim_end_matches('/').to_owned());
+        self
+    }
+
+    /// Adds the parameter `name`, overriding a meta-variable of the same
+    /// name.
+    pub fn param(mut self, name: impl Into<String>, value: impl Into<String>) -> FastCgi {
+        self.params.push((name.into(), value.into()));
+        self
+    }
+
+    /// How long the responder may take to answer a request in full, as a
+    /// [`Cgi`](crate::Cgi) program may to run. Defaults to 30 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> FastCgi {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Most output, stdout and stderr together, the responder may send for
+    /// a request. Defaults to 10 MiB.
+    pub fn max_response_size(mut self, bytes: usize) -> FastCgi {
+        self.max_response_size = bytes;
+        self
+    }
+
+    fn exchange(&self, request: &Request) -> Result<Response, GatewayError> {
+        let deadline = Instant::now() + self.timeout;
+        let stream = UnixStream::connect(&self.socket)?;
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        let mut records = Vec::new();
+        // Role, then flags without FCGI_KEEP_CONN: the responder closes
+        // the connection when done.
+        let [role_high, role_low] = RESPONDER.to_be_bytes();
+        write_record(
+            &mut records,
+            BEGIN_REQUEST,
+            &[role_high, role_low, 0, 0, 0, 0, 0, 0],
+        )?;
+        let mut params = Vec::new();
+        let variables = cgi::meta_variables(request, self.script_name.as_deref());
+        for (name, value) in variables.iter().chain(&self.params) {
+            encode_param(&mut params, name, value);
+        }
+        write_stream(&mut records, PARAMS, &params)?;
+        write_stream(&mut records, STDIN, request.body())?;
+        // Sending on its own thread keeps a responder that answers before
+        // reading the whole body from blocking on a full socket buffer.
+        let mut writer = stream.try_clone()?;
+        thread::spawn(move || {
+            // Responders need not read the body.
+            let _ = writer.write_all(&records);
+        });
+
+        let mut reader = BufReader::new(&stream);
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+        let protocol_status = loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(GatewayError::TimedOut);
+            }
+            stream.set_read_timeout(Some(remaining))?;
+            let mut header = [0; 8];
+            reader.read_exact(&mut header).map_err(|e| match e.kind() {
+                io::ErrorKind::UnexpectedEof => GatewayError::Malformed("no END_REQUEST"),
+                _ => GatewayError::Io(e),
+            })?;
+            if header[0] != VERSION {
+                return Err(GatewayError::Malformed("unsupported version"));
+            }
+            let length = u16::from_be_bytes([header[4], header[5]]);
+            let mut content = vec![0; usize::from(length)];
+            reader.read_exact(&mut content)?;
+            io::copy(
+                &mut (&mut reader).take(u64::from(header[6])),
+                &mut io::sink(),
+            )?;
+            if u16::from_be_bytes([header[2], header[3]]) != REQUEST_ID {
+                continue;
+            }
+            if matches!(header[1], STDOUT | STDERR)
+                && stdout.len() + stderr.len() + content.len() > self.max_response_size
+            {
+                return Err(GatewayError::TooLarge);
+            }
+            match header[1] {
+                STDOUT => stdout.extend_from_slice(&content),
+                STDERR => stderr.extend_from_slice(&content),
+                END_REQUEST => match content.get(4) {
+                    Some(&status) => break status,
+                    None => return Err(GatewayError::Malformed("short END_REQUEST")),
+                },
+                _ => {}
+            }
+        };
+
+        for line in String::from_utf8_lossy(&stderr).lines() {
+            warn!(
+                "[{}] {}: {line}",
+                request.connection_id(),
+                self.socket.display()
+            );
+        }
+        match protocol_status {
+            0 => cgi::parse_response(&stdout),
+            OVERLOADED => Err(GatewayError::Unavailable),
+            _ => Err(GatewayError::Malformed("request rejected")),
+        }
+    }
+}
+
+impl Handler for FastCgi {
+    fn handle(&self, request: &mut Request) -> Response {
+        match self.exchange(request) {
+            Ok(response) => response,
+            Err(e) => {
+                warn!(
+                    "[{}] FastCGI responder {} failed: {e}",
+                    request.connection_id(),
+                    self.socket.display()
+                );
+                e.response()
+            }
+        }
+    }
+}
+
+fn write_record<W: Write>(writer: &mut W, kind: u8, content: &[u8]) -> io::Result<()> {
+    let [id_high, id_low] = REQUEST_ID.to_be_bytes();
+    let [length_high, length_low] = (content.len() as u16).to_be_bytes();
+    // Padding to a multiple of eight bytes is recommended, not required.
+    let padding = (8 - content.len() % 8) % 8;
+    writer.write_all(&[
+        VERSION,
+        kind,
+        id_high,
+        id_low,
+        length_high,
+        length_low,
+        padding as u8,
+        0,
+    ])?;
+    writer.write_all(content)?;
+    writer.write_all(&[0; 8][..padding])
+}
+
+/// Writes `data` as a stream of records, ended by an empty one.
+fn write_stream<W: Write>(writer: &mut W, kind: u8, data: &[u8]) -> io::Result<()> {
+    for chunk in data.chunks(MAX_CONTENT) {
+        write_record(writer, kind, chunk)?;
+    }
+    write_record(writer, kind, &[])
+}
+
+/// Appends a name-value pair, each length in one byte below 128 and four
+/// bytes with the high bit set above.
+fn encode_param(out: &mut Vec<u8>, name: &str, value: &str) {
+    for length in [name.len(), value.len()] {
+        if length < 128 {
+            out.push(length as u8);
+        } else {
+            out.extend_from_slice(&(length as u32 | 0x8000_0000).to_be_bytes());
+        }
+    }
+    out.extend_from_slice(name.as_bytes());
+    out.extend_from_slice(value.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_records_and_params() {
+        let mut params = Vec::new();
+        encode_param(&mut params, "A", "xy");
+        encode_param(&mut params, "LONG", &"v".repeat(200));
+        assert_eq!(&params[..5], b"\x01\x02Axy");
+        assert_eq!(&params[5..10], b"\x04\x80\x00\x00\xc8");
+
+        let mut record = Vec::new();
+        write_record(&mut record, STDIN, b"abc").unwrap();
+        assert_eq!(record, b"\x01\x05\x00\x01\x00\x03\x05\x00abc\0\0\0\0\0");
+
+        let mut stream = Vec::new();
+        write_stream(&mut stream, STDIN, &vec![7; MAX_CONTENT + 1]).unwrap();
+        // A full record padded by one byte, the last byte padded by seven,
+        // and the empty record ending the stream.
+        assert_eq!(stream.len(), 8 + MAX_CONTENT + 1 + 8 + 1 + 7 + 8);
+    }
+}
@@ -0,0 +1,750 @@
+//! Form bodies: `application/x-www-form-urlencoded` and `multipart/form-data`.
+//!
+//! [`Form::parse`] turns the body of a [`Request`] into text fields and
+//! uploaded files. Multipart bodies are parsed as they are read, through a
+//! buffer of a few KiB, and uploads over [`FormLimits::memory_threshold`]
+//! are written straight to temporary files. Those are removed when the
+//! [`UploadedFile`] is dropped, unless [`persist`](UploadedFile::persist)ed.
+//!
+//! Handlers [streaming the body](crate::Handler::streams_body), e.g. wrapped
+//! in [`StreamingBody`](crate::handler::StreamingBody), get uploads limited
+//! only by [`FormLimits::max_size`]; for all others the connection reads the
+//! whole body first, up to its
+//! [`max_body_size`](crate::connection::ConnectionConfig::max_body_size).
+
+use std::{
+    env, fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind, Read, Write},
+    mem,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use uuid::Uuid;
+
+use crate::{request::Request, status::StatusCode, url};
+
+/// Limits applied while parsing a form.
+#[derive(Debug, Clone)]
+pub struct FormLimits {
+    /// Maximum number of fields and files together.
+    pub max_parts: usize,
+    /// Maximum size of the body in bytes, checked while it is read. Bodies
+    /// the connection reads in full are also limited by its `max_body_size`.
+    pub max_size: u64,
+    /// Uploads larger than this many bytes are written to disk rather than
+    /// kept in memory.
+    pub memory_threshold: usize,
+    /// Directory for uploads written to disk.
+    pub upload_dir: PathBuf,
+}
+
+impl Default for FormLimits {
+    fn default() -> Self {
+        FormLimits {
+            max_parts: 100,
+            max_size: 10 * 1024 * 1024,
+            memory_threshold: 64 * 1024,
+            upload_dir: env::temp_dir(),
+        }
+    }
+}
+
+/// Why a form could not be parsed, or a field not read.
+#[derive(Debug)]
+pub enum FormError {
+    /// The body is neither urlencoded nor `multipart/form-data`.
+    UnsupportedMediaType,
+    /// The body is not a well-formed form.
+    Malformed(&'static str),
+    /// More parts than [`FormLimits::max_parts`].
+    TooManyParts,
+    /// The body exceeds [`FormLimits::max_size`].
+    TooLarge,
+    /// Reading the body from the client failed.
+    Body(io::Error),
+    /// Writing an upload to disk failed.
+    Io(io::Error),
+    /// A required field was not sent.
+    MissingField(String),
+    /// A field could not be converted to the requested type.
+    InvalidField(String),
+}
+
+impl FormError {
+    /// The status code to answer with.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            FormError::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            FormError::TooManyParts | FormError::TooLarge => StatusCode::CONTENT_TOO_LARGE,
+            FormError::Body(e) if e.kind() == ErrorKind::TimedOut => StatusCode::REQUEST_TIMEOUT,
+            FormError::Body(_) => StatusCode::BAD_REQUEST,
+            FormError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            FormError::Malformed(_) | FormError::MissingField(_) | FormError::InvalidField(_) => {
+                StatusCode::BAD_REQUEST
+            }
+        }
+    }
+}
+
+impl fmt::Display for FormError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormError::UnsupportedMediaType => write!(f, "body is not a form"),
+            FormError::Malformed(reason) => write!(f, "malformed form: {reason}"),
+            FormError::TooManyParts => write!(f, "too many form parts"),
+            FormError::TooLarge => write!(f, "form too large"),
+            FormError::Body(e) => write!(f, "cannot read form: {e}"),
+            FormError::Io(e) => write!(f, "cannot store upload: {e}"),
+            FormError::MissingField(name) => write!(f, "missing field {name:?}"),
+            FormError::InvalidField(name) => write!(f, "invalid value for field {name:?}"),
+        }
+    }
+}
+
+impl std::error::Error for FormError {}
+
+impl From<io::Error> for FormError {
+    fn from(e: io::Error) -> Self {
+        FormError::Io(e)
+    }
+}
+
+/// The fields and files of a parsed form, in the order they were sent.
+///
+/// # Examples
+///
+/// ```no_run
+/// use webserver::{Request, Response, StatusCode, form::{Form, FormLimits}};
+///
+/// fn upload(request: &mut Request) -> Response {
+///     let form = match Form::parse(request, &FormLimits::default()) {
+///         Ok(form) => form,
+///         Err(e) => return Response::error(e.status()),
+///     };
+///     let Ok(copies) = form.get::<u32>("copies") else {
+///         return Response::error(StatusCode::BAD_REQUEST);
+///     };
+///     for (name, file) in form.into_files() {
+///         if name == "document" {
+///             let to = format!("uploads/{}", file.file_name().unwrap_or("unnamed"));
+///             file.persist(to).unwrap();
+///         }
+///     }
+///     Response::text(StatusCode::OK, format!("{copies} copies"))
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct Form {
+    fields: Vec<(String, String)>,
+    files: Vec<(String, UploadedFile)>,
+}
+
+impl Form {
+    /// Reads the body of `request` through
+    /// [`body_reader`](Request::body_reader) and parses it according to its
+    /// `Content-Type`.
+    pub fn parse(request: &mut Request, limits: &FormLimits) -> Result<Form, FormError> {
+        let content_type = request
+            .header("Content-Type")
+            .unwrap_or_default()
+            .to_owned();
+        let mut params = content_type.split(';');
+        let essence = params.next().unwrap_or_default().trim();
+
+        if essence.eq_ignore_ascii_case("application/x-www-form-urlencoded") {
+            parse_urlencoded(request.body_reader(), limits)
+        } else if essence.eq_ignore_ascii_case("multipart/form-data") {
+            let boundary = params
+                .filter_map(|param| param.split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("boundary"))
+                .map(|(_, value)| unquote(value.trim()))
+                .filter(|boundary| (1..=70).contains(&boundary.len()))
+                .ok_or(FormError::Malformed("missing boundary"))?;
+            parse_multipart(request.body_reader(), &boundary, limits)
+        } else {
+            Err(FormError::UnsupportedMediaType)
+        }
+    }
+
+    /// The first value of the text field `name`.
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// All values of the text field `name`, e.g. of checkboxes.
+    pub fn all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.fields
+            .iter()
+            .filter(move |(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Parses the first value of the text field `name`, e.g. as a number.
+    pub fn get<T: FromStr>(&self, name: &str) -> Result<T, FormError> {
+        self.field(name)
+            .ok_or_else(|| FormError::MissingField(name.to_owned()))?
+            .trim()
+            .parse()
+            .map_err(|_| FormError::InvalidField(name.to_owned()))
+    }
+
+    /// All text fields as `(name, value)` pairs.
+    pub fn fields(&self) -> &[(String, String)] {
+        &self.fields
+    }
+
+    /// The first file uploaded as `name`.
+    pub fn file(&self, name: &str) -> Option<&UploadedFile> {
+        self.files
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, file)| file)
+    }
+
+    /// All uploaded files as `(field name, file)` pairs.
+    pub fn files(&self) -> &[(String, UploadedFile)] {
+        &self.files
+    }
+
+    /// Takes ownership of the uploaded files, e.g. to keep them past the
+    /// request.
+    pub fn into_files(self) -> Vec<(String, UploadedFile)> {
+        self.files
+    }
+}
+
+/// A file sent in a `multipart/form-data` body.
+#[derive(Debug)]
+pub struct UploadedFile {
+    file_name: Option<String>,
+    content_type: String,
+    size: u64,
+    data: Data,
+}
+
+#[derive(Debug)]
+enum Data {
+    Memory(Vec<u8>),
+    /// A temporary file, removed on drop.
+    Disk(PathBuf),
+}
+
+impl UploadedFile {
+    /// The file name the client sent, without any directory part.
+    pub fn file_name(&self) -> Option<&str> {
+        self.file_name.as_deref()
+    }
+
+    /// The media type the client sent, `application/octet-stream` if none.
+    pub fn content_type(&self) -> &str {
+        &self.content_type
+    }
+
+    /// The size in bytes.
+    pub fn len(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns `true` if the file is empty.
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// The contents if the file is small enough to be kept in memory.
+    pub fn bytes(&self) -> Option<&[u8]> {
+        match &self.data {
+            Data::Memory(bytes) => Some(bytes),
+            Data::Disk(_) => None,
+        }
+    }
+
+    /// The temporary file holding the contents if the file was written to
+    /// disk.
+    pub fn path(&self) -> Option<&Path> {
+        match &self.data {
+            Data::Memory(_) => None,
+            Data::Disk(path) => Some(path),
+        }
+    }
+
+    /// Opens the contents for reading, wherever they are.
+    pub fn open(&self) -> io::Result<Box<dyn Read + '_>> {
+        match &self.data {
+            Data::Memory(bytes) => Ok(Box::new(&bytes[..])),
+            Data::Disk(path) => Ok(Box::new(File::open(path)?)),
+        }
+    }
+
+    /// Moves the contents to `to`, replacing any file there.
+    ///
+    /// Falls back to copying when `to` is on another file system.
+    pub fn persist(self, to: impl AsRef<Path>) -> io::Result<()> {
+        let to = to.as_ref();
+        match &self.data {
+            Data::Memory(bytes) => fs::write(to, bytes),
+            Data::Disk(path) => {
+                if fs::rename(path, to).is_err() {
+                    fs::copy(path, to)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Drop for UploadedFile {
+    fn drop(&mut self) {
+        if let Data::Disk(path) = &self.data {
+            // Already gone if it was persisted by renaming.
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+fn parse_urlencoded(body: impl Read, limits: &FormLimits) -> Result<Form, FormError> {
+    let mut body = Scanner::new(body, limits.max_size);
+    while body.fill()? {}
+    let body = std::str::from_utf8(&body.buf).map_err(|_| FormError::Malformed("not UTF-8"))?;
+    let fields = url::parse_query(body);
+    if fields.len() > limits.max_parts {
+        return Err(FormError::TooManyParts);
+    }
+    Ok(Form {
+        fields,
+        files: Vec::new(),
+    })
+}
+
+/// Longest line allowed after a boundary, in bytes.
+const MAX_LINE: usize = 1024;
+/// Longest head allowed for one part, in bytes.
+const MAX_PART_HEAD: usize = 8 * 1024;
+
+fn parse_multipart(
+    body: impl Read,
+    boundary: &str,
+    limits: &FormLimits,
+) -> Result<Form, FormError> {
+    let mut body = Scanner::new(body, limits.max_size);
+    let delimiter = format!("\r\n--{boundary}");
+    if body.starts_with(&delimiter.as_bytes()[2..])? {
+        body.consume(delimiter.len() - 2);
+    } else {
+        // Skip the preamble; the first delimiter then also follows a CRLF.
+        body.read_until(delimiter.as_bytes(), "missing boundary", |_| Ok(()))?;
+    }
+    let mut form = Form::default();
+    let mut parts = 0;
+
+    loop {
+        if body.starts_with(b"--")? {
+            // Whatever follows the final boundary is ignored, but read, so
+            // the connection can carry on.
+            while body.fill()? {
+                body.buf.clear();
+            }
+            return Ok(form);
+        }
+        // Whitespace may follow a boundary before its line ends.
+        let line = body.read_line(MAX_LINE, "unterminated part")?;
+        if !line.iter().all(|&b| b == b' ' || b == b'\t') {
+            return Err(FormError::Malformed("garbage after boundary"));
+        }
+
+        parts += 1;
+        if parts > limits.max_parts {
+            return Err(FormError::TooManyParts);
+        }
+        let mut head = Vec::new();
+        loop {
+            let line = body.read_line(MAX_PART_HEAD, "unterminated part head")?;
+            if line.is_empty() {
+                break;
+            }
+            head.extend_from_slice(&line);
+            head.extend_from_slice(b"\r\n");
+            if head.len() > MAX_PART_HEAD {
+                return Err(FormError::Malformed("part head too long"));
+            }
+        }
+        let head =
+            String::from_utf8(head).map_err(|_| FormError::Malformed("part head is not UTF-8"))?;
+        let part = PartHead::parse(&head)?;
+
+        let Some(file_name) = part.file_name else {
+            let mut value = Vec::new();
+            body.read_until(delimiter.as_bytes(), "missing final boundary", |chunk| {
+                value.extend_from_slice(chunk);
+                Ok(())
+            })?;
+            let value =
+                String::from_utf8(value).map_err(|_| FormError::Malformed("field is not UTF-8"))?;
+            form.fields.push((part.name, value));
+            continue;
+        };
+        // Browsers on Windows may send full paths; never let one pick a directory.
+        let file_name = file_name.rsplit(['/', '\\']).next().unwrap_or_default();
+        let mut file = UploadedFile {
+            file_name: (!file_name.is_empty()).then(|| file_name.to_owned()),
+            content_type: part
+                .content_type
+                .unwrap_or("application/octet-stream")
+                .to_owned(),
+            size: 0,
+            data: Data::Memory(Vec::new()),
+        };
+        let mut disk = None;
+        body.read_until(delimiter.as_bytes(), "missing final boundary", |chunk| {
+            file.append(&mut disk, chunk, limits)
+        })?;
+        form.files.push((part.name, file));
+    }
+}
+
+/// What the header fields of a part say about it.
+struct PartHead<'a> {
+    name: String,
+    file_name: Option<String>,
+    content_type: Option<&'a str>,
+}
+
+impl PartHead<'_> {
+    fn parse(head: &str) -> Result<PartHead<'_>, FormError> {
+        let mut disposition = None;
+        let mut content_type = None;
+        for line in head.split("\r\n").filter(|line| !line.is_empty()) {
+            let (name, value) = line
+                .split_once(':')
+                .ok_or(FormError::Malformed("malformed part header"))?;
+            if name.eq_ignore_ascii_case("Content-Disposition") {
+                disposition = Some(value.trim());
+            } else if name.eq_ignore_ascii_case("Content-Type") {
+                content_type = Some(value.trim());
+            }
+        }
+
+        let disposition =
+            disposition.ok_or(FormError::Malformed("part without Content-Disposition"))?;
+        let mut params = disposition.split(';');
+        if !params
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .eq_ignore_ascii_case("form-data")
+        {
+            return Err(FormError::Malformed("part is not form-data"));
+        }
+        let mut name = None;
+        let mut file_name = None;
+        for param in params {
+            let Some((key, value)) = param.split_once('=') else {
+                continue;
+            };
+            match key.trim().to_ascii_lowercase().as_str() {
+                "name" => name = Some(unquote(value.trim())),
+                "filename" => file_name = Some(unquote(value.trim())),
+                _ => {}
+            }
+        }
+        Ok(PartHead {
+            name: name.ok_or(FormError::Malformed("part without a name"))?,
+            file_name,
+            content_type,
+        })
+    }
+}
+
+impl UploadedFile {
+    /// Adds `chunk` to the contents, moving them to a temporary file, kept
+    /// open in `disk`, once they outgrow the memory threshold.
+    fn append(
+        &mut self,
+        disk: &mut Option<File>,
+        chunk: &[u8],
+        limits: &FormLimits,
+    ) -> Result<(), FormError> {
+        self.size += chunk.len() as u64;
+        if let Some(file) = disk {
+            return Ok(file.write_all(chunk)?);
+        }
+        if let Data::Memory(bytes) = &mut self.data {
+            bytes.extend_from_slice(chunk);
+            if bytes.len() > limits.memory_threshold {
+                let path = limits.upload_dir.join(format!("upload-{}", Uuid::new_v4()));
+                let mut file = OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&path)?;
+                // From here on, dropping the upload removes the file.
+                let Data::Memory(bytes) = mem::replace(&mut self.data, Data::Disk(path)) else {
+                    unreachable!("the contents were in memory");
+                };
+                file.write_all(&bytes)?;
+                *disk = Some(file);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads a body through a buffer that only grows by what one search needs.
+struct Scanner<R> {
+    reader: io::Take<R>,
+    buf: Vec<u8>,
+}
+
+/// How much is read from the body at a time.
+const CHUNK: usize = 8 * 1024;
+
+impl<R: Read> Scanner<R> {
+    /// Reads `reader`, failing with [`FormError::TooLarge`] past `max_size`
+    /// bytes.
+    fn new(reader: R, max_size: u64) -> Scanner<R> {
+        Scanner {
+            reader: reader.take(max_size.saturating_add(1)),
+            buf: Vec::new(),
+        }
+    }
+
+    /// Appends more of the body to the buffer. Returns `false` at its end.
+    fn fill(&mut self) -> Result<bool, FormError> {
+        let mut chunk = [0; CHUNK];
+        let n = loop {
+            match self.reader.read(&mut chunk) {
+                Ok(n) => break n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(FormError::Body(e)),
+            }
+        };
+        if n == 0 && self.reader.limit() == 0 {
+            return Err(FormError::TooLarge);
+        }
+        self.buf.extend_from_slice(&chunk[..n]);
+        Ok(n > 0)
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.buf.drain(..amount);
+    }
+
+    /// Returns `true` if the rest of the body starts with `prefix`.
+    fn starts_with(&mut self, prefix: &[u8]) -> Result<bool, FormError> {
+        while self.buf.len() < prefix.len() && self.fill()? {}
+        Ok(self.buf.starts_with(prefix))
+    }
+
+    /// Reads up to the next CRLF, which is consumed but not returned. Lines
+    /// longer than `limit` are malformed, as are unterminated ones.
+    fn read_line(
+        &mut self,
+        limit: usize,
+        unterminated: &'static str,
+    ) -> Result<Vec<u8>, FormError> {
+        let mut searched = 0;
+        loop {
+            if let Some(end) = find(&self.buf[searched..], b"\r\n").map(|i| searched + i) {
+                let line = self.buf[..end].to_vec();
+                self.consume(end + 2);
+                return Ok(line);
+            }
+            if self.buf.len() > limit {
+                return Err(FormError::Malformed("line too long"));
+            }
+            searched = self.buf.len().saturating_sub(1);
+            if !self.fill()? {
+                return Err(FormError::Malformed(unterminated));
+            }
+        }
+    }
+
+    /// Passes everything up to `delimiter` to `out` and consumes the
+    /// delimiter; a body without one is malformed.
+    fn read_until(
+        &mut self,
+        delimiter: &[u8],
+        missing: &'static str,
+        mut out: impl FnMut(&[u8]) -> Result<(), FormError>,
+    ) -> Result<(), FormError> {
+        loop {
+            if let Some(start) = find(&self.buf, delimiter) {
+                out(&self.buf[..start])?;
+                self.consume(start + delimiter.len());
+                return Ok(());
+            }
+            // Keep what may be the start of a delimiter cut off by the read.
+            let safe = self.buf.len().saturating_sub(delimiter.len() - 1);
+            out(&self.buf[..safe])?;
+            self.consume(safe);
+            if !self.fill()? {
+                return Err(FormError::Malformed(missing));
+            }
+        }
+    }
+}
+
+/// Strips the quotes and backslash escapes of a quoted-string.
+fn unquote(value: &str) -> String {
+    let Some(inner) = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+    else {
+        return value.to_owned();
+    };
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.extend(chars.next()),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::ParseLimits;
+    use std::io::Cursor;
+
+    fn post(content_type: &str, body: &[u8]) -> Request {
+        let mut raw = format!(
+            "POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Type: {content_type}\r\n\
+             Content-Length: {}\r\n\r\n",
+            body.len()
+        )
+        .into_bytes();
+        raw.extend_from_slice(body);
+        let mut reader = Cursor::new(raw);
+        let mut request = Request::parse(&mut reader, &ParseLimits::default()).unwrap();
+        request.read_body(&mut reader, usize::MAX).unwrap();
+        request
+    }
+
+    #[test]
+    fn parses_urlencoded_fields() {
+        let mut request = post(
+            "application/x-www-form-urlencoded",
+            b"name=Ada+Lovelace&tag=a&tag=b&age=36",
+        );
+        let form = Form::parse(&mut request, &FormLimits::default()).unwrap();
+        assert_eq!(form.field("name"), Some("Ada Lovelace"));
+        assert_eq!(form.all("tag").collect::<Vec<_>>(), ["a", "b"]);
+        assert_eq!(form.get::<u8>("age").unwrap(), 36);
+        assert!(matches!(
+            form.get::<u8>("name"),
+            Err(FormError::InvalidField(_))
+        ));
+        assert!(matches!(
+            form.get::<u8>("height"),
+            Err(FormError::MissingField(_))
+        ));
+
+        let limits = FormLimits {
+            max_parts: 2,
+            ..FormLimits::default()
+        };
+        assert!(matches!(
+            Form::parse(&mut request, &limits),
+            Err(FormError::TooManyParts)
+        ));
+    }
+
+    #[test]
+    fn parses_multipart_fields_and_files() {
+        let body = b"preamble\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+            Q3 report\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"small\"; filename=\"C:\\\\notes.txt\"\r\n\
+            Content-Type: text/plain\r\n\r\n\
+            hi\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"large\"; filename=\"data.bin\"\r\n\r\n\
+            0123456789\r\n--XyZ--\r\n";
+        let mut request = post("multipart/form-data; boundary=\"XyZ\"", body);
+        let limits = FormLimits {
+            memory_threshold: 4,
+            ..FormLimits::default()
+        };
+        let form = Form::parse(&mut request, &limits).unwrap();
+
+        assert_eq!(
+            form.fields(),
+            [("title".to_owned(), "Q3 report".to_owned())]
+        );
+        let small = form.file("small").unwrap();
+        assert_eq!(small.file_name(), Some("notes.txt"));
+        assert_eq!(small.content_type(), "text/plain");
+        assert_eq!(small.bytes(), Some(&b"hi"[..]));
+
+        let large = form.file("large").unwrap();
+        assert_eq!(large.content_type(), "application/octet-stream");
+        assert_eq!(large.len(), 10);
+        let path = large.path().unwrap().to_owned();
+        assert_eq!(fs::read(&path).unwrap(), b"0123456789");
+        drop(form);
+        assert!(!path.exists());
+
+        let mut truncated = post("multipart/form-data; boundary=XyZ", &body[..60]);
+        assert!(matches!(
+            Form::parse(&mut truncated, &limits),
+            Err(FormError::Malformed(_))
+        ));
+        let mut json = post("application/json", b"{}");
+        assert!(matches!(
+            Form::parse(&mut json, &limits),
+            Err(FormError::UnsupportedMediaType)
+        ));
+    }
+
+    /// Hands out one byte per read, so every delimiter is cut somewhere.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.0.len().min(buf.len()).min(1);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn parses_multipart_bodies_as_they_arrive() {
+        let upload = "line\r\n--Xy not yet\r\n".repeat(1000);
+        let body = format!(
+            "--XyZ\r\nContent-Disposition: form-data; name=\"note\"\r\n\r\nhi\r\n\
+             --XyZ  \r\nContent-Disposition: form-data; name=\"log\"; filename=\"a.log\"\r\n\r\n\
+             {upload}\r\n--XyZ--"
+        );
+        let limits = FormLimits {
+            memory_threshold: 100,
+            ..FormLimits::default()
+        };
+        let form = parse_multipart(Trickle(body.as_bytes()), "XyZ", &limits).unwrap();
+
+        assert_eq!(form.field("note"), Some("hi"));
+        let log = form.file("log").unwrap();
+        assert_eq!(log.len(), upload.len() as u64);
+        assert_eq!(fs::read(log.path().unwrap()).unwrap(), upload.as_bytes());
+
+        let limits = FormLimits {
+            max_size: body.len() as u64 - 1,
+            ..limits
+        };
+        assert!(matches!(
+            parse_multipart(Trickle(body.as_bytes()), "XyZ", &limits),
+            Err(FormError::TooLarge)
+        ));
+    }
+}
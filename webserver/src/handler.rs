@@ -0,0 +1,73 @@
+//! The `Handler` trait that turns requests into responses.
+
+use std::sync::Arc;
+
+use crate::{request::Request, response::Response};
+
+/// Something that can answer a request.
+///
+/// Handlers are shared between the pool's worker threads, hence `Send + Sync`.
+/// Any `Fn(&mut Request) -> Response` closure is a handler.
+pub trait Handler: Send + Sync {
+    fn handle(&self, request: &mut Request) -> Response;
+
+    /// Returns `true` if the handler reads the body of `request` itself,
+    /// through [`Request::body_reader`] as it arrives, rather than from the
+    /// body the connection otherwise reads in full before calling it.
+    ///
+    /// Streamed bodies are not limited by
+    /// [`ConnectionConfig::max_body_size`](crate::connection::ConnectionConfig::max_body_size);
+    /// the handler enforces a limit of its own. The connection is closed
+    /// after the response if part of the body was left unread. Defaults to
+    /// `false`; see [`StreamingBody`] for closures.
+    fn streams_body(&self, _request: &Request) -> bool {
+        false
+    }
+}
+
+impl<F> Handler for F
+where
+    F: Fn(&mut Request) -> Response + Send + Sync,
+{
+    fn handle(&self, request: &mut Request) -> Response {
+        self(request)
+    }
+}
+
+impl<H: Handler + ?Sized> Handler for Arc<H> {
+    fn handle(&self, request: &mut Request) -> Response {
+        (**self).handle(request)
+    }
+
+    fn streams_body(&self, request: &Request) -> bool {
+        (**self).streams_body(request)
+    }
+}
+
+/// Runs a handler, typically a closure, with the request body left on the
+/// connection; see [`Handler::streams_body`].
+///
+/// # Examples
+///
+/// ```
+/// use std::io::{self, Read};
+///
+/// use webserver::{Method, Request, Response, Router, StatusCode, handler::StreamingBody};
+///
+/// let count = |request: &mut Request| match io::copy(&mut request.body_reader(), &mut io::sink()) {
+///     Ok(len) => Response::text(StatusCode::OK, format!("{len} bytes")),
+///     Err(_) => Response::error(StatusCode::BAD_REQUEST),
+/// };
+/// let router = Router::new().route(Method::Post, "/count", StreamingBody(count));
+/// ```
+pub struct StreamingBody<H>(pub H);
+
+impl<H: Handler> Handler for StreamingBody<H> {
+    fn handle(&self, request: &mut Request) -> Response {
+        self.0.handle(request)
+    }
+
+    fn streams_body(&self, _request: &Request) -> bool {
+        true
+    }
+}
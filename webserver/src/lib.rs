@@ -1,10 +1,73 @@
+// `ServerConfig::to_json` is one large `json!` literal.
+#![recursion_limit = "256"]
+
+pub mod acceptor;
+pub mod access_log;
+pub mod admin;
+mod autoindex;
+pub mod body;
+pub mod cgi;
+pub mod chunked;
+mod conditional;
+pub mod config;
+pub mod connection;
+pub mod cookie;
+mod date;
+pub mod encoding;
+pub mod fastcgi;
+pub mod form;
+pub mod handler;
+pub mod headers;
+pub mod log;
+pub mod metrics;
+pub mod middleware;
+pub mod mime;
+pub mod proxy;
+pub mod request;
+pub mod response;
+pub mod router;
+pub mod server;
+pub mod session;
+pub mod sse;
+pub mod static_files;
+pub mod status;
+pub mod stream;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod url;
+pub mod virtual_hosts;
+pub mod websocket;
 mod worker;
 
-use std::sync::{Arc, Mutex, mpsc};
+use std::{
+    sync::{Arc, Mutex, atomic::Ordering, mpsc},
+    thread,
+    time::{Duration, Instant},
+};
 
 use uuid::Uuid;
 
-use crate::worker::{Job, Worker};
+use crate::{
+    log::debug,
+    worker::{Job, PoolStats, Worker},
+};
+
+pub use access_log::{AccessLog, LogFormat};
+pub use body::Body;
+pub use cgi::Cgi;
+pub use config::{ConfigError, ServerConfig};
+pub use fastcgi::FastCgi;
+pub use handler::Handler;
+pub use headers::Headers;
+pub use metrics::Metrics;
+pub use proxy::Proxy;
+pub use request::{BodyFraming, Method, ParseError, ParseLimits, Request, Version};
+pub use response::Response;
+pub use router::Router;
+pub use server::{DEFAULT_MAX_ADMIN_CONNECTIONS, Listener, Server, ShutdownHandle, ShutdownReport};
+pub use static_files::StaticFiles;
+pub use status::StatusCode;
+pub use virtual_hosts::VirtualHosts;
 
 #[derive(Debug)]
 pub enum PoolCreationError {
@@ -15,6 +78,7 @@ pub enum PoolCreationError {
 pub struct ThreadPool {
     workers: Vec<Worker>,
     sender: Option<mpsc::Sender<Job>>,
+    stats: Arc<PoolStats>,
 }
 
 impl ThreadPool {
@@ -29,11 +93,12 @@ impl ThreadPool {
 
         let (sender, receiver) = mpsc::channel();
         let receiver = Arc::new(Mutex::new(receiver));
+        let stats = Arc::new(PoolStats::new(size));
 
         let mut workers = Vec::with_capacity(size);
         for id in 0..size {
             // create some workers
-            if let Ok(worker) = Worker::new(id, Arc::clone(&receiver)) {
+            if let Ok(worker) = Worker::new(id, Arc::clone(&receiver), Arc::clone(&stats)) {
                 workers.push(worker);
             } else {
                 return Err(PoolCreationError::WorkerSpawnFailed);
@@ -43,6 +108,7 @@ impl ThreadPool {
         Ok(ThreadPool {
             workers,
             sender: Some(sender),
+            stats,
         })
     }
 
@@ -55,19 +121,54 @@ impl ThreadPool {
             closure: Box::new(f),
         };
 
+        self.stats.queued.fetch_add(1, Ordering::Relaxed);
         self.sender
             .as_ref()
             .unwrap()
             .send(job)
             .expect("Failed to send the job to worker");
     }
+
+    /// The counters workers update, for [`Metrics`](metrics::Metrics).
+    pub(crate) fn stats(&self) -> Arc<PoolStats> {
+        Arc::clone(&self.stats)
+    }
+
+    /// Stops taking jobs and waits up to `timeout` for the workers to finish
+    /// the ones they have.
+    ///
+    /// Returns how many workers were still busy; those are detached rather
+    /// than joined.
+    pub fn shutdown(mut self, timeout: Duration) -> usize {
+        drop(self.sender.take());
+        let deadline = Instant::now() + timeout;
+
+        while self
+            .workers
+            .iter()
+            .any(|worker| !worker.thread.is_finished())
+            && Instant::now() < deadline
+        {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let (finished, stuck): (Vec<Worker>, Vec<Worker>) = self
+            .workers
+            .drain(..)
+            .partition(|worker| worker.thread.is_finished());
+        for worker in finished {
+            debug!("Shutting down worker {}", worker.id);
+            worker.thread.join().unwrap();
+        }
+        stuck.len()
+    }
 }
 
 impl Drop for ThreadPool {
     fn drop(&mut self) {
         drop(self.sender.take());
         for worker in &mut self.workers.drain(..) {
-            println!("Shutting down worker {}", worker.id);
+            debug!("Shutting down worker {}", worker.id);
 
             worker.thread.join().unwrap();
         }
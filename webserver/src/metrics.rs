@@ -0,0 +1,349 @@
+//! Request, connection and worker pool metrics in the Prometheus text
+//! exposition format.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    sync::{
+        Arc, Mutex, MutexGuard, OnceLock, PoisonError,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    handler::Handler,
+    middleware::Middleware,
+    request::{Method, Request},
+    response::Response,
+    status::StatusCode,
+    worker::PoolStats,
+};
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+const BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+/// The `route` label of requests no route matched.
+const UNMATCHED: &str = "unmatched";
+
+/// Counters and gauges for one [`Server`](crate::Server).
+///
+/// The server counts connections and reads the worker pool; requests are
+/// recorded by the [`layer`](Metrics::layer) wrapped around the handler,
+/// which also answers the scrape endpoint. [`Server::bind`](crate::Server::bind)
+/// sets all of this up when `metrics` is enabled.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    requests: Mutex<BTreeMap<(&'static str, String, u16), u64>>,
+    latencies: Mutex<BTreeMap<String, Histogram>>,
+    connections_active: AtomicUsize,
+    connections_total: AtomicU64,
+    connections_rejected: AtomicU64,
+    pool: OnceLock<Arc<PoolStats>>,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Observations per bucket, not cumulative; the last one is `+Inf`.
+    counts: [u64; BUCKETS.len() + 1],
+    sum: f64,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    /// A middleware recording every request, and answering `GET path` with
+    /// the metrics. Add it as the outermost layer so that responses from
+    /// other layers are counted too.
+    pub fn layer(self: &Arc<Self>, path: impl Into<String>) -> MetricsLayer {
+        MetricsLayer {
+            metrics: Arc::clone(self),
+            path: path.into(),
+        }
+    }
+
+    /// Counts a response to a request for `route`, handled in `elapsed`.
+    pub fn record(&self, method: Method, route: &str, status: StatusCode, elapsed: Duration) {
+        *lock(&self.requests)
+            .entry((method.as_str(), route.to_owned(), status.as_u16()))
+            .or_default() += 1;
+
+        let seconds = elapsed.as_secs_f64();
+        let mut latencies = lock(&self.latencies);
+        let histogram = latencies.entry(route.to_owned()).or_default();
+        let bucket = BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(BUCKETS.len());
+        histogram.counts[bucket] += 1;
+        histogram.sum += seconds;
+    }
+
+    /// Counts an open connection until the returned guard is dropped.
+    pub(crate) fn connection(&self) -> ConnectionGuard<'_> {
+        self.connections_total.fetch_add(1, Ordering::Relaxed);
+        self.connections_active.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard { metrics: self }
+    }
+
+    /// Counts a connection refused because too many were open.
+    pub(crate) fn connection_rejected(&self) {
+        self.connections_rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Reports on `pool` from now on. Only the first pool attached counts.
+    pub(crate) fn attach_pool(&self, pool: Arc<PoolStats>) {
+        let _ = self.pool.set(pool);
+    }
+
+    /// Renders everything in the text exposition format, version 0.0.4.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "webserver_http_requests_total",
+            "counter",
+            "Responses sent, by method, route and status.",
+        );
+        for ((method, route, status), count) in lock(&self.requests).iter() {
+            let _ = writeln!(
+                out,
+                "webserver_http_requests_total{{method=\"{method}\",route=\"{}\",status=\"{status}\"}} {count}",
+                escape(route)
+            );
+        }
+
+        header(
+            &mut out,
+            "webserver_http_request_duration_seconds",
+            "histogram",
+            "Time the handler took to produce a response, by route.",
+        );
+        for (route, histogram) in lock(&self.latencies).iter() {
+            let route = escape(route);
+            let mut cumulative = 0;
+            for (bound, count) in BUCKETS.iter().zip(&histogram.counts) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "webserver_http_request_duration_seconds_bucket{{route=\"{route}\",le=\"{bound}\"}} {cumulative}"
+                );
+            }
+            cumulative += histogram.counts[BUCKETS.len()];
+            let _ = writeln!(
+                out,
+                "webserver_http_request_duration_seconds_bucket{{route=\"{route}\",le=\"+Inf\"}} {cumulative}\n\
+                 webserver_http_request_duration_seconds_sum{{route=\"{route}\"}} {}\n\
+                 webserver_http_request_duration_seconds_count{{route=\"{route}\"}} {cumulative}",
+                histogram.sum
+            );
+        }
+
+        let mut single = |name: &str, kind: &str, help: &str, value: u64| {
+            header(&mut out, name, kind, help);
+            let _ = writeln!(out, "{name} {value}");
+        };
+        single(
+            "webserver_connections_active",
+            "gauge",
+            "Connections currently open.",
+            self.connections_active.load(Ordering::Relaxed) as u64,
+        );
+        single(
+            "webserver_connections_total",
+            "counter",
+            "Connections accepted.",
+            self.connections_total.load(Ordering::Relaxed),
+        );
+        single(
+            "webserver_connections_rejected_total",
+            "counter",
+            "Connections refused because max_connections were open.",
+            self.connections_rejected.load(Ordering::Relaxed),
+        );
+
+        if let Some(pool) = self.pool.get() {
+            let workers = pool.workers.load(Ordering::Relaxed);
+            let busy = pool.busy.load(Ordering::Relaxed).min(workers);
+            single(
+                "webserver_pool_workers",
+                "gauge",
+                "Threads in the worker pool.",
+                workers as u64,
+            );
+            single(
+                "webserver_pool_workers_busy",
+                "gauge",
+                "Workers running a job.",
+                busy as u64,
+            );
+            single(
+                "webserver_pool_workers_idle",
+                "gauge",
+                "Workers waiting for a job.",
+                (workers - busy) as u64,
+            );
+            single(
+                "webserver_pool_queue_depth",
+                "gauge",
+                "Jobs waiting in the pool's channel for a free worker.",
+                pool.queued.load(Ordering::Relaxed) as u64,
+            );
+            single(
+                "webserver_pool_jobs_completed_total",
+                "counter",
+                "Jobs that ran to completion.",
+                pool.completed.load(Ordering::Relaxed),
+            );
+            single(
+                "webserver_pool_jobs_panicked_total",
+                "counter",
+                "Jobs that panicked; their worker carried on.",
+                pool.panicked.load(Ordering::Relaxed),
+            );
+        }
+
+        out
+    }
+}
+
+/// Keeps a connection counted as active; see [`Metrics::connection`].
+pub(crate) struct ConnectionGuard<'a> {
+    metrics: &'a Metrics,
+}
+
+impl Drop for ConnectionGuard<'_> {
+    fn drop(&mut self) {
+        self.metrics
+            .connections_active
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Records requests into [`Metrics`] and serves them to scrapers; see
+/// [`Metrics::layer`].
+#[derive(Debug, Clone)]
+pub struct MetricsLayer {
+    metrics: Arc<Metrics>,
+    path: String,
+}
+
+impl Middleware for MetricsLayer {
+    fn handle(&self, request: &mut Request, next: &dyn Handler) -> Response {
+        let start = Instant::now();
+        let response = if request.path() == self.path
+            && matches!(request.method(), Method::Get | Method::Head)
+        {
+            request.route = Some(self.path.clone());
+            Response::new(StatusCode::OK)
+                .with_header("Content-Type", "text/plain; version=0.0.4; charset=utf-8")
+                .with_body(self.metrics.render())
+        } else {
+            next.handle(request)
+        };
+
+        self.metrics.record(
+            request.method(),
+            request.route().unwrap_or(UNMATCHED),
+            response.status(),
+            start.elapsed(),
+        );
+        response
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} {kind}");
+}
+
+/// Escapes a label value: backslash, double quote and line feed.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ThreadPool, request::ParseLimits, router::Router};
+    use std::io::Cursor;
+
+    fn request(path: &str) -> Request {
+        let raw = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        Request::parse(&mut Cursor::new(raw.as_bytes()), &ParseLimits::default()).unwrap()
+    }
+
+    #[test]
+    fn records_requests_by_route_and_status() {
+        let metrics = Arc::new(Metrics::new());
+        let layer = metrics.layer("/metrics");
+        let router = Router::new().get("/users/:id", |_: &mut Request| {
+            Response::text(StatusCode::OK, "user")
+        });
+
+        layer.handle(&mut request("/users/1"), &router);
+        layer.handle(&mut request("/users/2"), &router);
+        layer.handle(&mut request("/nowhere"), &router);
+        let _connection = metrics.connection();
+
+        let response = layer.handle(&mut request("/metrics"), &router);
+        let text = String::from_utf8(response.into_body().into_bytes().unwrap()).unwrap();
+        assert!(text.contains(
+            "webserver_http_requests_total{method=\"GET\",route=\"/users/:id\",status=\"200\"} 2\n"
+        ));
+        assert!(text.contains(
+            "webserver_http_requests_total{method=\"GET\",route=\"unmatched\",status=\"404\"} 1\n"
+        ));
+        assert!(text.contains(
+            "webserver_http_request_duration_seconds_bucket{route=\"/users/:id\",le=\"+Inf\"} 2\n"
+        ));
+        assert!(
+            text.contains(
+                "webserver_http_request_duration_seconds_count{route=\"/users/:id\"} 2\n"
+            )
+        );
+        assert!(text.contains("webserver_connections_active 1\n"));
+        assert!(text.contains("# TYPE webserver_http_request_duration_seconds histogram\n"));
+
+        // The scrape itself shows up in the next one.
+        assert!(
+            metrics
+                .render()
+                .contains("route=\"/metrics\",status=\"200\"} 1\n")
+        );
+    }
+
+    #[test]
+    fn reports_the_worker_pool() {
+        let metrics = Metrics::new();
+        let pool = ThreadPool::build(2).unwrap();
+        metrics.attach_pool(pool.stats());
+
+        pool.execute(uuid::Uuid::new_v4(), || panic!("job failed"));
+        pool.execute(uuid::Uuid::new_v4(), || {});
+        pool.shutdown(Duration::from_secs(5));
+
+        let text = metrics.render();
+        assert!(text.contains("webserver_pool_workers 2\n"));
+        assert!(text.contains("webserver_pool_workers_busy 0\n"));
+        assert!(text.contains("webserver_pool_queue_depth 0\n"));
+        assert!(text.contains("webserver_pool_jobs_panicked_total 1\n"));
+        assert!(text.contains("webserver_pool_jobs_completed_total 1\n"));
+    }
+
+    #[test]
+    fn escapes_label_values() {
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
@@ -0,0 +1,188 @@
+//! Middleware: layers that wrap a [`Handler`] to inspect or change requests
+//! and responses, or answer on their own.
+//!
+//! Layers run in the order they are added to a [`Chain`]: the first one sees
+//! the request first and the response last.
+
+mod basic_auth;
+mod compression;
+mod cors;
+mod error_pages;
+mod rate_limit;
+mod request_id;
+mod sessions;
+mod timing;
+
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{handler::Handler, request::Request, response::Response};
+
+pub use basic_auth::BasicAuth;
+pub use compression::Compression;
+pub use cors::Cors;
+pub use error_pages::ErrorPages;
+pub use rate_limit::RateLimit;
+pub use request_id::RequestId;
+pub use sessions::Sessions;
+pub use timing::Timing;
+
+/// One layer of a [`Chain`].
+///
+/// Call `next.handle(request)` to pass the request on, or return a response
+/// without calling it to short-circuit the rest of the chain. Any
+/// `Fn(&mut Request, &dyn Handler) -> Response` closure is a middleware.
+pub trait Middleware: Send + Sync {
+    fn handle(&self, request: &mut Request, next: &dyn Handler) -> Response;
+}
+
+impl<F> Middleware for F
+where
+    F: Fn(&mut Request, &dyn Handler) -> Response + Send + Sync,
+{
+    fn handle(&self, request: &mut Request, next: &dyn Handler) -> Response {
+        self(request, next)
+    }
+}
+
+/// A handler wrapped in middleware layers.
+///
+/// # Examples
+///
+/// ```
+/// use webserver::{Request, Response, Router, StatusCode};
+/// use webserver::middleware::{Chain, RequestId, Timing};
+///
+/// let router = Router::new().get("/", |_: &mut Request| Response::text(StatusCode::OK, "hi"));
+/// let app = Chain::new(router).with(RequestId::new()).with(Timing);
+/// ```
+pub struct Chain {
+    layers: Vec<Box<dyn Middleware>>,
+    handler: Box<dyn Handler>,
+}
+
+impl Chain {
+    /// Starts a chain that ends in `handler`.
+    pub fn new<H: Handler + 'static>(handler: H) -> Chain {
+        Chain {
+            layers: Vec::new(),
+            handler: Box::new(handler),
+        }
+    }
+
+    /// Adds `layer` inside the layers added so far.
+    pub fn with<M: Middleware + 'static>(mut self, layer: M) -> Chain {
+        self.layers.push(Box::new(layer));
+        self
+    }
+}
+
+impl Handler for Chain {
+    fn handle(&self, request: &mut Request) -> Response {
+        Next {
+            layers: &self.layers,
+            handler: &*self.handler,
+        }
+        .handle(request)
+    }
+
+    fn streams_body(&self, request: &Request) -> bool {
+        self.handler.streams_body(request)
+    }
+}
+
+/// The rest of a chain, as seen by one layer.
+struct Next<'a> {
+    layers: &'a [Box<dyn Middleware>],
+    handler: &'a dyn Handler,
+}
+
+impl Handler for Next<'_> {
+    fn handle(&self, request: &mut Request) -> Response {
+        match self.layers.split_first() {
+            Some((layer, layers)) => layer.handle(
+                request,
+                &Next {
+                    layers,
+                    handler: self.handler,
+                },
+            ),
+            None => self.handler.handle(request),
+        }
+    }
+}
+
+/// The built-in layers, by the name used in the configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Builtin {
+    RequestId,
+    Timing,
+    Cors,
+    BasicAuth,
+    Compression,
+    RateLimit,
+}
+
+impl FromStr for Builtin {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "request_id" => Ok(Builtin::RequestId),
+            "timing" => Ok(Builtin::Timing),
+            "cors" => Ok(Builtin::Cors),
+            "basic_auth" => Ok(Builtin::BasicAuth),
+            "compression" => Ok(Builtin::Compression),
+            "rate_limit" => Ok(Builtin::RateLimit),
+            _ => Err(
+                "expected request_id, timing, cors, basic_auth, compression or rate_limit"
+                    .to_owned(),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{request::ParseLimits, status::StatusCode};
+    use std::io::Cursor;
+
+    fn request() -> Request {
+        let raw = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        Request::parse(&mut Cursor::new(&raw[..]), &ParseLimits::default()).unwrap()
+    }
+
+    fn tag(name: &'static str) -> impl Middleware {
+        move |request: &mut Request, next: &dyn Handler| {
+            request.headers_mut().append("X-Seen", name);
+            let mut response = next.handle(request);
+            response.headers_mut().append("X-Order", name);
+            response
+        }
+    }
+
+    #[test]
+    fn runs_layers_in_order() {
+        let handler = |request: &mut Request| {
+            let seen: Vec<_> = request.headers().get_all("X-Seen").collect();
+            Response::text(StatusCode::OK, seen.join(","))
+        };
+        let chain = Chain::new(handler).with(tag("outer")).with(tag("inner"));
+
+        let response = chain.handle(&mut request());
+        assert_eq!(response.body().bytes(), Some(&b"outer,inner"[..]));
+        let order: Vec<_> = response.headers().get_all("X-Order").collect();
+        assert_eq!(order, ["inner", "outer"]);
+    }
+
+    #[test]
+    fn layers_can_short_circuit() {
+        let chain = Chain::new(|_: &mut Request| -> Response { unreachable!() })
+            .with(|_: &mut Request, _: &dyn Handler| Response::error(StatusCode::FORBIDDEN));
+
+        assert_eq!(chain.handle(&mut request()).status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,146 @@
+use std::{collections::HashMap, fs, io, path::Path};
+
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+
+use crate::{handler::Handler, request::Request, response::Response, status::StatusCode};
+
+use super::Middleware;
+
+/// HTTP Basic authentication (RFC 7617) against a fixed set of users.
+///
+/// Requests without valid credentials are answered with `401 Unauthorized`
+/// and never reach the inner layers. Basic auth sends passwords in the
+/// clear, so use it over HTTPS.
+#[derive(Clone)]
+pub struct BasicAuth {
+    realm: String,
+    users: HashMap<String, String>,
+}
+
+impl BasicAuth {
+    /// Protects `realm`, with no users yet.
+    pub fn new(realm: impl Into<String>) -> BasicAuth {
+        BasicAuth {
+            realm: realm.into(),
+            users: HashMap::new(),
+        }
+    }
+
+    /// Reads users from a file of `name:password` lines. Blank lines and
+    /// lines starting with `#` are skipped.
+    pub fn from_file(realm: impl Into<String>, path: impl AsRef<Path>) -> io::Result<BasicAuth> {
+        let mut auth = BasicAuth::new(realm);
+        for (number, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, password) = line.split_once(':').ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: expected name:password", number + 1),
+                )
+            })?;
+            auth = auth.user(name, password);
+        }
+        Ok(auth)
+    }
+
+    /// Adds a user.
+    pub fn user(mut self, name: impl Into<String>, password: impl Into<String>) -> BasicAuth {
+        self.users.insert(name.into(), password.into());
+        self
+    }
+
+    fn is_authorized(&self, request: &Request) -> bool {
+        let Some((scheme, credentials)) = request
+            .header("Authorization")
+            .and_then(|value| value.trim().split_once(' '))
+        else {
+            return false;
+        };
+        if !scheme.eq_ignore_ascii_case("Basic") {
+            return false;
+        }
+        let Some(decoded) = STANDARD
+            .decode(credentials.trim())
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+        else {
+            return false;
+        };
+        let Some((name, password)) = decoded.split_once(':') else {
+            return false;
+        };
+
+        self.users
+            .get(name)
+            .is_some_and(|expected| constant_time_eq(expected.as_bytes(), password.as_bytes()))
+    }
+}
+
+impl std::fmt::Debug for BasicAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Never print the passwords.
+        f.debug_struct("BasicAuth")
+            .field("realm", &self.realm)
+            .field("users", &self.users.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl Middleware for BasicAuth {
+    fn handle(&self, request: &mut Request, next: &dyn Handler) -> Response {
+        if self.is_authorized(request) {
+            return next.handle(request);
+        }
+        let challenge = format!(
+            "Basic realm=\"{}\", charset=\"UTF-8\"",
+            self.realm.replace(['"', '\\'], "")
+        );
+        Response::error(StatusCode::UNAUTHORIZED).with_header("WWW-Authenticate", challenge)
+    }
+}
+
+/// Compares without returning early, so timing does not reveal how much of
+/// a guessed password was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::ParseLimits;
+    use std::io::Cursor;
+
+    fn request(authorization: &str) -> Request {
+        let raw =
+            format!("GET / HTTP/1.1\r\nHost: localhost\r\nAuthorization: {authorization}\r\n\r\n");
+        Request::parse(&mut Cursor::new(raw.as_bytes()), &ParseLimits::default()).unwrap()
+    }
+
+    #[test]
+    fn checks_credentials() {
+        let auth = BasicAuth::new("admin").user("alice", "s3cret");
+        let ok = |_: &mut Request| Response::text(StatusCode::OK, "welcome");
+
+        let good = format!("Basic {}", STANDARD.encode("alice:s3cret"));
+        assert_eq!(
+            auth.handle(&mut request(&good), &ok).status(),
+            StatusCode::OK
+        );
+
+        let bad = format!("basic {}", STANDARD.encode("alice:guess"));
+        let response = auth.handle(&mut request(&bad), &ok);
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response.headers().get("WWW-Authenticate"),
+            Some("Basic realm=\"admin\", charset=\"UTF-8\"")
+        );
+        assert_eq!(
+            auth.handle(&mut request("Bearer token"), &ok).status(),
+            StatusCode::UNAUTHORIZED
+        );
+    }
+}
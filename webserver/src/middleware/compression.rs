@@ -0,0 +1,203 @@
+use crate::{
+    body::Body,
+    encoding::{self, Encoding},
+    handler::Handler,
+    request::Request,
+    response::Response,
+};
+
+use super::Middleware;
+
+/// Compresses response bodies with the best coding the client accepts.
+///
+/// In-memory bodies are compressed up front and keep a `Content-Length`;
+/// files and streams are compressed while they are sent, which makes them
+/// chunked. Bodies smaller than [`min_size`](Compression::min_size), media
+/// that is compressed already (images, audio, video, archives, fonts), event
+/// streams and responses that carry a `Content-Encoding` or `Content-Range`
+/// are left alone.
+#[derive(Debug, Clone)]
+pub struct Compression {
+    encodings: Vec<Encoding>,
+    min_size: u64,
+}
+
+impl Compression {
+    /// Offers brotli, gzip and deflate for bodies of 1 KiB and more.
+    pub fn new() -> Compression {
+        Compression {
+            encodings: Encoding::ALL.to_vec(),
+            min_size: 1024,
+        }
+    }
+
+    /// Replaces the codings offered, most preferred first.
+    pub fn encodings(mut self, encodings: impl IntoIterator<Item = Encoding>) -> Compression {
+        self.encodings = encodings.into_iter().collect();
+        self
+    }
+
+    /// Bodies shorter than `bytes` are sent uncompressed; the framing would
+    /// eat the savings.
+    pub fn min_size(mut self, bytes: u64) -> Compression {
+        self.min_size = bytes;
+        self
+    }
+
+    fn should_compress(&self, response: &Response) -> bool {
+        let status = response.status().as_u16();
+        let headers = response.headers();
+        (200..300).contains(&status)
+            && status != 204
+            && !headers.contains("Content-Encoding")
+            && !headers.contains("Content-Range")
+            && headers.get("Content-Type").is_some_and(is_compressible)
+            && response.body().len().is_none_or(|len| len >= self.min_size)
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::new()
+    }
+}
+
+impl Middleware for Compression {
+    fn handle(&self, request: &mut Request, next: &dyn Handler) -> Response {
+        let accept_encoding = request.header("Accept-Encoding").map(str::to_owned);
+        let mut response = next.handle(request);
+        if !self.should_compress(&response) {
+            return response;
+        }
+        // Caches must keep a copy per coding even for clients getting identity.
+        response
+            .headers_mut()
+            .append_token("Vary", "Accept-Encoding");
+
+        let Some(encoding) =
+            accept_encoding.and_then(|accept| encoding::negotiate(&accept, &self.encodings))
+        else {
+            return response;
+        };
+
+        let body = match response.replace_body(Body::Empty) {
+            Body::Bytes(bytes) => match encoding.encode(&bytes) {
+                Ok(encoded) if encoded.len() < bytes.len() => Body::Bytes(encoded),
+                _ => {
+                    response.replace_body(bytes);
+                    return response;
+                }
+            },
+            body => Body::Stream(encoding.encoder(body.into_reader())),
+        };
+        response.replace_body(body);
+
+        let headers = response.headers_mut();
+        headers.insert("Content-Encoding", encoding.as_str());
+        // Offsets into the file do not apply to the compressed bytes.
+        headers.remove("Accept-Ranges");
+        // The compressed bytes are a different representation.
+        if let Some(etag) = headers.get("ETag").and_then(|etag| etag.strip_suffix('"')) {
+            let etag = format!("{etag}-{}\"", encoding.as_str());
+            headers.insert("ETag", etag);
+        }
+        response
+    }
+}
+
+/// Returns `false` for media types that are compressed already, and for
+/// event streams, whose events an encoder would hold back.
+fn is_compressible(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    let (kind, subtype) = essence.split_once('/').unwrap_or((&essence, ""));
+
+    match kind {
+        "text" => subtype != "event-stream",
+        "image" => matches!(subtype, "svg+xml" | "x-icon" | "bmp"),
+        "audio" | "video" | "font" => false,
+        "application" => !matches!(
+            subtype,
+            "zip"
+                | "gzip"
+                | "x-gzip"
+                | "x-bzip2"
+                | "x-xz"
+                | "x-7z-compressed"
+                | "zstd"
+                | "x-rar-compressed"
+                | "pdf"
+                | "octet-stream"
+        ),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{request::ParseLimits, status::StatusCode};
+    use std::io::Cursor;
+
+    fn request(accept_encoding: &str) -> Request {
+        let raw = format!(
+            "GET / HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: {accept_encoding}\r\n\r\n"
+        );
+        Request::parse(&mut Cursor::new(raw.as_bytes()), &ParseLimits::default()).unwrap()
+    }
+
+    #[test]
+    fn compresses_text_for_willing_clients() {
+        let text = "<p>compress me</p>".repeat(100);
+        let page = |_: &mut Request| Response::html(StatusCode::OK, text.clone());
+        let compression = Compression::new();
+
+        let response = compression.handle(&mut request("gzip;q=0.5, br;q=0.9"), &page);
+        assert_eq!(response.headers().get("Content-Encoding"), Some("br"));
+        assert_eq!(response.headers().get("Vary"), Some("Accept-Encoding"));
+        let body = response.into_body().into_bytes().unwrap();
+        assert_eq!(Encoding::Brotli.decode(&body).unwrap(), text.as_bytes());
+
+        let response = compression.handle(&mut request("identity"), &page);
+        assert!(!response.headers().contains("Content-Encoding"));
+        assert_eq!(response.headers().get("Vary"), Some("Accept-Encoding"));
+
+        // Handlers negotiating codings themselves have listed it already.
+        let varied = |_: &mut Request| {
+            Response::html(StatusCode::OK, text.clone())
+                .with_header("Vary", "Origin, accept-encoding")
+        };
+        let response = compression.handle(&mut request("identity"), &varied);
+        let vary: Vec<_> = response.headers().get_all("Vary").collect();
+        assert_eq!(vary, ["Origin, accept-encoding"]);
+    }
+
+    #[test]
+    fn skips_tiny_and_compressed_bodies() {
+        let compression = Compression::new();
+        let tiny = |_: &mut Request| Response::text(StatusCode::OK, "tiny");
+        let response = compression.handle(&mut request("gzip"), &tiny);
+        assert!(!response.headers().contains("Content-Encoding"));
+
+        let png = |_: &mut Request| {
+            Response::new(StatusCode::OK)
+                .with_header("Content-Type", "image/png")
+                .with_body(vec![0; 4096])
+        };
+        let response = compression.handle(&mut request("gzip"), &png);
+        assert!(!response.headers().contains("Content-Encoding"));
+        assert!(!response.headers().contains("Vary"));
+
+        let events = |_: &mut Request| {
+            Response::new(StatusCode::OK)
+                .with_header("Content-Type", "text/event-stream")
+                .with_body(Body::chunks(std::iter::empty()))
+        };
+        let response = compression.handle(&mut request("gzip"), &events);
+        assert!(!response.headers().contains("Content-Encoding"));
+    }
+}
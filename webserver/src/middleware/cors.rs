@@ -0,0 +1,223 @@
+use std::time::Duration;
+
+use crate::{
+    handler::Handler,
+    request::{Method, Request},
+    response::Response,
+    status::StatusCode,
+};
+
+use super::Middleware;
+
+/// Adds Cross-Origin Resource Sharing headers and answers preflight
+/// requests.
+///
+/// Requests without an `Origin` header, or from origins that are not
+/// allowed, pass through untouched; the browser then blocks the response.
+#[derive(Debug, Clone)]
+pub struct Cors {
+    any_origin: bool,
+    origins: Vec<String>,
+    methods: Vec<Method>,
+    headers: Vec<String>,
+    expose_headers: Vec<String>,
+    credentials: bool,
+    max_age: Option<Duration>,
+}
+
+impl Cors {
+    /// Allows no origin yet, and `GET`, `HEAD` and `POST` once one is.
+    pub fn new() -> Cors {
+        Cors {
+            any_origin: false,
+            origins: Vec::new(),
+            methods: vec![Method::Get, Method::Head, Method::Post],
+            headers: Vec::new(),
+            expose_headers: Vec::new(),
+            credentials: false,
+            max_age: None,
+        }
+    }
+
+    /// Allows requests from `origin`, e.g. `https://example.com`. `*`
+    /// allows any origin.
+    pub fn allow_origin(mut self, origin: impl Into<String>) -> Cors {
+        let origin = origin.into();
+        if origin == "*" {
+            self.any_origin = true;
+        } else {
+            self.origins.push(origin);
+        }
+        self
+    }
+
+    /// Replaces the methods allowed in cross-origin requests.
+    pub fn allow_methods(mut self, methods: impl IntoIterator<Item = Method>) -> Cors {
+        self.methods = methods.into_iter().collect();
+        self
+    }
+
+    /// Request headers scripts may send, e.g. `Content-Type`.
+    pub fn allow_headers<S: Into<String>>(mut self, headers: impl IntoIterator<Item = S>) -> Cors {
+        self.headers = headers.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Response headers scripts may read besides the safelisted ones.
+    pub fn expose_headers<S: Into<String>>(mut self, headers: impl IntoIterator<Item = S>) -> Cors {
+        self.expose_headers = headers.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Lets browsers send cookies and credentials along.
+    pub fn allow_credentials(mut self) -> Cors {
+        self.credentials = true;
+        self
+    }
+
+    /// How long browsers may cache a preflight result.
+    pub fn max_age(mut self, max_age: Duration) -> Cors {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    fn allows(&self, origin: &str) -> bool {
+        self.any_origin || self.origins.iter().any(|allowed| allowed == origin)
+    }
+
+    /// Sets the headers shared by preflight and actual responses.
+    fn allow(&self, response: &mut Response, origin: &str) {
+        let headers = response.headers_mut();
+        // Credentials cannot be combined with the `*` wildcard.
+        if self.any_origin && !self.credentials {
+            headers.insert("Access-Control-Allow-Origin", "*");
+        } else {
+            headers.insert("Access-Control-Allow-Origin", origin);
+            headers.append("Vary", "Origin");
+        }
+        if self.credentials {
+            headers.insert("Access-Control-Allow-Credentials", "true");
+        }
+    }
+
+    fn preflight(&self, origin: &str) -> Response {
+        let mut response = Response::new(StatusCode::NO_CONTENT);
+        self.allow(&mut response, origin);
+
+        let methods: Vec<_> = self.methods.iter().map(Method::as_str).collect();
+        let headers = response.headers_mut();
+        headers.insert("Access-Control-Allow-Methods", methods.join(", "));
+        if !self.headers.is_empty() {
+            headers.insert("Access-Control-Allow-Headers", self.headers.join(", "));
+        }
+        if let Some(max_age) = self.max_age {
+            headers.insert("Access-Control-Max-Age", max_age.as_secs().to_string());
+        }
+        response
+    }
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Cors::new()
+    }
+}
+
+impl Middleware for Cors {
+    fn handle(&self, request: &mut Request, next: &dyn Handler) -> Response {
+        let Some(origin) = request
+            .header("Origin")
+            .filter(|origin| self.allows(origin))
+            .map(str::to_owned)
+        else {
+            return next.handle(request);
+        };
+
+        if request.method() == Method::Options
+            && request.headers().contains("Access-Control-Request-Method")
+        {
+            return self.preflight(&origin);
+        }
+
+        let mut response = next.handle(request);
+        self.allow(&mut response, &origin);
+        if !self.expose_headers.is_empty() {
+            response.headers_mut().insert(
+                "Access-Control-Expose-Headers",
+                self.expose_headers.join(", "),
+            );
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::ParseLimits;
+    use std::io::Cursor;
+
+    fn request(raw: &str) -> Request {
+        Request::parse(&mut Cursor::new(raw.as_bytes()), &ParseLimits::default()).unwrap()
+    }
+
+    fn ok(_: &mut Request) -> Response {
+        Response::text(StatusCode::OK, "ok")
+    }
+
+    #[test]
+    fn answers_preflight_requests() {
+        let cors = Cors::new()
+            .allow_origin("https://app.test")
+            .allow_methods([Method::Get, Method::Put])
+            .allow_headers(["Content-Type"])
+            .max_age(Duration::from_secs(600));
+        let mut preflight = request(
+            "OPTIONS /items HTTP/1.1\r\nHost: localhost\r\nOrigin: https://app.test\r\n\
+             Access-Control-Request-Method: PUT\r\n\r\n",
+        );
+
+        let response = cors.handle(&mut preflight, &ok);
+        let headers = response.headers();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            headers.get("Access-Control-Allow-Origin"),
+            Some("https://app.test")
+        );
+        assert_eq!(
+            headers.get("Access-Control-Allow-Methods"),
+            Some("GET, PUT")
+        );
+        assert_eq!(
+            headers.get("Access-Control-Allow-Headers"),
+            Some("Content-Type")
+        );
+        assert_eq!(headers.get("Access-Control-Max-Age"), Some("600"));
+    }
+
+    #[test]
+    fn only_allowed_origins_get_headers() {
+        let cors = Cors::new()
+            .allow_origin("*")
+            .expose_headers(["X-Request-Id"]);
+        let response = cors.handle(
+            &mut request("GET / HTTP/1.1\r\nHost: localhost\r\nOrigin: https://a.test\r\n\r\n"),
+            &ok,
+        );
+        assert_eq!(
+            response.headers().get("Access-Control-Allow-Origin"),
+            Some("*")
+        );
+        assert_eq!(
+            response.headers().get("Access-Control-Expose-Headers"),
+            Some("X-Request-Id")
+        );
+
+        let cors = Cors::new().allow_origin("https://app.test");
+        let response = cors.handle(
+            &mut request("GET / HTTP/1.1\r\nHost: localhost\r\nOrigin: https://evil.test\r\n\r\n"),
+            &ok,
+        );
+        assert!(!response.headers().contains("Access-Control-Allow-Origin"));
+    }
+}
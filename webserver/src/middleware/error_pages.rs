@@ -0,0 +1,115 @@
+use std::{fs, path::PathBuf};
+
+use crate::{handler::Handler, request::Request, response::Response};
+
+use super::Middleware;
+
+/// Replaces the body of error responses with a custom page.
+///
+/// A `4xx` or `5xx` response gets the contents of `<status>.html` from the
+/// pages directory, e.g. `404.html`, if that file exists; the status and the
+/// other headers are kept. This covers errors from proxied upstreams too.
+/// Only empty, plain-text and HTML bodies are replaced, so errors an API
+/// describes in JSON or another format reach its clients as they are.
+#[derive(Debug, Clone)]
+pub struct ErrorPages {
+    dir: PathBuf,
+}
+
+impl ErrorPages {
+    /// Serves pages from `dir`. Pages are read for every error, so they
+    /// can be edited while the server runs.
+    pub fn new(dir: impl Into<PathBuf>) -> ErrorPages {
+        ErrorPages { dir: dir.into() }
+    }
+}
+
+impl Middleware for ErrorPages {
+    fn handle(&self, request: &mut Request, next: &dyn Handler) -> Response {
+        let mut response = next.handle(request);
+        let status = response.status().as_u16();
+        if status < 400 || !for_people(&response) {
+            return response;
+        }
+        let Ok(page) = fs::read(self.dir.join(format!("{status}.html"))) else {
+            return response;
+        };
+
+        let headers = response.headers_mut();
+        headers.insert("Content-Type", "text/html; charset=utf-8");
+        headers.remove("Content-Encoding");
+        // Validators describe the original body. `Content-Range` stays, a
+        // `416` has to name the length of the resource in it.
+        headers.remove("ETag");
+        headers.remove("Last-Modified");
+        response.replace_body(page);
+        response
+    }
+}
+
+/// Returns `true` if the body is empty, plain text or HTML.
+fn for_people(response: &Response) -> bool {
+    let Some(content_type) = response.headers().get("Content-Type") else {
+        return true;
+    };
+    let essence = content_type.split(';').next().unwrap_or("").trim();
+    response.body().is_empty()
+        || essence.eq_ignore_ascii_case("text/plain")
+        || essence.eq_ignore_ascii_case("text/html")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{request::ParseLimits, status::StatusCode};
+    use std::io::Cursor;
+
+    #[test]
+    fn replaces_errors_that_have_a_page() {
+        let dir = std::env::temp_dir().join(format!("error-pages-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("404.html"), "<h1>gone</h1>").unwrap();
+        let pages = ErrorPages::new(&dir);
+        let raw = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let mut request =
+            Request::parse(&mut Cursor::new(&raw[..]), &ParseLimits::default()).unwrap();
+
+        let missing = |_: &mut Request| Response::error(StatusCode::NOT_FOUND);
+        let response = pages.handle(&mut request, &missing);
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.headers().get("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+        assert_eq!(response.body().bytes(), Some(&b"<h1>gone</h1>"[..]));
+
+        let described = |_: &mut Request| {
+            Response::new(StatusCode::NOT_FOUND)
+                .with_header("Content-Type", "application/json")
+                .with_body(r#"{"error":"no such user"}"#)
+        };
+        let response = pages.handle(&mut request, &described);
+        assert_eq!(
+            response.body().bytes(),
+            Some(&br#"{"error":"no such user"}"#[..])
+        );
+
+        fs::write(dir.join("416.html"), "<h1>out of range</h1>").unwrap();
+        let unsatisfiable = |_: &mut Request| {
+            Response::error(StatusCode::RANGE_NOT_SATISFIABLE)
+                .with_header("Content-Range", "bytes */10")
+        };
+        let response = pages.handle(&mut request, &unsatisfiable);
+        assert_eq!(response.headers().get("Content-Range"), Some("bytes */10"));
+        assert_eq!(response.body().bytes(), Some(&b"<h1>out of range</h1>"[..]));
+
+        let broken = |_: &mut Request| Response::error(StatusCode::INTERNAL_SERVER_ERROR);
+        let response = pages.handle(&mut request, &broken);
+        assert_eq!(
+            response.body().bytes(),
+            Some(&b"500 Internal Server Error"[..])
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,146 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use crate::{handler::Handler, request::Request, response::Response, status::StatusCode};
+
+use super::Middleware;
+
+/// Limits how fast each client IP may send requests, with a token bucket
+/// per address.
+///
+/// A client may make `burst` requests at once, then `per_second` on average.
+/// Further requests are answered with `429 Too Many Requests` and a
+/// `Retry-After` header. Behind a proxy every request shares the proxy's
+/// address. Requests that did not come from a socket are not limited.
+#[derive(Debug)]
+pub struct RateLimit {
+    per_second: f64,
+    burst: f64,
+    state: Mutex<Buckets>,
+}
+
+#[derive(Debug)]
+struct Buckets {
+    clients: HashMap<IpAddr, Bucket>,
+    swept: Instant,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimit {
+    /// Allows `per_second` requests per client on average, `burst` at once.
+    ///
+    /// # Panics
+    ///
+    /// If `per_second` is not positive or `burst` is 0.
+    pub fn new(per_second: f64, burst: u32) -> RateLimit {
+        assert!(per_second > 0.0, "rate must be positive");
+        assert!(burst > 0, "burst must be at least 1");
+        RateLimit {
+            per_second,
+            burst: f64::from(burst),
+            state: Mutex::new(Buckets {
+                clients: HashMap::new(),
+                swept: Instant::now(),
+            }),
+        }
+    }
+
+    /// Takes a token for `client`, or returns how long until one is free.
+    fn acquire(&self, client: IpAddr, now: Instant) -> Result<(), Duration> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+
+        // Buckets that have refilled are no different from new ones.
+        if now.duration_since(state.swept) >= Duration::from_secs(1) {
+            let (per_second, burst) = (self.per_second, self.burst);
+            state.clients.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * per_second
+                    < burst
+            });
+            state.swept = now;
+        }
+
+        let bucket = state.clients.entry(client).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        let refill = now.duration_since(bucket.updated).as_secs_f64() * self.per_second;
+        bucket.tokens = (bucket.tokens + refill).min(self.burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.per_second,
+            ))
+        }
+    }
+}
+
+impl Middleware for RateLimit {
+    fn handle(&self, request: &mut Request, next: &dyn Handler) -> Response {
+        let Some(client) = request.peer_addr() else {
+            return next.handle(request);
+        };
+        match self.acquire(client.ip().to_canonical(), Instant::now()) {
+            Ok(()) => next.handle(request),
+            Err(wait) => Response::error(StatusCode::TOO_MANY_REQUESTS).with_header(
+                "Retry-After",
+                wait.as_secs_f64().ceil().max(1.0).to_string(),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::ParseLimits;
+    use std::io::Cursor;
+
+    #[test]
+    fn limits_each_client_separately() {
+        let limit = RateLimit::new(2.0, 3);
+        let start = Instant::now();
+        let (a, b) = ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+
+        for _ in 0..3 {
+            assert_eq!(limit.acquire(a, start), Ok(()));
+        }
+        assert_eq!(limit.acquire(a, start), Err(Duration::from_millis(500)));
+        assert_eq!(limit.acquire(b, start), Ok(()));
+        assert_eq!(limit.acquire(a, start + Duration::from_millis(500)), Ok(()));
+
+        // Full buckets are forgotten.
+        limit.acquire(b, start + Duration::from_secs(5)).unwrap();
+        let state = limit.state.lock().unwrap();
+        assert_eq!(state.clients.keys().collect::<Vec<_>>(), [&b]);
+    }
+
+    #[test]
+    fn answers_429_with_retry_after() {
+        let limit = RateLimit::new(0.5, 1);
+        let app = |_: &mut Request| Response::text(StatusCode::OK, "ok");
+        let mut request = Request::parse(
+            &mut Cursor::new(&b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n"[..]),
+            &ParseLimits::default(),
+        )
+        .unwrap();
+        request.peer_addr = Some("[::ffff:10.0.0.1]:4000".parse().unwrap());
+
+        assert_eq!(limit.handle(&mut request, &app).status(), StatusCode::OK);
+        let response = limit.handle(&mut request, &app);
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers().get("Retry-After"), Some("2"));
+    }
+}
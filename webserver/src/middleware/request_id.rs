@@ -0,0 +1,57 @@
+use crate::{handler::Handler, request::Request, response::Response};
+
+use super::Middleware;
+
+/// Tags each request and its response with an id, `<connection uuid>-<n>`
+/// for the n-th request on the connection, so log lines and client reports
+/// can be matched up.
+///
+/// The id is set as a request header for handlers and as the same response
+/// header for the client.
+#[derive(Debug, Clone)]
+pub struct RequestId {
+    header: String,
+    trust_incoming: bool,
+}
+
+impl RequestId {
+    /// Uses the `X-Request-Id` header and ignores ids sent by clients.
+    pub fn new() -> RequestId {
+        RequestId {
+            header: "X-Request-Id".to_owned(),
+            trust_incoming: false,
+        }
+    }
+
+    /// Uses the header `name` instead of `X-Request-Id`.
+    pub fn header(mut self, name: impl Into<String>) -> RequestId {
+        self.header = name.into();
+        self
+    }
+
+    /// Keeps an id the client (e.g. a proxy in front) already sent.
+    pub fn trust_incoming(mut self) -> RequestId {
+        self.trust_incoming = true;
+        self
+    }
+}
+
+impl Default for RequestId {
+    fn default() -> Self {
+        RequestId::new()
+    }
+}
+
+impl Middleware for RequestId {
+    fn handle(&self, request: &mut Request, next: &dyn Handler) -> Response {
+        let id = match request.header(&self.header) {
+            Some(id) if self.trust_incoming && !id.is_empty() => id.to_owned(),
+            _ => format!("{}-{}", request.connection_id(), request.sequence()),
+        };
+        request.headers_mut().insert(&self.header, &id);
+
+        let mut response = next.handle(request);
+        response.headers_mut().insert(&self.header, id);
+        response
+    }
+}
@@ -0,0 +1,241 @@
+use std::time::Duration;
+
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use hmac_sha256::HMAC;
+use uuid::Uuid;
+
+use crate::{
+    cookie::{Cookie, SameSite},
+    handler::Handler,
+    log::{error, warn},
+    request::{self, Request},
+    response::Response,
+    session::{Session, SessionStore},
+    status::StatusCode,
+};
+
+use super::Middleware;
+
+/// Gives every request a [`Session`], found again on later requests through
+/// a cookie holding the session id.
+///
+/// The id is signed with HMAC-SHA256, so forged or altered cookies are
+/// ignored. Sessions are saved once the inner layers are done, and only
+/// if they changed; the cookie is `HttpOnly`, `SameSite=Lax` by default,
+/// and `Secure` on HTTPS requests. A session expires `ttl` after it was
+/// last saved.
+///
+/// # Examples
+///
+/// ```
+/// use webserver::{Request, Response, Router, StatusCode};
+/// use webserver::middleware::{Chain, Sessions};
+/// use webserver::session::MemoryStore;
+///
+/// let router = Router::new().get("/", |_: &mut Request| Response::text(StatusCode::OK, "hi"));
+/// let app = Chain::new(router).with(Sessions::new(MemoryStore::new(), [7; 32]));
+/// ```
+pub struct Sessions {
+    store: Box<dyn SessionStore>,
+    key: Vec<u8>,
+    cookie_name: String,
+    path: String,
+    ttl: Duration,
+    same_site: SameSite,
+}
+
+impl Sessions {
+    /// Keeps sessions in `store` and signs their ids with `key`, which
+    /// should be random and stay the same across restarts.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `key` is shorter than 32 bytes.
+    pub fn new<S: SessionStore + 'static>(store: S, key: impl Into<Vec<u8>>) -> Sessions {
+        let key = key.into();
+        assert!(key.len() >= 32, "session keys need at least 32 bytes");
+        Sessions {
+            store: Box::new(store),
+            key,
+            cookie_name: "session".to_owned(),
+            path: "/".to_owned(),
+            ttl: Duration::from_secs(24 * 60 * 60),
+            same_site: SameSite::Lax,
+        }
+    }
+
+    /// Names the cookie `name` instead of `session`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` is not a token, as [`Cookie::new`] would for every
+    /// request.
+    pub fn cookie_name(mut self, name: impl Into<String>) -> Sessions {
+        let name = name.into();
+        assert!(
+            !name.is_empty() && name.bytes().all(request::is_token_byte),
+            "invalid cookie name {name:?}"
+        );
+        self.cookie_name = name;
+        self
+    }
+
+    /// Only sends the cookie for paths below `path` instead of everywhere.
+    pub fn path(mut self, path: impl Into<String>) -> Sessions {
+        self.path = path.into();
+        self
+    }
+
+    /// Keeps sessions for `ttl` after their last change instead of a day.
+    pub fn ttl(mut self, ttl: Duration) -> Sessions {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Sets the `SameSite` attribute of the cookie.
+    pub fn same_site(mut self, same_site: SameSite) -> Sessions {
+        self.same_site = same_site;
+        self
+    }
+
+    fn sign(&self, id: &str) -> String {
+        format!("{id}.{}", URL_SAFE_NO_PAD.encode(HMAC::mac(id, &self.key)))
+    }
+
+    /// The session id in a signed cookie value, if the signature holds.
+    fn verify<'a>(&self, value: &'a str) -> Option<&'a str> {
+        let (id, signature) = value.split_once('.')?;
+        let signature: [u8; 32] = URL_SAFE_NO_PAD.decode(signature).ok()?.try_into().ok()?;
+        HMAC::verify(id, &self.key, &signature).then_some(id)
+    }
+
+    fn load(&self, request: &Request) -> Session {
+        let Some(id) = request
+            .cookie(&self.cookie_name)
+            .and_then(|value| self.verify(value))
+        else {
+            return Session::new(None, Default::default());
+        };
+        match self.store.load(id) {
+            Ok(Some(data)) => Session::new(Some(id.to_owned()), data),
+            Ok(None) => Session::new(None, Default::default()),
+            Err(e) => {
+                warn!("cannot load session: {e}");
+                Session::new(None, Default::default())
+            }
+        }
+    }
+
+    fn cookie(&self, value: String, request: &Request) -> Cookie {
+        let cookie = Cookie::new(&self.cookie_name, value)
+            .path(&self.path)
+            .http_only()
+            .same_site(self.same_site);
+        if request.is_secure() {
+            cookie.secure()
+        } else {
+            cookie
+        }
+    }
+}
+
+impl Middleware for Sessions {
+    fn handle(&self, request: &mut Request, next: &dyn Handler) -> Response {
+        let session = self.load(request);
+        request.session = Some(session.clone());
+        let response = next.handle(request);
+        request.session = None;
+
+        let state = session.lock();
+        let rotate = state.destroyed || state.regenerate;
+        if rotate
+            && let Some(id) = &state.id
+            && let Err(e) = self.store.remove(id)
+        {
+            warn!("cannot remove session: {e}");
+        }
+
+        // Regenerating moves unchanged data too.
+        let moved = state.regenerate && !state.destroyed && state.id.is_some();
+        if !state.changed && !moved {
+            if state.destroyed && state.id.is_some() {
+                let removal = Cookie::removal(&self.cookie_name, &self.path);
+                return response.with_cookie(&removal);
+            }
+            return response;
+        }
+        let id = match &state.id {
+            Some(id) if !rotate => id.clone(),
+            _ => Uuid::new_v4().simple().to_string(),
+        };
+        if let Err(e) = self.store.save(&id, &state.data, self.ttl) {
+            error!("cannot save session: {e}");
+            return Response::error(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+        let cookie = self.cookie(self.sign(&id), request).max_age(self.ttl);
+        response.with_cookie(&cookie)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{request::ParseLimits, session::MemoryStore};
+    use std::{io::Cursor, sync::Arc};
+
+    fn request(path: &str, cookie: Option<&str>) -> Request {
+        let cookie = cookie.map_or_else(String::new, |cookie| format!("Cookie: {cookie}\r\n"));
+        let raw = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n{cookie}\r\n");
+        Request::parse(&mut Cursor::new(raw), &ParseLimits::default()).unwrap()
+    }
+
+    /// The `name=value` part of the session cookie the response sets.
+    fn session_cookie(response: &Response) -> Option<String> {
+        let cookie: Cookie = response.headers().get("Set-Cookie")?.parse().unwrap();
+        Some(format!("{}={}", cookie.name(), cookie.value()))
+    }
+
+    #[test]
+    fn keeps_data_behind_signed_cookies() {
+        let store = Arc::new(MemoryStore::new());
+        let sessions = Sessions::new(store.clone(), [1; 32]);
+        let count = |request: &mut Request| {
+            let session = request.session().unwrap();
+            if request.path() == "/logout" {
+                session.destroy();
+            }
+            let visits = session.get::<u32>("visits").unwrap_or(0) + 1;
+            session.insert("visits", visits);
+            Response::text(StatusCode::OK, visits.to_string())
+        };
+
+        let first = sessions.handle(&mut request("/", None), &count);
+        let cookie = session_cookie(&first).unwrap();
+        let second = sessions.handle(&mut request("/", Some(&cookie)), &count);
+        assert_eq!(second.body().bytes(), Some(&b"2"[..]));
+        assert_eq!(session_cookie(&second), Some(cookie.clone()));
+        assert_eq!(store.len(), 1);
+
+        // A tampered id starts over.
+        let forged = cookie.replacen('=', "=f", 1);
+        let response = sessions.handle(&mut request("/", Some(&forged)), &count);
+        assert_eq!(response.body().bytes(), Some(&b"1"[..]));
+        assert_eq!(store.len(), 2);
+
+        // Requests that change nothing set no cookie.
+        let read_only = |_: &mut Request| Response::new(StatusCode::NO_CONTENT);
+        let response = sessions.handle(&mut request("/", Some(&cookie)), &read_only);
+        assert!(!response.headers().contains("Set-Cookie"));
+
+        let response = sessions.handle(&mut request("/logout", Some(&cookie)), &count);
+        assert_eq!(response.body().bytes(), Some(&b"1"[..]));
+        assert_ne!(session_cookie(&response), Some(cookie));
+        assert_eq!(store.len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid cookie name")]
+    fn refuses_cookie_names_that_are_not_tokens() {
+        let _ = Sessions::new(MemoryStore::new(), [7; 32]).cookie_name("my session");
+    }
+}
@@ -0,0 +1,26 @@
+use std::time::Instant;
+
+use crate::{handler::Handler, request::Request, response::Response};
+
+use super::Middleware;
+
+/// Reports how long the inner layers and the handler took, as
+/// `X-Response-Time: 1.234ms` and `Server-Timing: app;dur=1.234`.
+///
+/// Streamed bodies are produced after the headers are sent, so their time is
+/// not included.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timing;
+
+impl Middleware for Timing {
+    fn handle(&self, request: &mut Request, next: &dyn Handler) -> Response {
+        let start = Instant::now();
+        let mut response = next.handle(request);
+        let ms = start.elapsed().as_secs_f64() * 1000.0;
+
+        let headers = response.headers_mut();
+        headers.insert("X-Response-Time", format!("{ms:.3}ms"));
+        headers.append("Server-Timing", format!("app;dur={ms:.3}"));
+        response
+    }
+}
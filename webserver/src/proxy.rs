@@ -0,0 +1,488 @@
+//! Forwarding requests to upstream HTTP servers.
+
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader, BufWriter, ErrorKind, Read, Write},
+    net::{IpAddr, SocketAddr, TcpStream},
+    sync::{
+        Mutex, MutexGuard, PoisonError,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    body::Body,
+    chunked::ChunkedReader,
+    handler::Handler,
+    headers::Headers,
+    log::warn,
+    request::{self, Line, Method, ParseError, Request},
+    response::Response,
+    status::StatusCode,
+};
+
+/// Longest status or header line accepted from an upstream.
+const MAX_LINE: usize = 8 * 1024;
+/// Most header fields accepted from an upstream.
+const MAX_HEADERS: usize = 100;
+
+/// Header fields that describe one connection and are not forwarded.
+const HOP_BY_HOP: [&str; 9] = [
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Connection",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+    "Content-Length",
+    "Expect",
+];
+
+/// Forwards requests to upstream HTTP servers and streams their responses
+/// back.
+///
+/// Requests go round-robin to the upstreams. One that cannot be reached or
+/// does not answer in time is skipped for
+/// [`fail_timeout`](Proxy::fail_timeout), unless all of them are failing.
+/// Connection failures are retried on the next upstream; once a request has
+/// been sent it is not retried. Clients get `502 Bad Gateway` when no
+/// upstream answers properly and `504 Gateway Timeout` when one is too slow.
+///
+/// Upstreams see the client's `Host` and method, the path (minus
+/// [`strip_prefix`](Proxy::strip_prefix)) and query, and the client's
+/// address in `X-Forwarded-For` and `Forwarded`. Every request uses a new
+/// upstream connection.
+///
+/// # Examples
+///
+/// ```
+/// use webserver::{Proxy, Router};
+///
+/// let api = Proxy::new(["127.0.0.1:9000".parse().unwrap(), "127.0.0.1:9001".parse().unwrap()])
+///     .strip_prefix("/api");
+/// let router = Router::new().any("/api/*path", api);
+/// ```
+pub struct Proxy {
+    upstreams: Vec<Upstream>,
+    next: AtomicUsize,
+    strip_prefix: Option<String>,
+    connect_timeout: Duration,
+    timeout: Duration,
+    fail_timeout: Duration,
+}
+
+struct Upstream {
+    address: SocketAddr,
+    /// Skipped until then after a failure.
+    down_until: Mutex<Option<Instant>>,
+}
+
+/// Why an upstream did not produce a response.
+#[derive(Debug)]
+enum ProxyError {
+    Connect(io::Error),
+    Io(io::Error),
+    Malformed(&'static str),
+}
+
+impl Proxy {
+    /// Forwards to `upstreams`, in turn.
+    ///
+    /// # Panics
+    ///
+    /// If `upstreams` is empty.
+    pub fn new(upstreams: impl IntoIterator<Item = SocketAddr>) -> Proxy {
+        let upstreams: Vec<_> = upstreams
+            .into_iter()
+            .map(|address| Upstream {
+                address,
+                down_until: Mutex::new(None),
+            })
+            .collect();
+        assert!(!upstreams.is_empty(), "a proxy needs at least one upstream");
+        Proxy {
+            upstreams,
+            next: AtomicUsize::new(0),
+            strip_prefix: None,
+            connect_timeout: Duration::from_secs(5),
+            timeout: Duration::from_secs(30),
+            fail_timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// Removes `prefix` from the front of the path before forwarding, so
+    /// `/api/users` reaches the upstream as `/users`.
+    pub fn strip_prefix(mut self, prefix: impl Into<String>) -> Proxy {
+        let prefix = prefix.into();
+        self.strip_prefix = Some(prefix.trim_end_matches('/').to_owned());
+        self
+    }
+
+    /// How long connecting to an upstream may take. Defaults to 5 seconds.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Proxy {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// How long an upstream may take for each read or write once connected.
+    /// Defaults to 30 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> Proxy {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How long a failed upstream is left out of the rotation. Defaults to
+    /// 10 seconds.
+    pub fn fail_timeout(mut self, timeout: Duration) -> Proxy {
+        self.fail_timeout = timeout;
+        self
+    }
+
+    /// The upstreams to try, healthy ones in round-robin order, or all of
+    /// them if none is healthy.
+    fn candidates(&self) -> Vec<&Upstream> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let now = Instant::now();
+        let ordered =
+            (0..self.upstreams.len()).map(|i| &self.upstreams[(start + i) % self.upstreams.len()]);
+        let healthy: Vec<_> = ordered.clone().filter(|up| up.is_up(now)).collect();
+        if healthy.is_empty() {
+            ordered.collect()
+        } else {
+            healthy
+        }
+    }
+
+    /// Opens a connection to the first candidate that accepts one.
+    fn connect(&self, request: &Request) -> Result<(&Upstream, TcpStream), ProxyError> {
+        let mut last_error = None;
+        for upstream in self.candidates() {
+            match TcpStream::connect_timeout(&upstream.address, self.connect_timeout) {
+                Ok(stream) => return Ok((upstream, stream)),
+                Err(e) => {
+                    warn!(
+                        "[{}] Cannot connect to upstream {}: {e}",
+                        request.connection_id(),
+                        upstream.address
+                    );
+                    upstream.mark_down(self.fail_timeout);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(ProxyError::Connect(
+            last_error.expect("at least one upstream"),
+        ))
+    }
+
+    /// Sends `request` to `stream` and reads the response head.
+    fn exchange(&self, request: &Request, stream: TcpStream) -> Result<Response, ProxyError> {
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        stream.set_nodelay(true)?;
+
+        let mut writer = BufWriter::new(&stream);
+        writer.write_all(self.request_head(request, stream.peer_addr()?).as_bytes())?;
+        writer.write_all(request.body())?;
+        writer.flush()?;
+        drop(writer);
+
+        let mut reader = BufReader::new(stream);
+        let (status, headers) = loop {
+            let (status, headers) = read_head(&mut reader)?;
+            // Interim responses, e.g. 100 Continue, are not passed on.
+            if !status.is_informational() {
+                break (status, headers);
+            }
+            if status.as_u16() == 101 {
+                return Err(ProxyError::Malformed("unexpected protocol switch"));
+            }
+        };
+
+        let mut response = Response::new(status);
+        for (name, value) in headers.iter() {
+            if !is_hop_by_hop(&headers, name) {
+                response.headers_mut().append(name, value);
+            }
+        }
+        let body =
+            if matches!(status.as_u16(), 204 | 304) || headers.get("Content-Length") == Some("0") {
+                Body::Empty
+            } else if request.method() == Method::Head {
+                // The length can't be passed on without a body, so the head says
+                // chunked, as it would for the streamed `GET`.
+                Body::reader(io::empty())
+            } else if headers.has_token("Transfer-Encoding", "chunked") {
+                Body::reader(ChunkedReader::new(reader))
+            } else if let Some(length) = headers.get("Content-Length") {
+                let length = length
+                    .parse()
+                    .map_err(|_| ProxyError::Malformed("malformed Content-Length"))?;
+                Body::reader(Exact {
+                    reader,
+                    remaining: length,
+                })
+            } else {
+                // Without framing the body ends when the upstream closes.
+                Body::reader(reader)
+            };
+        Ok(response.with_body(body))
+    }
+
+    /// The request-target sent upstream, in origin form.
+    fn target(&self, request: &Request) -> String {
+        let target = origin_form(request.target());
+        let Some(prefix) = &self.strip_prefix else {
+            return target;
+        };
+        match target.strip_prefix(prefix.as_str()) {
+            Some(rest) if rest.starts_with('/') => rest.to_owned(),
+            // "/api" and "/api?x" become "/" and "/?x".
+            Some(rest) if rest.is_empty() || rest.starts_with('?') => format!("/{rest}"),
+            _ => target,
+        }
+    }
+
+    /// The request line and header fields sent upstream.
+    fn request_head(&self, request: &Request, upstream: SocketAddr) -> String {
+        let headers = request.headers();
+        let host = headers
+            .get("Host")
+            .map(str::to_owned)
+            .unwrap_or_else(|| upstream.to_string());
+        let proto = if request.is_secure() { "https" } else { "http" };
+
+        let mut head = format!(
+            "{} {} HTTP/1.1\r\nHost: {host}\r\n",
+            request.method(),
+            self.target(request)
+        );
+        for (name, value) in headers.iter() {
+            if is_hop_by_hop(headers, name)
+                || [
+                    "Host",
+                    "X-Forwarded-For",
+                    "X-Forwarded-Proto",
+                    "X-Forwarded-Host",
+                    "Forwarded",
+                ]
+                .iter()
+                .any(|skip| name.eq_ignore_ascii_case(skip))
+            {
+                continue;
+            }
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+
+        if let Some(client) = request.peer_addr() {
+            let client = client.ip().to_canonical();
+            let forwarded_for = headers
+                .get_all("X-Forwarded-For")
+                .chain([client.to_string().as_str()])
+                .collect::<Vec<_>>()
+                .join(", ");
+            head.push_str(&format!("X-Forwarded-For: {forwarded_for}\r\n"));
+
+            let node = match client {
+                IpAddr::V4(ip) => ip.to_string(),
+                IpAddr::V6(ip) => format!("\"[{ip}]\""),
+            };
+            let element = format!("for={node};host={};proto={proto}", quote(&host));
+            let forwarded = headers
+                .get_all("Forwarded")
+                .chain([element.as_str()])
+                .collect::<Vec<_>>()
+                .join(", ");
+            head.push_str(&format!("Forwarded: {forwarded}\r\n"));
+        }
+        head.push_str(&format!("X-Forwarded-Proto: {proto}\r\n"));
+        head.push_str(&format!("X-Forwarded-Host: {host}\r\n"));
+
+        if !request.body().is_empty() || request.content_length().is_some() {
+            head.push_str(&format!("Content-Length: {}\r\n", request.body().len()));
+        }
+        head.push_str("Connection: close\r\n\r\n");
+        head
+    }
+}
+
+impl Handler for Proxy {
+    fn handle(&self, request: &mut Request) -> Response {
+        let (upstream, stream) = match self.connect(request) {
+            Ok(connected) => connected,
+            Err(e) => return e.response(),
+        };
+        match self.exchange(request, stream) {
+            Ok(response) => {
+                upstream.mark_up();
+                response
+            }
+            Err(e) => {
+                warn!(
+                    "[{}] Upstream {} failed: {e}",
+                    request.connection_id(),
+                    upstream.address
+                );
+                upstream.mark_down(self.fail_timeout);
+                e.response()
+            }
+        }
+    }
+}
+
+impl Upstream {
+    fn is_up(&self, now: Instant) -> bool {
+        self.lock().is_none_or(|until| now >= until)
+    }
+
+    fn mark_up(&self) {
+        *self.lock() = None;
+    }
+
+    fn mark_down(&self, duration: Duration) {
+        *self.lock() = Some(Instant::now() + duration);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Option<Instant>> {
+        self.down_until
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl ProxyError {
+    fn response(&self) -> Response {
+        let timed_out =
+            |e: &io::Error| matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock);
+        let status = match self {
+            ProxyError::Connect(e) | ProxyError::Io(e) if timed_out(e) => {
+                StatusCode::GATEWAY_TIMEOUT
+            }
+            _ => StatusCode::BAD_GATEWAY,
+        };
+        Response::error(status)
+    }
+}
+
+impl fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProxyError::Connect(e) => write!(f, "cannot connect: {e}"),
+            ProxyError::Io(e) => write!(f, "{e}"),
+            ProxyError::Malformed(reason) => write!(f, "malformed response: {reason}"),
+        }
+    }
+}
+
+impl From<io::Error> for ProxyError {
+    fn from(e: io::Error) -> Self {
+        ProxyError::Io(e)
+    }
+}
+
+impl From<ParseError> for ProxyError {
+    fn from(e: ParseError) -> Self {
+        match e {
+            ParseError::Io(e) => ProxyError::Io(e),
+            _ => ProxyError::Malformed("malformed header line"),
+        }
+    }
+}
+
+/// Reads a status line and header fields.
+fn read_head<R: BufRead>(reader: &mut R) -> Result<(StatusCode, Headers), ProxyError> {
+    let line = match request::read_line(reader, MAX_LINE)? {
+        Line::Data(line) => line,
+        Line::Eof => return Err(ProxyError::Malformed("no response")),
+        Line::TooLong => return Err(ProxyError::Malformed("status line too long")),
+    };
+    let line = String::from_utf8_lossy(&line);
+    let mut parts = line.splitn(3, ' ');
+    let (Some(version), Some(code)) = (parts.next(), parts.next()) else {
+        return Err(ProxyError::Malformed("malformed status line"));
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err(ProxyError::Malformed("unsupported HTTP version"));
+    }
+    let status = match code.parse() {
+        Ok(value @ 100..=999) if code.len() == 3 => StatusCode::from_u16(value),
+        _ => return Err(ProxyError::Malformed("malformed status code")),
+    };
+
+    let mut headers = Headers::new();
+    loop {
+        let line = match request::read_line(reader, MAX_LINE)? {
+            Line::Data(line) => line,
+            Line::Eof => return Err(ProxyError::Malformed("unexpected end of headers")),
+            Line::TooLong => return Err(ProxyError::Malformed("header line too long")),
+        };
+        if line.is_empty() {
+            return Ok((status, headers));
+        }
+        if headers.len() == MAX_HEADERS {
+            return Err(ProxyError::Malformed("too many header fields"));
+        }
+        let (name, value) = request::parse_header(&line)?;
+        headers.append(name, value);
+    }
+}
+
+/// Returns `true` for fields that only concern the connection they came on,
+/// including those named in its `Connection` header.
+fn is_hop_by_hop(headers: &Headers, name: &str) -> bool {
+    HOP_BY_HOP.iter().any(|hop| name.eq_ignore_ascii_case(hop))
+        || headers.has_token("Connection", name)
+}
+
+/// Drops the scheme and authority of an absolute-form target.
+fn origin_form(target: &str) -> String {
+    let Some(rest) = target
+        .strip_prefix("http://")
+        .or_else(|| target.strip_prefix("https://"))
+    else {
+        return target.to_owned();
+    };
+    match rest.find(['/', '?']) {
+        Some(start) if rest[start..].starts_with('/') => rest[start..].to_owned(),
+        Some(start) => format!("/{}", &rest[start..]),
+        None => "/".to_owned(),
+    }
+}
+
+/// A `Forwarded` parameter value, quoted unless it is a plain token.
+fn quote(value: &str) -> String {
+    if !value.is_empty() && value.bytes().all(request::is_token_byte) {
+        value.to_owned()
+    } else {
+        format!("\"{}\"", value.replace(['\\', '"'], ""))
+    }
+}
+
+/// Reads exactly `remaining` bytes, failing if the upstream closes before,
+/// so a cut-off body is not passed on as complete.
+struct Exact<R> {
+    reader: R,
+    remaining: u64,
+}
+
+impl<R: Read> Read for Exact<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            return Ok(0);
+        }
+        let max = buf
+            .len()
+            .min(usize::try_from(self.remaining).unwrap_or(usize::MAX));
+        let n = self.reader.read(&mut buf[..max])?;
+        if n == 0 && max > 0 {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "upstream closed in the middle of the body",
+            ));
+        }
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
@@ -0,0 +1,779 @@
+//! Parsing of HTTP/1.x requests.
+
+use std::{
+    fmt,
+    io::{self, BufRead, Read},
+    net::SocketAddr,
+};
+
+use uuid::Uuid;
+
+use crate::{
+    chunked::ChunkedReader, connection::Incoming, cookie, headers::Headers, session::Session,
+    status::StatusCode, url,
+};
+
+/// The request methods the server understands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Options,
+    Patch,
+    Trace,
+    Connect,
+}
+
+impl Method {
+    /// Parses a method token. Methods are case-sensitive, so `get` is rejected.
+    pub fn from_token(token: &str) -> Option<Method> {
+        match token {
+            "GET" => Some(Method::Get),
+            "HEAD" => Some(Method::Head),
+            "POST" => Some(Method::Post),
+            "PUT" => Some(Method::Put),
+            "DELETE" => Some(Method::Delete),
+            "OPTIONS" => Some(Method::Options),
+            "PATCH" => Some(Method::Patch),
+            "TRACE" => Some(Method::Trace),
+            "CONNECT" => Some(Method::Connect),
+            _ => None,
+        }
+    }
+
+    /// Returns the method token, e.g. `"GET"`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Options => "OPTIONS",
+            Method::Patch => "PATCH",
+            Method::Trace => "TRACE",
+            Method::Connect => "CONNECT",
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The HTTP versions the server speaks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl Version {
+    /// Returns the protocol string, e.g. `"HTTP/1.1"`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Version::Http10 => "HTTP/1.0",
+            Version::Http11 => "HTTP/1.1",
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How the end of a request body is found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyFraming {
+    /// The request has no body.
+    None,
+    /// `Content-Length` announced this many bytes.
+    Length(u64),
+    /// `Transfer-Encoding: chunked`.
+    Chunked,
+}
+
+/// Upper bounds applied while reading a request head.
+#[derive(Debug, Clone)]
+pub struct ParseLimits {
+    /// Longest accepted request-target, in bytes.
+    pub max_target_len: usize,
+    /// Maximum number of header fields.
+    pub max_headers: usize,
+    /// Longest accepted single header line, in bytes.
+    pub max_header_line: usize,
+}
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        ParseLimits {
+            max_target_len: 8 * 1024,
+            max_headers: 100,
+            max_header_line: 8 * 1024,
+        }
+    }
+}
+
+/// Why a request could not be parsed.
+#[derive(Debug)]
+pub enum ParseError {
+    /// The peer closed the connection before sending anything.
+    ConnectionClosed,
+    /// Reading from the socket failed.
+    Io(io::Error),
+    /// The request is malformed.
+    BadRequest(&'static str),
+    /// The method token is not one we know.
+    UnknownMethod(String),
+    /// The request-target exceeds [`ParseLimits::max_target_len`].
+    UriTooLong,
+    /// Too many header fields, or a single one is too long.
+    HeadersTooLarge,
+    /// A well-formed version other than HTTP/1.0 or HTTP/1.1.
+    VersionNotSupported(String),
+    /// The body is larger than the configured maximum.
+    PayloadTooLarge,
+    /// An `Expect` value other than `100-continue`.
+    ExpectationFailed,
+    /// A transfer coding we cannot decode.
+    NotImplemented(&'static str),
+    /// The head or body took longer to arrive than allowed in total.
+    Timeout,
+}
+
+impl ParseError {
+    /// The status code to answer with, or `None` if the connection should
+    /// just be closed.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            ParseError::ConnectionClosed | ParseError::Io(_) => None,
+            ParseError::BadRequest(_) => Some(StatusCode::BAD_REQUEST),
+            ParseError::UnknownMethod(_) => Some(StatusCode::METHOD_NOT_ALLOWED),
+            ParseError::UriTooLong => Some(StatusCode::URI_TOO_LONG),
+            ParseError::HeadersTooLarge => Some(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE),
+            ParseError::VersionNotSupported(_) => Some(StatusCode::HTTP_VERSION_NOT_SUPPORTED),
+            ParseError::PayloadTooLarge => Some(StatusCode::CONTENT_TOO_LARGE),
+            ParseError::ExpectationFailed => Some(StatusCode::EXPECTATION_FAILED),
+            ParseError::NotImplemented(_) => Some(StatusCode::NOT_IMPLEMENTED),
+            ParseError::Timeout => Some(StatusCode::REQUEST_TIMEOUT),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::ConnectionClosed => write!(f, "connection closed"),
+            ParseError::Io(e) => write!(f, "read error: {e}"),
+            ParseError::BadRequest(reason) => write!(f, "bad request: {reason}"),
+            ParseError::UnknownMethod(method) => write!(f, "unknown method {method:?}"),
+            ParseError::UriTooLong => write!(f, "request-target too long"),
+            ParseError::HeadersTooLarge => write!(f, "request header fields too large"),
+            ParseError::VersionNotSupported(version) => {
+                write!(f, "unsupported version {version:?}")
+            }
+            ParseError::PayloadTooLarge => write!(f, "request body too large"),
+            ParseError::ExpectationFailed => write!(f, "unsupported expectation"),
+            ParseError::NotImplemented(what) => write!(f, "not implemented: {what}"),
+            ParseError::Timeout => write!(f, "request not received in time"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<io::Error> for ParseError {
+    fn from(e: io::Error) -> Self {
+        ParseError::Io(e)
+    }
+}
+
+/// A parsed HTTP request.
+#[derive(Debug, Clone)]
+pub struct Request {
+    method: Method,
+    target: String,
+    path: String,
+    query: Option<String>,
+    version: Version,
+    headers: Headers,
+    body: Vec<u8>,
+    framing: BodyFraming,
+    expects_continue: bool,
+    pub(crate) params: Vec<(String, String)>,
+    pub(crate) route: Option<String>,
+    pub(crate) peer_addr: Option<SocketAddr>,
+    pub(crate) secure: bool,
+    pub(crate) connection_id: Uuid,
+    pub(crate) sequence: usize,
+    pub(crate) session: Option<Session>,
+    pub(crate) incoming: Incoming,
+}
+
+impl Request {
+    /// Reads one request head (request line and header fields) from `reader`.
+    pub fn parse<R: BufRead>(reader: &mut R, limits: &ParseLimits) -> Result<Request, ParseError> {
+        // Generous allowance for the method and version around the target.
+        let line_limit = limits.max_target_len + 32;
+
+        // Ignore empty lines ahead of the request line, as RFC 9112 suggests.
+        let request_line = loop {
+            match read_line(reader, line_limit)? {
+                Line::Eof => return Err(ParseError::ConnectionClosed),
+                Line::TooLong => return Err(ParseError::UriTooLong),
+                Line::Data(line) if line.is_empty() => continue,
+                Line::Data(line) => break line,
+            }
+        };
+        let request_line = String::from_utf8(request_line)
+            .map_err(|_| ParseError::BadRequest("request line is not valid UTF-8"))?;
+
+        let mut parts = request_line.split(' ');
+        let (Some(method), Some(target), Some(version), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(ParseError::BadRequest("malformed request line"));
+        };
+
+        if method.is_empty() || !method.bytes().all(is_token_byte) {
+            return Err(ParseError::BadRequest("malformed method"));
+        }
+        let method = Method::from_token(method)
+            .ok_or_else(|| ParseError::UnknownMethod(method.to_owned()))?;
+        let version = parse_version(version)?;
+
+        if target.len() > limits.max_target_len {
+            return Err(ParseError::UriTooLong);
+        }
+        let (path, query) = parse_target(method, target)?;
+
+        let mut headers = Headers::new();
+        loop {
+            let line = match read_line(reader, limits.max_header_line)? {
+                Line::Eof => return Err(ParseError::BadRequest("unexpected end of headers")),
+                Line::TooLong => return Err(ParseError::HeadersTooLarge),
+                Line::Data(line) => line,
+            };
+            if line.is_empty() {
+                break;
+            }
+            if headers.len() == limits.max_headers {
+                return Err(ParseError::HeadersTooLarge);
+            }
+            let (name, value) = parse_header(&line)?;
+            headers.append(name, value);
+        }
+
+        // Exactly one Host, which HTTP/1.1 clients must send (RFC 9112 3.2).
+        let hosts: Vec<&str> = headers.get_all("Host").collect();
+        match hosts[..] {
+            [] if version == Version::Http11 => {
+                return Err(ParseError::BadRequest("missing Host header"));
+            }
+            [host] if !is_valid_host(host) => {
+                return Err(ParseError::BadRequest("malformed Host header"));
+            }
+            [_, _, ..] => return Err(ParseError::BadRequest("more than one Host header")),
+            _ => {}
+        }
+
+        let framing = parse_framing(&headers)?;
+        let expects_continue = match headers.get("Expect") {
+            None => false,
+            Some(value) if value.eq_ignore_ascii_case("100-continue") => version == Version::Http11,
+            Some(_) => return Err(ParseError::ExpectationFailed),
+        };
+
+        Ok(Request {
+            method,
+            target: target.to_owned(),
+            path,
+            query,
+            version,
+            headers,
+            body: Vec::new(),
+            framing,
+            expects_continue,
+            params: Vec::new(),
+            route: None,
+            peer_addr: None,
+            secure: false,
+            connection_id: Uuid::nil(),
+            sequence: 0,
+            session: None,
+            incoming: Incoming::default(),
+        })
+    }
+
+    /// The request method.
+    pub fn method(&self) -> Method {
+        self.method
+    }
+
+    /// The raw request-target as sent by the client, query string included.
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// The percent-decoded path, e.g. `/users/42`.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The raw query string without the leading `?`.
+    pub fn query(&self) -> Option<&str> {
+        self.query.as_deref()
+    }
+
+    /// Returns the first decoded value of the query parameter `name`.
+    pub fn query_param(&self, name: &str) -> Option<String> {
+        url::parse_query(self.query.as_deref()?)
+            .into_iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Returns the path parameter `name` captured by the [`Router`](crate::Router).
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The pattern of the [`Router`](crate::Router) route that matched,
+    /// e.g. `/users/:id`. Set once the router has run.
+    pub fn route(&self) -> Option<&str> {
+        self.route.as_deref()
+    }
+
+    /// The address of the client, or of the proxy in front of it. `None`
+    /// for requests that did not come from a socket.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// Returns `true` if the request came over TLS.
+    pub fn is_secure(&self) -> bool {
+        self.secure
+    }
+
+    /// The protocol version.
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// All request headers.
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    /// Mutable access to the request headers, e.g. for middleware.
+    pub fn headers_mut(&mut self) -> &mut Headers {
+        &mut self.headers
+    }
+
+    /// Shortcut for `headers().get(name)`.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// The value of the cookie `name` the client sent.
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        self.headers
+            .get_all("Cookie")
+            .flat_map(cookie::parse)
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+
+    /// The session set up by the [`Sessions`](crate::middleware::Sessions)
+    /// middleware; `None` without it.
+    pub fn session(&self) -> Option<&Session> {
+        self.session.as_ref()
+    }
+
+    /// The request body, empty if none was sent or it has not been read yet.
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Reads the body: as it arrives from the client when the handler
+    /// [streams it](crate::Handler::streams_body), otherwise from
+    /// [`body`](Request::body).
+    ///
+    /// A streamed body can be read only once. Reads past its deadline fail
+    /// with [`ErrorKind::TimedOut`](io::ErrorKind::TimedOut), bodies cut
+    /// short with [`ErrorKind::UnexpectedEof`](io::ErrorKind::UnexpectedEof).
+    pub fn body_reader(&mut self) -> Box<dyn Read + '_> {
+        if self.incoming.is_set() {
+            Box::new(&mut self.incoming)
+        } else {
+            Box::new(&self.body[..])
+        }
+    }
+
+    /// The id of the connection the request arrived on; nil for requests
+    /// that were not read from a connection.
+    pub fn connection_id(&self) -> Uuid {
+        self.connection_id
+    }
+
+    /// The position of the request on its connection, starting at 1.
+    pub fn sequence(&self) -> usize {
+        self.sequence
+    }
+
+    /// How the body is delimited, as announced by the head.
+    pub fn body_framing(&self) -> BodyFraming {
+        self.framing
+    }
+
+    /// The announced body length, if `Content-Length` was sent.
+    pub fn content_length(&self) -> Option<u64> {
+        match self.framing {
+            BodyFraming::Length(length) => Some(length),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if the client sent `Expect: 100-continue` and waits
+    /// for an interim response before sending the body.
+    pub fn expects_continue(&self) -> bool {
+        self.expects_continue && self.framing != BodyFraming::None
+    }
+
+    /// Reads the body that follows the head from `reader`.
+    ///
+    /// Bodies longer than `max_size` bytes are refused with
+    /// [`ParseError::PayloadTooLarge`].
+    pub fn read_body<R: BufRead>(
+        &mut self,
+        reader: &mut R,
+        max_size: usize,
+    ) -> Result<(), ParseError> {
+        match self.framing {
+            BodyFraming::None => {}
+            BodyFraming::Length(length) => {
+                if length > max_size as u64 {
+                    return Err(ParseError::PayloadTooLarge);
+                }
+                let mut body = vec![0; length as usize];
+                reader.read_exact(&mut body).map_err(body_error)?;
+                self.body = body;
+            }
+            BodyFraming::Chunked => {
+                let mut body = Vec::new();
+                ChunkedReader::new(&mut *reader)
+                    .take(max_size as u64 + 1)
+                    .read_to_end(&mut body)
+                    .map_err(body_error)?;
+                if body.len() > max_size {
+                    return Err(ParseError::PayloadTooLarge);
+                }
+                self.body = body;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub(crate) enum Line {
+    Eof,
+    TooLong,
+    Data(Vec<u8>),
+}
+
+/// Reads one CRLF (or bare LF) terminated line of at most `limit` bytes.
+pub(crate) fn read_line<R: BufRead>(reader: &mut R, limit: usize) -> Result<Line, ParseError> {
+    let mut buf = Vec::new();
+    let max = limit as u64 + 2; // room for the line ending
+    reader.by_ref().take(max).read_until(b'\n', &mut buf)?;
+
+    if buf.is_empty() {
+        return Ok(Line::Eof);
+    }
+    if buf.last() != Some(&b'\n') {
+        if buf.len() as u64 == max {
+            return Ok(Line::TooLong);
+        }
+        return Err(ParseError::BadRequest("unexpected end of request"));
+    }
+
+    buf.pop();
+    if buf.last() == Some(&b'\r') {
+        buf.pop();
+    }
+    if buf.len() > limit {
+        return Ok(Line::TooLong);
+    }
+    Ok(Line::Data(buf))
+}
+
+fn parse_version(version: &str) -> Result<Version, ParseError> {
+    match version {
+        "HTTP/1.1" => Ok(Version::Http11),
+        "HTTP/1.0" => Ok(Version::Http10),
+        _ => {
+            let digits = version.strip_prefix("HTTP/").map(|rest| rest.as_bytes());
+            match digits {
+                Some([major, b'.', minor]) if major.is_ascii_digit() && minor.is_ascii_digit() => {
+                    Err(ParseError::VersionNotSupported(version.to_owned()))
+                }
+                Some([major]) if major.is_ascii_digit() => {
+                    Err(ParseError::VersionNotSupported(version.to_owned()))
+                }
+                _ => Err(ParseError::BadRequest("malformed HTTP version")),
+            }
+        }
+    }
+}
+
+/// Splits a request-target into its decoded path and raw query string.
+fn parse_target(method: Method, target: &str) -> Result<(String, Option<String>), ParseError> {
+    if target == "*" {
+        return if method == Method::Options {
+            Ok(("*".to_owned(), None))
+        } else {
+            Err(ParseError::BadRequest(
+                "asterisk-form is only allowed for OPTIONS",
+            ))
+        };
+    }
+
+    let origin = if target.starts_with('/') {
+        target
+    } else if let Some(rest) = target
+        .strip_prefix("http://")
+        .or_else(|| target.strip_prefix("https://"))
+    {
+        // absolute-form: drop the authority, keep path and query
+        match rest.find(['/', '?']) {
+            Some(start) => &rest[start..],
+            None => "/",
+        }
+    } else {
+        return Err(ParseError::BadRequest("malformed request-target"));
+    };
+
+    if origin.bytes().any(|b| b.is_ascii_control()) {
+        return Err(ParseError::BadRequest(
+            "control character in request-target",
+        ));
+    }
+
+    let (raw_path, query) = match origin.split_once('?') {
+        Some((path, query)) => (path, Some(query.to_owned())),
+        None => (origin, None),
+    };
+    let raw_path = if raw_path.is_empty() { "/" } else { raw_path };
+    let path = url::percent_decode(raw_path)
+        .ok_or(ParseError::BadRequest("malformed percent-encoding"))?;
+
+    Ok((path, query))
+}
+
+/// Works out the body framing from `Transfer-Encoding` and `Content-Length`.
+fn parse_framing(headers: &Headers) -> Result<BodyFraming, ParseError> {
+    if headers.contains("Transfer-Encoding") {
+        // Both headers together are a request smuggling vector.
+        if headers.contains("Content-Length") {
+            return Err(ParseError::BadRequest(
+                "both Transfer-Encoding and Content-Length",
+            ));
+        }
+        let codings: Vec<String> = headers
+            .get_all("Transfer-Encoding")
+            .flat_map(|value| value.split(','))
+            .map(|coding| coding.trim().to_ascii_lowercase())
+            .filter(|coding| !coding.is_empty())
+            .collect();
+        return match codings.as_slice() {
+            [coding] if coding == "chunked" => Ok(BodyFraming::Chunked),
+            _ => Err(ParseError::NotImplemented("transfer coding")),
+        };
+    }
+
+    let mut length = None;
+    for value in headers
+        .get_all("Content-Length")
+        .flat_map(|value| value.split(','))
+    {
+        let value = value.trim();
+        if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(ParseError::BadRequest("malformed Content-Length"));
+        }
+        let value: u64 = value
+            .parse()
+            .map_err(|_| ParseError::BadRequest("malformed Content-Length"))?;
+        if length.is_some_and(|length| length != value) {
+            return Err(ParseError::BadRequest("conflicting Content-Length values"));
+        }
+        length = Some(value);
+    }
+
+    Ok(match length {
+        None | Some(0) => BodyFraming::None,
+        Some(length) => BodyFraming::Length(length),
+    })
+}
+
+fn body_error(e: io::Error) -> ParseError {
+    match e.kind() {
+        io::ErrorKind::InvalidData => ParseError::BadRequest("malformed chunked body"),
+        io::ErrorKind::UnexpectedEof => ParseError::BadRequest("body shorter than announced"),
+        _ => ParseError::Io(e),
+    }
+}
+
+pub(crate) fn parse_header(line: &[u8]) -> Result<(String, String), ParseError> {
+    if line[0] == b' ' || line[0] == b'\t' {
+        return Err(ParseError::BadRequest("obsolete header line folding"));
+    }
+    let colon = line
+        .iter()
+        .position(|&b| b == b':')
+        .ok_or(ParseError::BadRequest("header line without colon"))?;
+    let (name, value) = (&line[..colon], &line[colon + 1..]);
+
+    if name.is_empty() || !name.iter().copied().all(is_token_byte) {
+        return Err(ParseError::BadRequest("malformed header name"));
+    }
+    let name = String::from_utf8_lossy(name).into_owned();
+    let value = String::from_utf8_lossy(value)
+        .trim_matches([' ', '\t'])
+        .to_owned();
+
+    Ok((name, value))
+}
+
+/// `tchar` from RFC 9110.
+pub(crate) fn is_token_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+/// Checks a `Host` value: a host name or IP literal with an optional port.
+/// Empty is allowed for targets without an authority.
+fn is_valid_host(host: &str) -> bool {
+    host.bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b"-._~%!$&'()*+,;=:[]".contains(&b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &str) -> Result<Request, ParseError> {
+        Request::parse(&mut raw.as_bytes(), &ParseLimits::default())
+    }
+
+    #[test]
+    fn parses_request_line_and_headers() {
+        let request = parse(
+            "GET /users/42%20x?sort=name&q=a+b HTTP/1.1\r\nHost: example\r\nX-Thing:  1 \r\n\r\n",
+        )
+        .unwrap();
+
+        assert_eq!(request.method(), Method::Get);
+        assert_eq!(request.path(), "/users/42 x");
+        assert_eq!(request.query(), Some("sort=name&q=a+b"));
+        assert_eq!(request.query_param("q").as_deref(), Some("a b"));
+        assert_eq!(request.version(), Version::Http11);
+        assert_eq!(request.header("host"), Some("example"));
+        assert_eq!(request.header("x-thing"), Some("1"));
+    }
+
+    #[test]
+    fn accepts_absolute_form() {
+        let request = parse("GET http://example.com HTTP/1.0\r\n\r\n").unwrap();
+        assert_eq!(request.path(), "/");
+        assert_eq!(request.version(), Version::Http10);
+    }
+
+    #[test]
+    fn reads_length_and_chunked_bodies() {
+        let limits = ParseLimits::default();
+        let mut reader = "POST /a HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello\
+                          POST /b HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n"
+            .as_bytes();
+
+        let mut first = Request::parse(&mut reader, &limits).unwrap();
+        first.read_body(&mut reader, 1024).unwrap();
+        assert_eq!(first.body(), b"hello");
+
+        let mut second = Request::parse(&mut reader, &limits).unwrap();
+        assert_eq!(second.body_framing(), BodyFraming::Chunked);
+        second.read_body(&mut reader, 1024).unwrap();
+        assert_eq!(second.body(), b"abc");
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn refuses_oversized_bodies() {
+        let mut reader =
+            "PUT / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n8\r\n12345678\r\n0\r\n\r\n"
+                .as_bytes();
+        let mut request = Request::parse(&mut reader, &ParseLimits::default()).unwrap();
+
+        let err = request.read_body(&mut reader, 4).unwrap_err();
+        assert_eq!(err.status(), Some(StatusCode::CONTENT_TOO_LARGE));
+    }
+
+    #[test]
+    fn maps_errors_to_status_codes() {
+        let status = |raw: &str| parse(raw).unwrap_err().status().map(|s| s.as_u16());
+
+        assert_eq!(status("GET /\r\n\r\n"), Some(400));
+        assert_eq!(status("BREW / HTTP/1.1\r\n\r\n"), Some(405));
+        assert_eq!(status("GET / HTTP/2.0\r\n\r\n"), Some(505));
+        assert_eq!(status("GET / HTTP/1.1\r\nBad Header\r\n\r\n"), Some(400));
+        assert_eq!(status(""), None);
+        assert_eq!(
+            status("POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: x\r\n\r\n"),
+            Some(400)
+        );
+        assert_eq!(
+            status("POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: gzip\r\n\r\n"),
+            Some(501)
+        );
+        assert_eq!(
+            status("POST / HTTP/1.1\r\nHost: localhost\r\nExpect: tea\r\n\r\n"),
+            Some(417)
+        );
+
+        let long = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(9000));
+        assert_eq!(status(&long), Some(414));
+
+        let many = format!("GET / HTTP/1.1\r\n{}\r\n", "A: b\r\n".repeat(101));
+        assert_eq!(status(&many), Some(431));
+    }
+
+    #[test]
+    fn requires_one_valid_host() {
+        let status = |raw: &str| {
+            parse(raw)
+                .err()
+                .and_then(|e| e.status())
+                .map(|s| s.as_u16())
+        };
+
+        assert_eq!(status("GET / HTTP/1.1\r\n\r\n"), Some(400));
+        assert_eq!(status("GET / HTTP/1.0\r\n\r\n"), None);
+        assert_eq!(status("GET / HTTP/1.1\r\nHost: [::1]:8080\r\n\r\n"), None);
+        assert_eq!(
+            status("GET / HTTP/1.1\r\nHost: a.test\r\nHost: b.test\r\n\r\n"),
+            Some(400)
+        );
+        assert_eq!(
+            status("GET / HTTP/1.1\r\nHost: a.test/x\r\n\r\n"),
+            Some(400)
+        );
+    }
+}
@@ -0,0 +1,308 @@
+//! HTTP responses built by handlers.
+
+use std::{
+    fmt,
+    io::{self, BufReader, Read, Write},
+};
+
+use crate::{
+    body::Body,
+    chunked::ChunkedWriter,
+    cookie::Cookie,
+    headers::Headers,
+    request::{Method, Version},
+    status::StatusCode,
+    stream::Stream,
+};
+
+/// How much of a streamed body is read before it is sent as one chunk.
+const STREAM_BUFFER_SIZE: usize = 16 * 1024;
+
+/// An HTTP response: status, header fields and body.
+#[derive(Debug)]
+pub struct Response {
+    status: StatusCode,
+    headers: Headers,
+    body: Body,
+    upgrade: Option<Upgrade>,
+}
+
+/// Takes over the connection once a `101 Switching Protocols` response has
+/// been sent, with whatever the client already sent after the request still
+/// in the buffer.
+pub(crate) struct Upgrade(pub(crate) Box<dyn FnOnce(BufReader<Stream>) + Send>);
+
+impl fmt::Debug for Upgrade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Upgrade")
+    }
+}
+
+impl Response {
+    /// Creates an empty response with the given status.
+    pub fn new(status: StatusCode) -> Response {
+        Response {
+            status,
+            headers: Headers::new(),
+            body: Body::Empty,
+            upgrade: None,
+        }
+    }
+
+    /// Creates a `text/html` response.
+    pub fn html(status: StatusCode, body: impl Into<Body>) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", "text/html; charset=utf-8")
+            .with_body(body)
+    }
+
+    /// Creates a `text/plain` response.
+    pub fn text(status: StatusCode, body: impl Into<Body>) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_body(body)
+    }
+
+    /// Creates an `application/json` response from serialized JSON.
+    pub fn json(status: StatusCode, body: impl Into<Body>) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", "application/json")
+            .with_body(body)
+    }
+
+    /// Creates a plain-text response whose body is the reason phrase,
+    /// e.g. `400 Bad Request`.
+    pub fn error(status: StatusCode) -> Response {
+        Response::text(status, status.to_string())
+    }
+
+    /// Sets the header `name`, replacing previous values.
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Response {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Adds a `Set-Cookie` header for `cookie`, keeping earlier ones.
+    pub fn with_cookie(mut self, cookie: &Cookie) -> Response {
+        self.headers.append("Set-Cookie", cookie.to_string());
+        self
+    }
+
+    /// Replaces the body.
+    pub fn with_body(mut self, body: impl Into<Body>) -> Response {
+        self.body = body.into();
+        self
+    }
+
+    /// The status code.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// Changes the status code.
+    pub fn set_status(&mut self, status: StatusCode) {
+        self.status = status;
+    }
+
+    /// The response header fields.
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    /// Mutable access to the response header fields.
+    pub fn headers_mut(&mut self) -> &mut Headers {
+        &mut self.headers
+    }
+
+    /// The response body.
+    pub fn body(&self) -> &Body {
+        &self.body
+    }
+
+    /// Replaces the body, returning the previous one.
+    pub fn replace_body(&mut self, body: impl Into<Body>) -> Body {
+        std::mem::replace(&mut self.body, body.into())
+    }
+
+    /// Takes the body out of the response.
+    pub fn into_body(self) -> Body {
+        self.body
+    }
+
+    /// Hands the connection to `upgrade` after this response, which should
+    /// be a `101 Switching Protocols`.
+    pub(crate) fn with_upgrade<F>(mut self, upgrade: F) -> Response
+    where
+        F: FnOnce(BufReader<Stream>) + Send + 'static,
+    {
+        self.upgrade = Some(Upgrade(Box::new(upgrade)));
+        self
+    }
+
+    /// Takes out the protocol that takes over the connection, if any.
+    pub(crate) fn take_upgrade(&mut self) -> Option<Upgrade> {
+        self.upgrade.take()
+    }
+
+    /// Returns `true` if the status code forbids a body (1xx, 204 and 304).
+    fn is_bodiless(&self) -> bool {
+        self.status.is_informational() || matches!(self.status.as_u16(), 204 | 304)
+    }
+
+    /// Serializes the response onto `writer` as the answer to a `method`
+    /// request from a `version` client, returning the number of body bytes
+    /// written.
+    ///
+    /// Bodies of known length get `Content-Length`. Streams are chunked for
+    /// HTTP/1.1 clients and written raw for HTTP/1.0 ones, in which case the
+    /// caller has to close the connection afterwards. `HEAD` requests get the
+    /// head only.
+    pub fn write_to<W: Write>(
+        self,
+        writer: &mut W,
+        method: Method,
+        version: Version,
+    ) -> io::Result<u64> {
+        let bodiless = self.is_bodiless();
+        let length = self.body.len();
+        let chunked = !bodiless && length.is_none() && version == Version::Http11;
+
+        let mut head = format!("HTTP/1.1 {}\r\n", self.status);
+        for (name, value) in self.headers.iter() {
+            if name.eq_ignore_ascii_case("Content-Length")
+                || name.eq_ignore_ascii_case("Transfer-Encoding")
+            {
+                continue;
+            }
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        if chunked {
+            head.push_str("Transfer-Encoding: chunked\r\n");
+        } else if let (false, Some(length)) = (bodiless, length) {
+            head.push_str(&format!("Content-Length: {length}\r\n"));
+        }
+        head.push_str("\r\n");
+
+        if bodiless || method == Method::Head {
+            writer.write_all(head.as_bytes())?;
+            writer.flush()?;
+            return Ok(0);
+        }
+        // Bodies in memory go out right behind the head and small ones in the
+        // same write, so they don't take two segments and wait on delayed
+        // ACKs.
+        if let Body::Bytes(bytes) = &self.body {
+            if bytes.len() <= STREAM_BUFFER_SIZE {
+                let mut out = head.into_bytes();
+                out.extend_from_slice(bytes);
+                writer.write_all(&out)?;
+            } else {
+                writer.write_all(head.as_bytes())?;
+                writer.write_all(bytes)?;
+            }
+            writer.flush()?;
+            return Ok(bytes.len() as u64);
+        }
+        writer.write_all(head.as_bytes())?;
+
+        let mut reader = self.body.into_reader();
+        let written = if chunked {
+            let mut chunked_writer = ChunkedWriter::new(&mut *writer);
+            let written = stream(&mut reader, &mut chunked_writer)?;
+            chunked_writer.finish()?;
+            written
+        } else {
+            stream(&mut reader, writer)?
+        };
+        writer.flush()?;
+        // Stopping short would leave the client waiting for the rest, or
+        // reading the next response as part of this one.
+        if let Some(length) = length
+            && written != length
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("body ended after {written} of {length} bytes"),
+            ));
+        }
+        Ok(written)
+    }
+}
+
+/// Copies `reader` to `writer`, flushing after every piece so streamed
+/// events reach the client without delay.
+fn stream<W: Write>(reader: &mut dyn Read, writer: &mut W) -> io::Result<u64> {
+    let mut buf = vec![0; STREAM_BUFFER_SIZE];
+    let mut written = 0;
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => return Ok(written),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        writer.write_all(&buf[..n])?;
+        writer.flush()?;
+        written += n as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Seek;
+
+    /// Records every `write` call separately.
+    #[derive(Default)]
+    struct Writes(Vec<Vec<u8>>);
+
+    impl Write for Writes {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.push(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn writes_small_responses_at_once() {
+        let mut writes = Writes::default();
+        let response = Response::text(StatusCode::OK, "hello");
+        assert_eq!(
+            response
+                .write_to(&mut writes, Method::Get, Version::Http11)
+                .unwrap(),
+            5
+        );
+        assert_eq!(writes.0.len(), 1);
+        assert!(writes.0[0].ends_with(b"Content-Length: 5\r\n\r\nhello"));
+    }
+
+    #[test]
+    fn sends_files_from_their_position_and_checks_their_length() {
+        let path = std::env::temp_dir().join(format!("body-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "0123456789").unwrap();
+        let mut file = std::fs::File::open(&path).unwrap();
+        file.seek(io::SeekFrom::Start(4)).unwrap();
+
+        let mut out = Vec::new();
+        let response = Response::new(StatusCode::OK).with_body(Body::file(file).unwrap());
+        response
+            .write_to(&mut out, Method::Get, Version::Http11)
+            .unwrap();
+        assert!(out.ends_with(b"Content-Length: 6\r\n\r\n456789"));
+
+        // The file shrank after its length was taken.
+        let file = std::fs::File::open(&path).unwrap();
+        let response = Response::new(StatusCode::OK).with_body(Body::File { file, len: 20 });
+        let error = response
+            .write_to(&mut Vec::new(), Method::Get, Version::Http11)
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,334 @@
+//! Dispatching requests to handlers by method and path pattern.
+
+use std::{fs, sync::Arc};
+
+use crate::{
+    handler::Handler,
+    request::{Method, Request},
+    response::Response,
+    status::StatusCode,
+};
+
+/// Routes requests to handlers registered by method and path pattern.
+///
+/// Patterns are made of `/`-separated segments:
+///
+/// * `users` matches the literal segment,
+/// * `:id` matches any single non-empty segment and captures it as `id`,
+/// * `*path` matches the rest of the path (possibly empty) and must come last.
+///
+/// Captures are available to handlers through [`Request::param`]. A `HEAD`
+/// request is served by the `GET` route when no `HEAD` route exists.
+///
+/// # Examples
+///
+/// ```
+/// use webserver::{Request, Response, Router, StatusCode};
+///
+/// let router = Router::new().get("/users/:id", |request: &mut Request| {
+///     let id = request.param("id").unwrap_or_default().to_owned();
+///     Response::text(StatusCode::OK, id)
+/// });
+/// ```
+pub struct Router {
+    routes: Vec<Route>,
+    not_found: Box<dyn Handler>,
+}
+
+struct Route {
+    method: Method,
+    pattern: Pattern,
+    handler: Box<dyn Handler>,
+}
+
+impl Router {
+    /// Creates a router without routes. Unmatched requests get `assets/404.html`.
+    pub fn new() -> Router {
+        Router {
+            routes: Vec::new(),
+            not_found: Box::new(not_found),
+        }
+    }
+
+    /// Registers `handler` for `method` requests matching `pattern`.
+    ///
+    /// Routes are tried in registration order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pattern` does not start with `/` or has a wildcard segment
+    /// that is not the last one.
+    pub fn route<H: Handler + 'static>(
+        mut self,
+        method: Method,
+        pattern: &str,
+        handler: H,
+    ) -> Router {
+        self.routes.push(Route {
+            method,
+            pattern: Pattern::parse(pattern),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    /// Registers a `GET` route.
+    pub fn get<F>(self, pattern: &str, handler: F) -> Router
+    where
+        F: Fn(&mut Request) -> Response + Send + Sync + 'static,
+    {
+        self.route(Method::Get, pattern, handler)
+    }
+
+    /// Registers a `POST` route.
+    pub fn post<F>(self, pattern: &str, handler: F) -> Router
+    where
+        F: Fn(&mut Request) -> Response + Send + Sync + 'static,
+    {
+        self.route(Method::Post, pattern, handler)
+    }
+
+    /// Registers a `PUT` route.
+    pub fn put<F>(self, pattern: &str, handler: F) -> Router
+    where
+        F: Fn(&mut Request) -> Response + Send + Sync + 'static,
+    {
+        self.route(Method::Put, pattern, handler)
+    }
+
+    /// Registers a `DELETE` route.
+    pub fn delete<F>(self, pattern: &str, handler: F) -> Router
+    where
+        F: Fn(&mut Request) -> Response + Send + Sync + 'static,
+    {
+        self.route(Method::Delete, pattern, handler)
+    }
+
+    /// Registers `handler` for requests with any method, e.g. a
+    /// [`Proxy`](crate::Proxy).
+    pub fn any<H: Handler + 'static>(mut self, pattern: &str, handler: H) -> Router {
+        let handler = Arc::new(handler);
+        for method in [
+            Method::Get,
+            Method::Head,
+            Method::Post,
+            Method::Put,
+            Method::Delete,
+            Method::Options,
+            Method::Patch,
+            Method::Trace,
+            Method::Connect,
+        ] {
+            self = self.route(method, pattern, Arc::clone(&handler));
+        }
+        self
+    }
+
+    /// Replaces the handler used when no route matches the path.
+    pub fn not_found<H: Handler + 'static>(mut self, handler: H) -> Router {
+        self.not_found = Box::new(handler);
+        self
+    }
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Router::new()
+    }
+}
+
+impl Handler for Router {
+    fn handle(&self, request: &mut Request) -> Response {
+        let method = request.method();
+        let mut allowed = Vec::new();
+        let mut get_fallback = None;
+
+        for route in &self.routes {
+            let Some(params) = route.pattern.matches(request.path()) else {
+                continue;
+            };
+            if route.method == method {
+                request.params = params;
+                request.route = Some(route.pattern.source.clone());
+                return route.handler.handle(request);
+            }
+            if method == Method::Head && route.method == Method::Get && get_fallback.is_none() {
+                get_fallback = Some((route, params));
+            }
+            allowed.push(route.method);
+        }
+
+        if let Some((route, params)) = get_fallback {
+            request.params = params;
+            request.route = Some(route.pattern.source.clone());
+            return route.handler.handle(request);
+        }
+
+        if allowed.is_empty() {
+            return self.not_found.handle(request);
+        }
+
+        if allowed.contains(&Method::Get) {
+            allowed.push(Method::Head);
+        }
+        let mut names: Vec<&str> = allowed.iter().map(Method::as_str).collect();
+        names.sort_unstable();
+        names.dedup();
+
+        Response::error(StatusCode::METHOD_NOT_ALLOWED).with_header("Allow", names.join(", "))
+    }
+
+    /// Asks the route `handle` would pick; its captures are not set yet.
+    fn streams_body(&self, request: &Request) -> bool {
+        self.routes
+            .iter()
+            .find(|route| {
+                route.method == request.method() && route.pattern.matches(request.path()).is_some()
+            })
+            .is_some_and(|route| route.handler.streams_body(request))
+    }
+}
+
+/// Serves `assets/404.html`, or a plain-text body if the page is missing.
+pub(crate) fn not_found(_request: &mut Request) -> Response {
+    match fs::read("assets/404.html") {
+        Ok(contents) => Response::html(StatusCode::NOT_FOUND, contents),
+        Err(_) => Response::error(StatusCode::NOT_FOUND),
+    }
+}
+
+enum Segment {
+    Literal(String),
+    Param(String),
+    Wildcard(String),
+}
+
+struct Pattern {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+impl Pattern {
+    fn parse(pattern: &str) -> Pattern {
+        let rest = pattern
+            .strip_prefix('/')
+            .unwrap_or_else(|| panic!("route pattern {pattern:?} must start with '/'"));
+
+        let parts: Vec<&str> = rest.split('/').collect();
+        let segments = parts
+            .iter()
+            .enumerate()
+            .map(|(i, part)| {
+                if let Some(name) = part.strip_prefix(':') {
+                    Segment::Param(name.to_owned())
+                } else if let Some(name) = part.strip_prefix('*') {
+                    assert!(
+                        i == parts.len() - 1,
+                        "wildcard must be the last segment of {pattern:?}"
+                    );
+                    Segment::Wildcard(name.to_owned())
+                } else {
+                    Segment::Literal((*part).to_owned())
+                }
+            })
+            .collect();
+
+        Pattern {
+            source: pattern.to_owned(),
+            segments,
+        }
+    }
+
+    /// Returns the captured parameters if `path` matches.
+    fn matches(&self, path: &str) -> Option<Vec<(String, String)>> {
+        let rest = path.strip_prefix('/')?;
+        let mut parts = rest.split('/');
+        let mut params = Vec::new();
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Wildcard(name) => {
+                    let remainder: Vec<&str> = parts.by_ref().collect();
+                    params.push((name.clone(), remainder.join("/")));
+                    return Some(params);
+                }
+                Segment::Literal(literal) => {
+                    if parts.next()? != literal {
+                        return None;
+                    }
+                }
+                Segment::Param(name) => {
+                    let part = parts.next().filter(|part| !part.is_empty())?;
+                    params.push((name.clone(), part.to_owned()));
+                }
+            }
+        }
+
+        match parts.next() {
+            None => Some(params),
+            Some(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::ParseLimits;
+
+    fn request(method: &str, path: &str) -> Request {
+        let raw = format!("{method} {path} HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        Request::parse(&mut raw.as_bytes(), &ParseLimits::default()).unwrap()
+    }
+
+    fn echo_param(name: &'static str) -> impl Fn(&mut Request) -> Response {
+        move |request: &mut Request| {
+            Response::text(
+                StatusCode::OK,
+                request.param(name).unwrap_or("-").to_owned(),
+            )
+        }
+    }
+
+    fn router() -> Router {
+        Router::new()
+            .get("/users/:id", echo_param("id"))
+            .get("/static/*path", echo_param("path"))
+            .post("/users", |_: &mut Request| {
+                Response::new(StatusCode::CREATED)
+            })
+            .not_found(|_: &mut Request| Response::error(StatusCode::NOT_FOUND))
+    }
+
+    #[test]
+    fn captures_params_and_wildcards() {
+        let router = router();
+
+        let response = router.handle(&mut request("GET", "/users/42"));
+        assert_eq!(response.body().bytes(), Some(&b"42"[..]));
+
+        let response = router.handle(&mut request("GET", "/static/css/site.css"));
+        assert_eq!(response.body().bytes(), Some(&b"css/site.css"[..]));
+
+        let response = router.handle(&mut request("HEAD", "/users/7"));
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn answers_405_with_allow_header() {
+        let response = router().handle(&mut request("DELETE", "/users"));
+
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers().get("Allow"), Some("POST"));
+    }
+
+    #[test]
+    fn falls_back_to_not_found() {
+        let router = router();
+
+        for path in ["/users/", "/users/1/posts", "/nope"] {
+            let response = router.handle(&mut request("GET", path));
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{path}");
+        }
+    }
+}
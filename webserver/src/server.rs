@@ -0,0 +1,635 @@
+//! The server: accept loop, worker pool and graceful shutdown.
+
+use std::{
+    collections::HashMap,
+    io,
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        Arc, Condvar, Mutex, MutexGuard, PoisonError,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use uuid::Uuid;
+
+use crate::{
+    ThreadPool,
+    acceptor::{Accepted, Acceptor, Waker},
+    admin::{self, Health},
+    config::ServerConfig,
+    connection::{ConnectionConfig, serve_connection},
+    handler::Handler,
+    log::{debug, error, warn},
+    metrics::Metrics,
+    middleware::{BasicAuth, Chain},
+    request::{Method, Request, Version},
+    response::Response,
+    status::StatusCode,
+    stream::Stream,
+};
+
+/// Admin connections served at once unless
+/// [`Server::max_admin_connections`] says otherwise.
+pub const DEFAULT_MAX_ADMIN_CONNECTIONS: usize = 8;
+
+/// Accepts connections and hands them to a [`ThreadPool`].
+///
+/// [`run`](Server::run) blocks until a [`ShutdownHandle`] asks it to stop,
+/// then [`shutdown`](Server::shutdown) drains the connections still open.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use webserver::{Router, Server, ServerConfig};
+///
+/// let config = ServerConfig::default();
+/// let server = Server::bind(&config, Router::new()).unwrap();
+///
+/// let handle = server.handle();
+/// // e.g. from a signal handler:
+/// handle.shutdown();
+///
+/// server.run().unwrap();
+/// let report = server.shutdown(Duration::from_secs(10));
+/// println!("{} completed, {} aborted", report.completed, report.aborted);
+/// ```
+pub struct Server {
+    acceptor: Acceptor,
+    /// What each of the acceptor's listeners serves, by index.
+    kinds: Vec<ListenerKind>,
+    waker: Waker,
+    pool: ThreadPool,
+    handler: Arc<dyn Handler>,
+    redirect: Option<Arc<HttpsRedirect>>,
+    admin: Option<Arc<dyn Handler>>,
+    /// Admin connections open, each on its own thread.
+    admin_connections: Arc<AtomicUsize>,
+    max_admin_connections: usize,
+    connection_config: Arc<ConnectionConfig>,
+    tracker: Arc<Tracker>,
+    max_connections: Option<usize>,
+}
+
+/// A bound socket and what the [`Server`] speaks on it.
+pub enum Listener {
+    /// Plain HTTP.
+    Http(TcpListener),
+    /// Plain HTTP that redirects every request to HTTPS on `https_port`.
+    RedirectToHttps {
+        listener: TcpListener,
+        https_port: u16,
+    },
+    /// HTTPS.
+    #[cfg(feature = "tls")]
+    Https {
+        listener: TcpListener,
+        tls: Arc<rustls::ServerConfig>,
+    },
+    /// The admin endpoints, each connection on its own thread so they answer
+    /// even when every worker is busy; at most
+    /// [`Server::max_admin_connections`] at once. Bind it to a private
+    /// address, or protect it with [`Server::admin_auth`].
+    Admin(TcpListener),
+}
+
+#[derive(Clone)]
+enum ListenerKind {
+    Http,
+    Redirect,
+    Admin,
+    #[cfg(feature = "tls")]
+    Https(Arc<rustls::ServerConfig>),
+}
+
+/// Asks a running [`Server`] to stop accepting connections.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    waker: Waker,
+    tracker: Arc<Tracker>,
+}
+
+/// What happened to the requests in flight during [`Server::shutdown`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShutdownReport {
+    /// Requests that finished after shutdown began.
+    pub completed: usize,
+    /// Requests cut off when the deadline passed.
+    pub aborted: usize,
+}
+
+impl Server {
+    /// Binds the configured addresses and starts the worker pool.
+    ///
+    /// With `tls_bind` set (and the `tls` feature enabled) an HTTPS listener
+    /// runs next to the plain one. With `metrics` set, the handler is wrapped
+    /// in a [`Metrics`] layer serving `metrics_path`, and with
+    /// `health_checks` in a [`Health`] layer. `admin_bind` adds the admin
+    /// listener, behind basic authentication with `admin_auth_file`. At most
+    /// `max_connections` are served at once.
+    pub fn bind<H: Handler + 'static>(config: &ServerConfig, handler: H) -> io::Result<Server> {
+        let http = TcpListener::bind(config.bind)?;
+        #[cfg(feature = "tls")]
+        let listeners = match config.tls_bind {
+            Some(tls_bind) => {
+                let tls = config.tls_config().map_err(io::Error::other)?;
+                let listener = TcpListener::bind(tls_bind)?;
+                let http = if config.redirect_to_https {
+                    Listener::RedirectToHttps {
+                        listener: http,
+                        https_port: listener.local_addr()?.port(),
+                    }
+                } else {
+                    Listener::Http(http)
+                };
+                vec![http, Listener::Https { listener, tls }]
+            }
+            None => vec![Listener::Http(http)],
+        };
+        #[cfg(not(feature = "tls"))]
+        let listeners = vec![Listener::Http(http)];
+        let admin = config.admin_bind.map(TcpListener::bind).transpose()?;
+        let listeners = listeners
+            .into_iter()
+            .chain(admin.map(Listener::Admin))
+            .collect();
+
+        let mut connection_config = config.connection_config();
+        connection_config.access_log = config.access_log()?.map(Arc::new);
+        let handler: Arc<dyn Handler> = if config.metrics {
+            let metrics = Arc::new(Metrics::new());
+            connection_config.metrics = Some(Arc::clone(&metrics));
+            Arc::new(Chain::new(handler).with(metrics.layer(&config.metrics_path)))
+        } else {
+            Arc::new(handler)
+        };
+        let mut server =
+            Server::from_listeners(listeners, config.workers, connection_config, handler)?
+                .max_connections(config.max_connections);
+
+        if config.health_checks {
+            let health = Health::new(server.handle());
+            server.handler = Arc::new(Chain::new(Arc::clone(&server.handler)).with(health));
+        }
+        if server.admin.is_some() {
+            let router =
+                admin::router(server.handle(), server.pool.stats(), Some(config.to_json()));
+            server.admin = Some(Arc::new(router));
+            if let Some(path) = &config.admin_auth_file {
+                server = server.admin_auth(BasicAuth::from_file("admin", path)?);
+            }
+        }
+        Ok(server)
+    }
+
+    /// Serves plain HTTP from an already bound `listener`.
+    pub fn from_listener<H: Handler + 'static>(
+        listener: TcpListener,
+        workers: usize,
+        connection_config: ConnectionConfig,
+        handler: H,
+    ) -> io::Result<Server> {
+        Server::from_listeners(
+            vec![Listener::Http(listener)],
+            workers,
+            connection_config,
+            handler,
+        )
+    }
+
+    /// Serves connections from several already bound listeners at once.
+    pub fn from_listeners<H: Handler + 'static>(
+        listeners: Vec<Listener>,
+        workers: usize,
+        connection_config: ConnectionConfig,
+        handler: H,
+    ) -> io::Result<Server> {
+        let mut redirect = None;
+        let mut has_admin = false;
+        let (sockets, kinds) = listeners
+            .into_iter()
+            .map(|listener| match listener {
+                Listener::Http(listener) => (listener, ListenerKind::Http),
+                Listener::RedirectToHttps {
+                    listener,
+                    https_port,
+                } => {
+                    redirect = Some(Arc::new(HttpsRedirect { https_port }));
+                    (listener, ListenerKind::Redirect)
+                }
+                #[cfg(feature = "tls")]
+                Listener::Https { listener, tls } => (listener, ListenerKind::Https(tls)),
+                Listener::Admin(listener) => {
+                    has_admin = true;
+                    (listener, ListenerKind::Admin)
+                }
+            })
+            .unzip();
+
+        let (acceptor, waker) = Acceptor::new(sockets)?;
+        let pool = ThreadPool::build(workers)
+            .map_err(|e| io::Error::other(format!("cannot start worker pool: {e:?}")))?;
+        if let Some(metrics) = &connection_config.metrics {
+            metrics.attach_pool(pool.stats());
+        }
+
+        let tracker = Arc::new(Tracker::default());
+        let admin = has_admin.then(|| {
+            let shutdown = ShutdownHandle {
+                waker: waker.clone(),
+                tracker: Arc::clone(&tracker),
+            };
+            Arc::new(admin::router(shutdown, pool.stats(), None)) as Arc<dyn Handler>
+        });
+
+        Ok(Server {
+            acceptor,
+            kinds,
+            waker,
+            pool,
+            handler: Arc::new(handler),
+            redirect,
+            admin,
+            admin_connections: Arc::new(AtomicUsize::new(0)),
+            max_admin_connections: DEFAULT_MAX_ADMIN_CONNECTIONS,
+            connection_config: Arc::new(connection_config),
+            tracker,
+            max_connections: None,
+        })
+    }
+
+    /// Refuses connections beyond `limit` open at once with
+    /// `503 Service Unavailable` and `Retry-After`, instead of queueing them
+    /// for the workers. 0 means no limit, the default. The admin listener
+    /// is exempt and does not count towards the limit.
+    pub fn max_connections(mut self, limit: usize) -> Server {
+        self.max_connections = (limit > 0).then_some(limit);
+        self
+    }
+
+    /// Refuses admin connections beyond `limit` open at once with
+    /// `503 Service Unavailable`, since each gets a thread of its own.
+    /// Defaults to [`DEFAULT_MAX_ADMIN_CONNECTIONS`].
+    pub fn max_admin_connections(mut self, limit: usize) -> Server {
+        self.max_admin_connections = limit;
+        self
+    }
+
+    /// Requires the users of `auth` on the admin listener, whose
+    /// `POST /shutdown` anyone reaching it could call otherwise.
+    pub fn admin_auth(mut self, auth: BasicAuth) -> Server {
+        if let Some(admin) = self.admin.take() {
+            self.admin = Some(Arc::new(Chain::new(admin).with(auth)));
+        }
+        self
+    }
+
+    /// The address of the first listener.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.acceptor.listeners()[0].local_addr()
+    }
+
+    /// The addresses of all listeners, in the order they were given.
+    pub fn local_addrs(&self) -> io::Result<Vec<SocketAddr>> {
+        self.acceptor
+            .listeners()
+            .iter()
+            .map(TcpListener::local_addr)
+            .collect()
+    }
+
+    /// The metrics this server reports to, if any.
+    pub fn metrics(&self) -> Option<&Arc<Metrics>> {
+        self.connection_config.metrics.as_ref()
+    }
+
+    /// Returns a handle that can stop the server from another thread.
+    pub fn handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            waker: self.waker.clone(),
+            tracker: Arc::clone(&self.tracker),
+        }
+    }
+
+    /// Accepts connections until [`ShutdownHandle::shutdown`] is called.
+    pub fn run(&self) -> io::Result<()> {
+        while !self.tracker.is_draining() {
+            let (stream, listener) = match self.acceptor.accept()? {
+                Accepted::Connection {
+                    stream, listener, ..
+                } => (stream, listener),
+                Accepted::Woken => continue,
+            };
+
+            let kind = self.kinds[listener].clone();
+            let is_admin = matches!(kind, ListenerKind::Admin);
+            let admin_open = self.admin_connections.load(Ordering::SeqCst);
+            if is_admin {
+                if admin_open >= self.max_admin_connections {
+                    debug!("Refusing an admin connection, {admin_open} already open");
+                    self.refuse(stream, &kind);
+                    continue;
+                }
+            } else if let Some(limit) = self.max_connections {
+                // Admin connections are tracked too, but don't take app slots.
+                let open = self.tracker.lock().len().saturating_sub(admin_open);
+                if open >= limit {
+                    debug!("Refusing a connection, {open} already open");
+                    self.refuse(stream, &kind);
+                    continue;
+                }
+            }
+
+            let connection_id = Uuid::new_v4();
+            if let Err(e) = self.tracker.register(connection_id, &stream) {
+                error!("[{connection_id}] Cannot track connection: {e}");
+                continue;
+            }
+
+            let handler: Arc<dyn Handler> = match (&kind, &self.redirect, &self.admin) {
+                (ListenerKind::Redirect, Some(redirect), _) => redirect.clone(),
+                (ListenerKind::Admin, _, Some(admin)) => admin.clone(),
+                _ => Arc::clone(&self.handler),
+            };
+            let config = Arc::clone(&self.connection_config);
+            let tracker = Arc::clone(&self.tracker);
+            // Moved into the job, so the connection is unregistered even if
+            // the job is dropped without running.
+            let registration = Registration {
+                tracker: Arc::clone(&tracker),
+                connection_id,
+            };
+            let job = move || {
+                let stream = match kind {
+                    ListenerKind::Http | ListenerKind::Redirect | ListenerKind::Admin => {
+                        Stream::Plain(stream)
+                    }
+                    #[cfg(feature = "tls")]
+                    ListenerKind::Https(tls) => match Stream::tls(stream, tls) {
+                        Ok(stream) => stream,
+                        Err(e) => {
+                            warn!("[{connection_id}] Cannot start TLS: {e}");
+                            return;
+                        }
+                    },
+                };
+                let upgraded =
+                    serve_connection(connection_id, stream, &*handler, &config, Some(&tracker));
+                // Upgraded connections live long; they get a thread of their
+                // own so they don't hold up a worker.
+                if let Some(upgraded) = upgraded {
+                    let spawned =
+                        thread::Builder::new()
+                            .name("upgraded".to_owned())
+                            .spawn(move || {
+                                let _registration = registration;
+                                upgraded.run();
+                            });
+                    if let Err(e) = spawned {
+                        error!(
+                            "[{connection_id}] Cannot start thread for upgraded connection: {e}"
+                        );
+                    }
+                }
+            };
+
+            if !is_admin {
+                self.pool.execute(connection_id, job);
+                continue;
+            }
+            // Released when the thread ends, or right away if it can't start.
+            let slot = AdminSlot::new(Arc::clone(&self.admin_connections));
+            let spawned = thread::Builder::new()
+                .name("admin".to_owned())
+                .spawn(move || {
+                    let _slot = slot;
+                    job();
+                });
+            if let Err(e) = spawned {
+                error!("[{connection_id}] Cannot start admin thread: {e}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Turns away a connection over the limit without tying up a worker.
+    fn refuse(&self, mut stream: TcpStream, kind: &ListenerKind) {
+        if let Some(metrics) = &self.connection_config.metrics {
+            metrics.connection_rejected();
+        }
+        // Plain text would be unreadable to TLS clients; they just see the
+        // connection close.
+        #[cfg(feature = "tls")]
+        if matches!(kind, ListenerKind::Https(_)) {
+            return;
+        }
+        #[cfg(not(feature = "tls"))]
+        let _ = kind;
+        let response = Response::error(StatusCode::SERVICE_UNAVAILABLE)
+            .with_header("Retry-After", "1")
+            .with_header("Connection", "close");
+        if stream
+            .set_write_timeout(Some(Duration::from_millis(100)))
+            .is_ok()
+        {
+            let _ = response.write_to(&mut stream, Method::Get, Version::Http11);
+        }
+        let _ = stream.shutdown(Shutdown::Write);
+    }
+
+    /// Drains the server after [`run`](Server::run) returned.
+    ///
+    /// No new connections are accepted. Requests in progress may finish,
+    /// idle keep-alive connections are closed right away and busy ones after
+    /// their current response. Upgraded connections see the client go away
+    /// and should wind down. Whatever is still open when `deadline` has
+    /// passed is shut down forcibly, and workers still stuck then are left
+    /// behind.
+    pub fn shutdown(self, deadline: Duration) -> ShutdownReport {
+        let expires = Instant::now() + deadline;
+        self.tracker.start_draining();
+
+        let mut connections = self.tracker.lock();
+        for tracked in connections.values().filter(|tracked| !tracked.busy) {
+            // Wakes workers waiting for the next keep-alive request.
+            let _ = tracked.stream.shutdown(Shutdown::Read);
+        }
+
+        while !connections.is_empty() {
+            let now = Instant::now();
+            if now >= expires {
+                break;
+            }
+            connections = self
+                .tracker
+                .closed
+                .wait_timeout(connections, expires - now)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+
+        let aborted = connections.values().filter(|tracked| tracked.busy).count();
+        for tracked in connections.values() {
+            let _ = tracked.stream.shutdown(Shutdown::Both);
+        }
+        drop(connections);
+
+        let stuck = self
+            .pool
+            .shutdown(expires.saturating_duration_since(Instant::now()) + Duration::from_secs(1));
+        if stuck > 0 {
+            warn!("{stuck} worker(s) did not stop in time, leaving them behind");
+        }
+
+        ShutdownReport {
+            completed: self.tracker.completed.load(Ordering::SeqCst),
+            aborted,
+        }
+    }
+}
+
+impl ShutdownHandle {
+    /// Stops accepting new connections; [`Server::run`] returns soon after.
+    pub fn shutdown(&self) {
+        self.tracker.start_draining();
+        self.waker.wake();
+    }
+
+    /// Returns `true` once shutdown has begun.
+    pub fn is_shutting_down(&self) -> bool {
+        self.tracker.is_draining()
+    }
+
+    /// Open connections, and how many of them are in the middle of a request.
+    pub(crate) fn connections(&self) -> (usize, usize) {
+        let connections = self.tracker.lock();
+        let busy = connections.values().filter(|tracked| tracked.busy).count();
+        (connections.len(), busy)
+    }
+}
+
+/// Sends clients of the plain listener to the same URL over HTTPS.
+struct HttpsRedirect {
+    https_port: u16,
+}
+
+impl Handler for HttpsRedirect {
+    fn handle(&self, request: &mut Request) -> Response {
+        let Some(host) = request.header("Host").map(strip_port) else {
+            return Response::error(StatusCode::BAD_REQUEST);
+        };
+        let authority = match self.https_port {
+            443 => host.to_owned(),
+            port => format!("{host}:{port}"),
+        };
+        // Absolute-form targets already name the host.
+        let target = request.target();
+        let path = match target.find("://") {
+            Some(scheme_end) => target[scheme_end + 3..]
+                .find('/')
+                .map_or("/", |start| &target[scheme_end + 3 + start..]),
+            None => target,
+        };
+
+        // 301 lets clients turn a POST into a GET; 308 keeps the method.
+        let status = match request.method() {
+            Method::Get | Method::Head => StatusCode::MOVED_PERMANENTLY,
+            _ => StatusCode::PERMANENT_REDIRECT,
+        };
+        Response::new(status).with_header("Location", format!("https://{authority}{path}"))
+    }
+}
+
+/// `example.com:80` becomes `example.com`, `[::1]:80` becomes `[::1]`.
+pub(crate) fn strip_port(host: &str) -> &str {
+    match host.rfind(':') {
+        Some(colon) if !host[colon..].contains(']') => &host[..colon],
+        _ => host,
+    }
+}
+
+/// Keeps track of open connections so shutdown can drain them.
+#[derive(Default)]
+pub(crate) struct Tracker {
+    draining: AtomicBool,
+    connections: Mutex<HashMap<Uuid, Tracked>>,
+    closed: Condvar,
+    completed: AtomicUsize,
+}
+
+struct Tracked {
+    stream: TcpStream,
+    busy: bool,
+}
+
+impl Tracker {
+    fn lock(&self) -> MutexGuard<'_, HashMap<Uuid, Tracked>> {
+        self.connections
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn register(&self, connection_id: Uuid, stream: &TcpStream) -> io::Result<()> {
+        let tracked = Tracked {
+            stream: stream.try_clone()?,
+            busy: false,
+        };
+        self.lock().insert(connection_id, tracked);
+        Ok(())
+    }
+
+    fn start_draining(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+    }
+
+    pub(crate) fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Marks the connection busy; shutdown lets it finish the response.
+    pub(crate) fn request_started(&self, connection_id: Uuid) {
+        if let Some(tracked) = self.lock().get_mut(&connection_id) {
+            tracked.busy = true;
+        }
+    }
+
+    pub(crate) fn request_finished(&self, connection_id: Uuid) {
+        if let Some(tracked) = self.lock().get_mut(&connection_id) {
+            tracked.busy = false;
+        }
+        if self.is_draining() {
+            self.completed.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Counts an admin connection as open while it lives.
+struct AdminSlot(Arc<AtomicUsize>);
+
+impl AdminSlot {
+    fn new(open: Arc<AtomicUsize>) -> AdminSlot {
+        open.fetch_add(1, Ordering::SeqCst);
+        AdminSlot(open)
+    }
+}
+
+impl Drop for AdminSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Unregisters a connection when its job ends, even by panic.
+struct Registration {
+    tracker: Arc<Tracker>,
+    connection_id: Uuid,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.tracker.lock().remove(&self.connection_id);
+        self.tracker.closed.notify_all();
+    }
+}
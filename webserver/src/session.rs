@@ -0,0 +1,417 @@
+//! Server-side sessions: data kept for a client between requests.
+//!
+//! The [`Sessions`](crate::middleware::Sessions) middleware finds the
+//! session of a request through a signed cookie and hands it to handlers as
+//! [`Request::session`](crate::Request::session). The data itself lives in a
+//! [`SessionStore`], in memory or on disk.
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+
+use crate::log::warn;
+
+/// The data of one session, by key.
+pub type SessionData = HashMap<String, Value>;
+
+/// How often stores drop expired sessions nobody asked for again.
+const PURGE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Where session data is kept.
+pub trait SessionStore: Send + Sync {
+    /// Loads the data of session `id`; `None` if it is unknown or expired.
+    fn load(&self, id: &str) -> io::Result<Option<SessionData>>;
+
+    /// Stores the data of session `id` for `ttl`, replacing earlier data.
+    fn save(&self, id: &str, data: &SessionData, ttl: Duration) -> io::Result<()>;
+
+    /// Deletes session `id`; unknown ids are not an error.
+    fn remove(&self, id: &str) -> io::Result<()>;
+}
+
+impl<S: SessionStore + ?Sized> SessionStore for Arc<S> {
+    fn load(&self, id: &str) -> io::Result<Option<SessionData>> {
+        (**self).load(id)
+    }
+
+    fn save(&self, id: &str, data: &SessionData, ttl: Duration) -> io::Result<()> {
+        (**self).save(id, data, ttl)
+    }
+
+    fn remove(&self, id: &str) -> io::Result<()> {
+        (**self).remove(id)
+    }
+}
+
+/// Keeps sessions in memory; they are lost when the server stops.
+#[derive(Debug)]
+pub struct MemoryStore {
+    inner: Mutex<MemoryInner>,
+}
+
+#[derive(Debug)]
+struct MemoryInner {
+    sessions: HashMap<String, (SessionData, Instant)>,
+    last_purge: Instant,
+}
+
+impl MemoryStore {
+    /// Creates an empty store.
+    pub fn new() -> MemoryStore {
+        MemoryStore {
+            inner: Mutex::new(MemoryInner {
+                sessions: HashMap::new(),
+                last_purge: Instant::now(),
+            }),
+        }
+    }
+
+    /// The number of sessions held, expired ones not purged yet included.
+    pub fn len(&self) -> usize {
+        self.lock().sessions.len()
+    }
+
+    /// Returns `true` if the store holds no sessions.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MemoryInner> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        MemoryStore::new()
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load(&self, id: &str) -> io::Result<Option<SessionData>> {
+        let mut inner = self.lock();
+        match inner.sessions.get(id) {
+            Some((data, expires)) if *expires > Instant::now() => Ok(Some(data.clone())),
+            Some(_) => {
+                inner.sessions.remove(id);
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn save(&self, id: &str, data: &SessionData, ttl: Duration) -> io::Result<()> {
+        let mut inner = self.lock();
+        let now = Instant::now();
+        if now.duration_since(inner.last_purge) >= PURGE_INTERVAL {
+            inner.sessions.retain(|_, (_, expires)| *expires > now);
+            inner.last_purge = now;
+        }
+        inner
+            .sessions
+            .insert(id.to_owned(), (data.clone(), now + ttl));
+        Ok(())
+    }
+
+    fn remove(&self, id: &str) -> io::Result<()> {
+        self.lock().sessions.remove(id);
+        Ok(())
+    }
+}
+
+/// Keeps each session in a JSON file `<id>.json` in a directory, so
+/// sessions survive restarts and can be shared by several servers.
+#[derive(Debug)]
+pub struct FileStore {
+    dir: PathBuf,
+    last_purge: Mutex<Instant>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Record {
+    /// Seconds since the Unix epoch.
+    expires: u64,
+    data: SessionData,
+}
+
+impl FileStore {
+    /// Stores sessions in `dir`, creating it if needed.
+    pub fn new(dir: impl Into<PathBuf>) -> io::Result<FileStore> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(FileStore {
+            dir,
+            last_purge: Mutex::new(Instant::now()),
+        })
+    }
+
+    /// Deletes the files of expired sessions, and files left half-written
+    /// by a crash. Files that cannot be read are logged and skipped.
+    pub fn purge_expired(&self) -> io::Result<()> {
+        let now = unix_now();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let partial = path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+            let result = if partial {
+                purge_partial(&path)
+            } else if path
+                .extension()
+                .is_some_and(|extension| extension == "json")
+            {
+                read_record(&path).and_then(|record| match record {
+                    Some(record) if record.expires <= now => remove_file(&path),
+                    _ => Ok(()),
+                })
+            } else {
+                Ok(())
+            };
+            if let Err(e) = result {
+                warn!("cannot purge session file {}: {e}", path.display());
+            }
+        }
+        Ok(())
+    }
+
+    fn path(&self, id: &str) -> io::Result<PathBuf> {
+        if id.is_empty()
+            || !id
+                .bytes()
+                .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_')
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "session ids are alphanumeric",
+            ));
+        }
+        Ok(self.dir.join(format!("{id}.json")))
+    }
+}
+
+impl SessionStore for FileStore {
+    fn load(&self, id: &str) -> io::Result<Option<SessionData>> {
+        let path = self.path(id)?;
+        match read_record(&path)? {
+            Some(record) if record.expires > unix_now() => Ok(Some(record.data)),
+            Some(_) => remove_file(&path).map(|()| None),
+            None => Ok(None),
+        }
+    }
+
+    fn save(&self, id: &str, data: &SessionData, ttl: Duration) -> io::Result<()> {
+        let path = self.path(id)?;
+        {
+            let mut last_purge = self
+                .last_purge
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            if last_purge.elapsed() >= PURGE_INTERVAL {
+                *last_purge = Instant::now();
+                // Failing to tidy up is no reason to lose this session.
+                if let Err(e) = self.purge_expired() {
+                    warn!("cannot purge sessions in {}: {e}", self.dir.display());
+                }
+            }
+        }
+
+        let record = Record {
+            expires: unix_now() + ttl.as_secs(),
+            data: data.clone(),
+        };
+        // Write aside and rename, so readers never see half a file.
+        let partial = self.dir.join(format!(".{id}.{}", uuid::Uuid::new_v4()));
+        fs::write(&partial, serde_json::to_vec(&record)?)?;
+        fs::rename(&partial, &path).inspect_err(|_| {
+            let _ = fs::remove_file(&partial);
+        })
+    }
+
+    fn remove(&self, id: &str) -> io::Result<()> {
+        remove_file(&self.path(id)?)
+    }
+}
+
+/// Reads a session file; `None` if it does not exist.
+fn read_record(path: &Path) -> io::Result<Option<Record>> {
+    match fs::read(path) {
+        Ok(bytes) => serde_json::from_slice(&bytes)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Deletes a file `save` wrote aside, once it is too old to be in use.
+fn purge_partial(path: &Path) -> io::Result<()> {
+    let age = fs::metadata(path)?
+        .modified()?
+        .elapsed()
+        .unwrap_or_default();
+    if age >= PURGE_INTERVAL {
+        remove_file(path)?;
+    }
+    Ok(())
+}
+
+fn remove_file(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// The session of a request, shared with the middleware that saves it once
+/// the response is ready.
+///
+/// Values are stored as JSON, so anything `Serialize` can go in and come
+/// back out as any `Deserialize` type of the same shape.
+///
+/// # Examples
+///
+/// ```
+/// use webserver::{Request, Response, StatusCode};
+///
+/// fn visits(request: &mut Request) -> Response {
+///     let Some(session) = request.session() else {
+///         return Response::error(StatusCode::INTERNAL_SERVER_ERROR);
+///     };
+///     let count = session.get::<u32>("visits").unwrap_or(0) + 1;
+///     session.insert("visits", count);
+///     Response::text(StatusCode::OK, format!("visit {count}"))
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Session {
+    state: Arc<Mutex<SessionState>>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct SessionState {
+    /// The id the session was loaded with, `None` for a new session.
+    pub(crate) id: Option<String>,
+    pub(crate) data: SessionData,
+    pub(crate) changed: bool,
+    pub(crate) destroyed: bool,
+    pub(crate) regenerate: bool,
+}
+
+impl Session {
+    pub(crate) fn new(id: Option<String>, data: SessionData) -> Session {
+        Session {
+            state: Arc::new(Mutex::new(SessionState {
+                id,
+                data,
+                ..SessionState::default()
+            })),
+        }
+    }
+
+    pub(crate) fn lock(&self) -> MutexGuard<'_, SessionState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns `true` if the client had no session yet; one is only
+    /// created once something is inserted.
+    pub fn is_new(&self) -> bool {
+        self.lock().id.is_none()
+    }
+
+    /// The value of `key`, or `None` if it is missing or of another shape.
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let state = self.lock();
+        T::deserialize(state.data.get(key)?).ok()
+    }
+
+    /// Sets `key` to `value`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `value` cannot be represented as JSON, e.g. a map with
+    /// non-string keys.
+    pub fn insert(&self, key: impl Into<String>, value: impl Serialize) {
+        let value = serde_json::to_value(value).expect("session values must be JSON");
+        let mut state = self.lock();
+        state.data.insert(key.into(), value);
+        state.changed = true;
+    }
+
+    /// Removes `key`, returning whether it was set.
+    pub fn remove(&self, key: &str) -> bool {
+        let mut state = self.lock();
+        let removed = state.data.remove(key).is_some();
+        state.changed |= removed;
+        removed
+    }
+
+    /// Ends the session: its data is deleted from the store and the cookie
+    /// from the browser. Values inserted afterwards start a new session.
+    pub fn destroy(&self) {
+        let mut state = self.lock();
+        state.data.clear();
+        state.changed = false;
+        state.destroyed = true;
+    }
+
+    /// Moves the data to a new session id, e.g. after logging in, so an id
+    /// planted before by someone else becomes worthless.
+    pub fn regenerate(&self) {
+        self.lock().regenerate = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_store_keeps_sessions_until_they_expire() {
+        let dir = std::env::temp_dir().join(format!("sessions-{}", uuid::Uuid::new_v4()));
+        let store = FileStore::new(&dir).unwrap();
+        let data = SessionData::from([("user".to_owned(), Value::from("ada"))]);
+
+        store.save("abc", &data, Duration::from_secs(60)).unwrap();
+        assert_eq!(store.load("abc").unwrap(), Some(data.clone()));
+        store.save("old", &data, Duration::ZERO).unwrap();
+        assert_eq!(store.load("old").unwrap(), None);
+        assert!(!dir.join("old.json").exists());
+
+        store.remove("abc").unwrap();
+        store.remove("abc").unwrap();
+        assert_eq!(store.load("abc").unwrap(), None);
+        assert!(store.load("../etc/passwd").is_err());
+
+        // Neither broken files nor leftovers stop the purge.
+        fs::write(dir.join("broken.json"), "{").unwrap();
+        let stale = dir.join(".abc.partial");
+        fs::write(&stale, "{").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&stale)
+            .unwrap()
+            .set_modified(SystemTime::now() - PURGE_INTERVAL)
+            .unwrap();
+        store.save("old", &data, Duration::ZERO).unwrap();
+        store.purge_expired().unwrap();
+        assert!(!dir.join("old.json").exists());
+        assert!(!stale.exists());
+        assert!(dir.join("broken.json").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
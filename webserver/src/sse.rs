@@ -0,0 +1,259 @@
+//! Server-Sent Events (`text/event-stream`) responses.
+//!
+//! [`channel`] pairs an [`EventSender`] with an [`EventStream`]. The stream
+//! becomes the response, the sender pushes [`Event`]s to it from any thread.
+//! While no events flow, the stream writes a comment line every
+//! [`keep_alive`](EventStream::keep_alive) interval, which keeps proxies from
+//! timing the connection out and notices clients that went away: the write
+//! fails, the worker is released and [`EventSender::send`] starts failing.
+//! A client that keeps the connection open but stops reading is noticed once
+//! a write has been stuck for
+//! [`write_timeout`](crate::connection::ConnectionConfig::write_timeout).
+//!
+//! An event stream holds its worker until every sender is dropped or the
+//! client leaves, and counts as busy during a graceful shutdown.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::{thread, time::Duration};
+//! use webserver::{Request, Response, Router, sse::{self, Event}};
+//!
+//! fn ticks(request: &mut Request) -> Response {
+//!     // Resume after the last tick a reconnecting client saw.
+//!     let first = sse::last_event_id(request)
+//!         .and_then(|id| id.parse::<u64>().ok())
+//!         .map_or(0, |id| id + 1);
+//!     let (sender, stream) = sse::channel();
+//!     thread::spawn(move || {
+//!         for tick in first.. {
+//!             let event = Event::new(format!("tick {tick}")).id(tick.to_string());
+//!             if sender.send(event).is_err() {
+//!                 break;
+//!             }
+//!             thread::sleep(Duration::from_secs(1));
+//!         }
+//!     });
+//!     stream.into_response()
+//! }
+//!
+//! let router = Router::new().get("/ticks", ticks);
+//! ```
+
+use std::{
+    io::{self, Cursor, Read},
+    sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender},
+    time::Duration,
+};
+
+use crate::{body::Body, request::Request, response::Response, status::StatusCode};
+
+/// Events queued for a client before [`EventSender::send`] waits for it.
+const EVENT_BUFFER: usize = 64;
+/// Default interval between heartbeats on an idle stream.
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+/// A comment line, ignored by clients.
+const HEARTBEAT: &[u8] = b":\n\n";
+
+/// One event on an [`EventStream`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Event {
+    id: Option<String>,
+    event: Option<String>,
+    data: String,
+    retry: Option<Duration>,
+}
+
+impl Event {
+    /// An unnamed event (a `message` to browsers) carrying `data`. Line
+    /// breaks in `data` are kept.
+    pub fn new(data: impl Into<String>) -> Event {
+        Event {
+            data: data.into(),
+            ..Event::default()
+        }
+    }
+
+    /// Sets the id a reconnecting client sends back as `Last-Event-ID`.
+    /// Line breaks and NUL characters are removed.
+    pub fn id(mut self, id: impl Into<String>) -> Event {
+        self.id = Some(id.into().replace(['\r', '\n', '\0'], ""));
+        self
+    }
+
+    /// Sets the event name clients listen for. Line breaks are removed.
+    pub fn event(mut self, name: impl Into<String>) -> Event {
+        self.event = Some(name.into().replace(['\r', '\n'], ""));
+        self
+    }
+
+    /// Tells the client how long to wait before reconnecting.
+    pub fn retry(mut self, delay: Duration) -> Event {
+        self.retry = Some(delay);
+        self
+    }
+
+    /// The event in wire format, ending with the blank line that
+    /// dispatches it.
+    fn encode(&self) -> Vec<u8> {
+        let mut frame = String::new();
+        if let Some(id) = &self.id {
+            frame.push_str(&format!("id: {id}\n"));
+        }
+        if let Some(event) = &self.event {
+            frame.push_str(&format!("event: {event}\n"));
+        }
+        if let Some(retry) = self.retry {
+            frame.push_str(&format!("retry: {}\n", retry.as_millis()));
+        }
+        for line in self.data.replace("\r\n", "\n").split(['\r', '\n']) {
+            frame.push_str(&format!("data: {line}\n"));
+        }
+        frame.push('\n');
+        frame.into_bytes()
+    }
+}
+
+/// Creates a connected sender and stream.
+pub fn channel() -> (EventSender, EventStream) {
+    let (sender, receiver) = mpsc::sync_channel(EVENT_BUFFER);
+    let stream = EventStream {
+        events: receiver,
+        keep_alive: KEEP_ALIVE,
+        retry: None,
+    };
+    (EventSender { events: sender }, stream)
+}
+
+/// The `Last-Event-ID` a reconnecting client sent, to resume the stream
+/// after the last event it saw.
+pub fn last_event_id(request: &Request) -> Option<&str> {
+    request.header("Last-Event-ID")
+}
+
+/// Pushes events to an [`EventStream`]. Clones feed the same stream, which
+/// ends once all of them are dropped.
+#[derive(Debug, Clone)]
+pub struct EventSender {
+    events: SyncSender<Vec<u8>>,
+}
+
+impl EventSender {
+    /// Queues `event` for the client, waiting while it is far behind.
+    /// Fails with [`ErrorKind::BrokenPipe`](io::ErrorKind::BrokenPipe) once
+    /// the client has gone away.
+    pub fn send(&self, event: Event) -> io::Result<()> {
+        self.events
+            .send(event.encode())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "event stream closed"))
+    }
+}
+
+/// The receiving end of a [`channel`], turned into a response with
+/// [`into_response`](EventStream::into_response).
+#[derive(Debug)]
+pub struct EventStream {
+    events: Receiver<Vec<u8>>,
+    keep_alive: Duration,
+    retry: Option<Duration>,
+}
+
+impl EventStream {
+    /// Sets how long the stream may stay silent before a heartbeat goes
+    /// out; 15 seconds by default.
+    pub fn keep_alive(mut self, interval: Duration) -> EventStream {
+        self.keep_alive = interval;
+        self
+    }
+
+    /// Tells the client how long to wait before reconnecting, right at the
+    /// start of the stream.
+    pub fn retry(mut self, delay: Duration) -> EventStream {
+        self.retry = Some(delay);
+        self
+    }
+
+    /// A `200 OK` response streaming the events as they are sent.
+    pub fn into_response(self) -> Response {
+        // Something to write straight away, so the client sees the head
+        // without waiting for the first event.
+        let first = match self.retry {
+            Some(retry) => format!("retry: {}\n\n", retry.as_millis()).into_bytes(),
+            None => HEARTBEAT.to_vec(),
+        };
+        let reader = EventReader {
+            events: self.events,
+            keep_alive: self.keep_alive,
+            pending: Cursor::new(first),
+        };
+        Response::new(StatusCode::OK)
+            .with_header("Content-Type", "text/event-stream")
+            .with_header("Cache-Control", "no-cache")
+            .with_body(Body::reader(reader))
+    }
+}
+
+/// Reads queued events, or a heartbeat when none came in time, until every
+/// sender is gone.
+struct EventReader {
+    events: Receiver<Vec<u8>>,
+    keep_alive: Duration,
+    pending: Cursor<Vec<u8>>,
+}
+
+impl Read for EventReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.pending.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                return Ok(n);
+            }
+            let next = match self.events.recv_timeout(self.keep_alive) {
+                Ok(event) => event,
+                Err(RecvTimeoutError::Timeout) => HEARTBEAT.to_vec(),
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            };
+            self.pending = Cursor::new(next);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_fields_and_multiline_data() {
+        let event = Event::new("first\nsecond\r\nthird")
+            .id("7\n")
+            .event("update")
+            .retry(Duration::from_secs(3));
+        assert_eq!(
+            String::from_utf8(event.encode()).unwrap(),
+            "id: 7\nevent: update\nretry: 3000\ndata: first\ndata: second\ndata: third\n\n"
+        );
+        assert_eq!(Event::new("").encode(), b"data: \n\n");
+    }
+
+    #[test]
+    fn sends_heartbeats_until_the_senders_are_gone() {
+        let (sender, stream) = channel();
+        let mut body = stream
+            .keep_alive(Duration::from_millis(10))
+            .into_response()
+            .into_body()
+            .into_reader();
+        let mut buf = [0; 64];
+
+        let n = body.read(&mut buf).unwrap();
+        assert_eq!(&buf[..n], HEARTBEAT);
+        let n = body.read(&mut buf).unwrap();
+        assert_eq!(&buf[..n], HEARTBEAT);
+
+        sender.send(Event::new("hi")).unwrap();
+        drop(sender);
+        let mut rest = String::new();
+        body.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "data: hi\n\n");
+    }
+}
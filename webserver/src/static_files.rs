@@ -0,0 +1,547 @@
+//! Serving files from a document root.
+
+use std::{
+    fs::{self, File},
+    io::{ErrorKind, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    autoindex,
+    body::Body,
+    conditional::{self, ByteRange, Validators},
+    encoding::{self, Encoding},
+    handler::Handler,
+    mime,
+    request::{Method, Request},
+    response::Response,
+    router,
+    status::StatusCode,
+    url,
+};
+
+/// Serves files below a document root.
+///
+/// The file is looked up from the `path` capture of a wildcard route such as
+/// `/static/*path`, or from the whole request path when there is none.
+/// Directories are answered with their `index.html`; `..` segments and
+/// symlinks leading out of the root are refused with `403 Forbidden`.
+/// Directories without one can be listed, see
+/// [`autoindex`](StaticFiles::autoindex).
+///
+/// With [`precompressed`](StaticFiles::precompressed), a `style.css.br` or
+/// `style.css.gz` next to `style.css` is sent instead to clients that accept
+/// that coding.
+///
+/// Files carry an `ETag` and `Last-Modified`; `If-None-Match` and
+/// `If-Modified-Since` are answered with `304 Not Modified`, and a single
+/// `Range` (subject to `If-Range`) with `206 Partial Content`.
+/// [`cache_control`](StaticFiles::cache_control) rules pick a
+/// `Cache-Control` header by path.
+///
+/// # Examples
+///
+/// ```
+/// use webserver::{Method, Router, StaticFiles};
+///
+/// let files = StaticFiles::new("assets")
+///     .cache_control("*.html", "no-cache")
+///     .cache_control("/fonts/**", "max-age=31536000, immutable");
+/// let router = Router::new().route(Method::Get, "/static/*path", files);
+/// ```
+pub struct StaticFiles {
+    root: PathBuf,
+    index: String,
+    precompressed: bool,
+    cache_rules: Vec<(String, String)>,
+    autoindex: Vec<String>,
+}
+
+impl StaticFiles {
+    /// Creates a handler serving files below `root`.
+    pub fn new(root: impl Into<PathBuf>) -> StaticFiles {
+        StaticFiles {
+            root: root.into(),
+            index: "index.html".to_owned(),
+            precompressed: false,
+            cache_rules: Vec::new(),
+            autoindex: Vec::new(),
+        }
+    }
+
+    /// Changes the file served for directory requests, `index.html` by default.
+    pub fn index(mut self, name: impl Into<String>) -> StaticFiles {
+        self.index = name.into();
+        self
+    }
+
+    /// Serves `.br` and `.gz` siblings of files when the client accepts them.
+    pub fn precompressed(mut self, enabled: bool) -> StaticFiles {
+        self.precompressed = enabled;
+        self
+    }
+
+    /// Sends `Cache-Control: value` with files matching `pattern`. The first
+    /// matching rule wins.
+    ///
+    /// `*` matches within a path segment, `**` across segments and `?` a
+    /// single character. Patterns starting with `/` match the path below the
+    /// root, others just the file name, so `*.css` matches every stylesheet
+    /// and `/img/*` only files directly in `img`.
+    pub fn cache_control(
+        mut self,
+        pattern: impl Into<String>,
+        value: impl Into<String>,
+    ) -> StaticFiles {
+        self.cache_rules.push((pattern.into(), value.into()));
+        self
+    }
+
+    /// Lists the contents of directories matching `pattern` that have no
+    /// index file; other directories get `404 Not Found` as before.
+    /// Patterns work as in [`cache_control`](StaticFiles::cache_control):
+    /// `/downloads` and `/downloads/**` cover that directory and every one
+    /// below it, `/` is the root itself.
+    ///
+    /// Listings show name, size, modification time and type of each entry
+    /// except hidden ones, sorted by `?sort=name|size|modified|type` and
+    /// `?order=asc|desc`; `?format=json` returns them as JSON.
+    pub fn autoindex(mut self, pattern: impl Into<String>) -> StaticFiles {
+        self.autoindex.push(pattern.into());
+        self
+    }
+
+    fn cache_rule(&self, relative: &str) -> Option<&str> {
+        self.cache_rules
+            .iter()
+            .find(|(pattern, _)| path_matches(pattern, relative))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn lists(&self, relative: &str) -> bool {
+        let relative = relative.trim_end_matches('/');
+        self.autoindex
+            .iter()
+            .any(|pattern| path_matches(pattern, relative))
+    }
+
+    /// Finds the best precompressed sibling of `path` the client accepts.
+    fn precompressed_sibling(&self, path: &Path, request: &Request) -> Option<(PathBuf, Encoding)> {
+        if !self.precompressed {
+            return None;
+        }
+        let accept_encoding = request.header("Accept-Encoding")?;
+        let siblings: Vec<(PathBuf, Encoding)> = Encoding::ALL
+            .into_iter()
+            .filter_map(|encoding| {
+                let mut sibling = path.as_os_str().to_owned();
+                sibling.push(format!(".{}", encoding.extension()?));
+                let sibling = PathBuf::from(sibling);
+                sibling.is_file().then_some((sibling, encoding))
+            })
+            .collect();
+
+        let available: Vec<Encoding> = siblings.iter().map(|(_, encoding)| *encoding).collect();
+        let chosen = encoding::negotiate(accept_encoding, &available)?;
+        siblings
+            .into_iter()
+            .find(|(_, encoding)| *encoding == chosen)
+    }
+
+    /// Maps the request onto a path below the root, or `None` if it tries to
+    /// escape it.
+    fn resolve(&self, relative: &str) -> Option<PathBuf> {
+        let mut path = self.root.clone();
+        for segment in relative.split('/') {
+            match segment {
+                "" | "." => continue,
+                ".." => return None,
+                _ if segment.contains(['\\', '\0']) => return None,
+                _ => path.push(segment),
+            }
+        }
+        Some(path)
+    }
+
+    /// Lists `dir`, leaving out entries that lead out of the root.
+    fn listing(&self, dir: &Path, request: &mut Request) -> Response {
+        if !is_within(&self.root, dir) {
+            return Response::error(StatusCode::FORBIDDEN);
+        }
+        match autoindex::entries(dir) {
+            Ok(mut entries) => {
+                entries.retain(|entry| is_within(&self.root, &entry.path));
+                autoindex::response(request, entries)
+            }
+            Err(e) => error_response(e.kind(), request),
+        }
+    }
+}
+
+impl Handler for StaticFiles {
+    fn handle(&self, request: &mut Request) -> Response {
+        let relative = request.param("path").unwrap_or(request.path()).to_owned();
+
+        let Some(mut path) = self.resolve(&relative) else {
+            return Response::error(StatusCode::FORBIDDEN);
+        };
+
+        let metadata = match fs::metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(e) => return error_response(e.kind(), request),
+        };
+
+        let mut served = relative;
+        if metadata.is_dir() {
+            // Relative links in the index page need the trailing slash.
+            if !request.path().ends_with('/') {
+                // Re-encoded, and without the empty segments that would make
+                // `//host` a protocol-relative redirect off the site.
+                let segments: Vec<_> = request
+                    .path()
+                    .split('/')
+                    .filter(|segment| !segment.is_empty())
+                    .map(url::percent_encode)
+                    .collect();
+                let mut location = format!("/{}/", segments.join("/"));
+                if let Some(query) = request.query() {
+                    location = format!("{location}?{query}");
+                }
+                return Response::new(StatusCode::MOVED_PERMANENTLY)
+                    .with_header("Location", location);
+            }
+            if !path.join(&self.index).exists() && self.lists(&served) {
+                return self.listing(&path, request);
+            }
+            path.push(&self.index);
+            served = format!("{}/{}", served.trim_end_matches('/'), self.index);
+        }
+
+        if !is_within(&self.root, &path) {
+            return Response::error(StatusCode::FORBIDDEN);
+        }
+
+        let content_type = mime::from_path(&path);
+        let mut encoding = None;
+        if let Some((sibling, chosen)) = self
+            .precompressed_sibling(&path, request)
+            .filter(|(sibling, _)| is_within(&self.root, sibling))
+        {
+            path = sibling;
+            encoding = Some(chosen);
+        }
+
+        let opened = File::open(&path).and_then(|file| {
+            let metadata = file.metadata()?;
+            Ok((file, metadata))
+        });
+        let (mut file, metadata) = match opened {
+            Ok(opened) => opened,
+            Err(e) => return error_response(e.kind(), request),
+        };
+        let len = metadata.len();
+        let validators = Validators::from_metadata(&metadata);
+
+        let mut response = Response::new(StatusCode::OK)
+            .with_header("Content-Type", content_type)
+            .with_header("Accept-Ranges", "bytes")
+            .with_header("ETag", validators.etag.clone());
+        let headers = response.headers_mut();
+        if let Some(last_modified) = validators.last_modified_header() {
+            headers.insert("Last-Modified", last_modified);
+        }
+        if let Some(cache_control) = self.cache_rule(&served) {
+            headers.insert("Cache-Control", cache_control);
+        }
+        if self.precompressed {
+            headers.append_token("Vary", "Accept-Encoding");
+        }
+        if let Some(encoding) = encoding {
+            headers.insert("Content-Encoding", encoding.as_str());
+        }
+
+        if validators.not_modified(request) {
+            response.set_status(StatusCode::NOT_MODIFIED);
+            return response;
+        }
+
+        let range = if request.method() == Method::Get {
+            conditional::byte_range(request, &validators, len)
+        } else {
+            ByteRange::Full
+        };
+        match range {
+            ByteRange::Full => {
+                response.replace_body(Body::File { file, len });
+            }
+            ByteRange::Partial(range) => {
+                if let Err(e) = file.seek(SeekFrom::Start(range.start)) {
+                    return error_response(e.kind(), request);
+                }
+                response.set_status(StatusCode::PARTIAL_CONTENT);
+                response.headers_mut().insert(
+                    "Content-Range",
+                    format!("bytes {}-{}/{len}", range.start, range.end - 1),
+                );
+                response.replace_body(Body::File {
+                    file,
+                    len: range.end - range.start,
+                });
+            }
+            ByteRange::Unsatisfiable => {
+                return Response::error(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .with_header("Content-Range", format!("bytes */{len}"));
+            }
+        }
+        response
+    }
+}
+
+/// Matches the path of a file below the root against a rule pattern:
+/// the whole path for patterns starting with `/`, else the file name.
+fn path_matches(pattern: &str, relative: &str) -> bool {
+    let relative = relative.trim_start_matches('/');
+    let file_name = relative.rsplit('/').next().unwrap_or(relative);
+    match pattern.strip_prefix('/') {
+        Some(pattern) => glob_match(pattern.as_bytes(), relative.as_bytes()),
+        None => glob_match(pattern.as_bytes(), file_name.as_bytes()),
+    }
+}
+
+/// Checks that `path`, with symlinks resolved, still lives below `root`.
+fn is_within(root: &Path, path: &Path) -> bool {
+    match (root.canonicalize(), path.canonicalize()) {
+        (Ok(root), Ok(path)) => path.starts_with(root),
+        // Let the read report missing files as 404.
+        (Ok(_), Err(e)) if e.kind() == ErrorKind::NotFound => true,
+        _ => false,
+    }
+}
+
+/// Matches `text` against a pattern of literal bytes, `?`, `*` (not
+/// crossing `/`) and `**`.
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*', rest @ ..] => (0..=text.len()).any(|skip| glob_match(rest, &text[skip..])),
+        [b'*', rest @ ..] => (0..=text.len())
+            .take_while(|&skip| skip == 0 || text[skip - 1] != b'/')
+            .any(|skip| glob_match(rest, &text[skip..])),
+        [b'?', rest @ ..] => {
+            matches!(text, [first, tail @ ..] if *first != b'/' && glob_match(rest, tail))
+        }
+        [literal, rest @ ..] => {
+            matches!(text, [first, tail @ ..] if first == literal && glob_match(rest, tail))
+        }
+    }
+}
+
+fn error_response(kind: ErrorKind, request: &mut Request) -> Response {
+    match kind {
+        ErrorKind::NotFound | ErrorKind::NotADirectory | ErrorKind::IsADirectory => {
+            router::not_found(request)
+        }
+        ErrorKind::PermissionDenied => Response::error(StatusCode::FORBIDDEN),
+        _ => Response::error(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::ParseLimits;
+
+    fn get(handler: &StaticFiles, path: &str) -> Response {
+        get_with(handler, path, "")
+    }
+
+    fn get_with(handler: &StaticFiles, path: &str, headers: &str) -> Response {
+        let raw = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n{headers}\r\n");
+        let mut request = Request::parse(&mut raw.as_bytes(), &ParseLimits::default()).unwrap();
+        handler.handle(&mut request)
+    }
+
+    #[test]
+    fn serves_files_and_refuses_traversal() {
+        let root = std::env::temp_dir().join(format!("static-files-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::create_dir_all(root.join("my docs")).unwrap();
+        fs::write(root.join("docs/index.html"), "<h1>docs</h1>").unwrap();
+        fs::write(root.join("logo.png"), [0x89, b'P', b'N', b'G']).unwrap();
+
+        let handler = StaticFiles::new(&root);
+
+        let response = get(&handler, "/logo.png");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get("Content-Type"), Some("image/png"));
+        let body = response.into_body().into_bytes().unwrap();
+        assert_eq!(body, [0x89, b'P', b'N', b'G']);
+
+        let response = get(&handler, "/docs/");
+        assert_eq!(response.into_body().into_bytes().unwrap(), b"<h1>docs</h1>");
+
+        let response = get(&handler, "/docs");
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(response.headers().get("Location"), Some("/docs/"));
+        let response = get(&handler, "//docs?a=1");
+        assert_eq!(response.headers().get("Location"), Some("/docs/?a=1"));
+        let response = get(&handler, "/my%20docs");
+        assert_eq!(response.headers().get("Location"), Some("/my%20docs/"));
+
+        let response = get(&handler, "/docs/%2e%2e/%2e%2e/etc/passwd");
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = get(&handler, "/missing.txt");
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn serves_precompressed_siblings() {
+        let root = std::env::temp_dir().join(format!("static-files-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("app.js"), "plain").unwrap();
+        fs::write(root.join("app.js.gz"), "gzipped").unwrap();
+        fs::write(root.join("app.js.br"), "brotli").unwrap();
+
+        let handler = StaticFiles::new(&root).precompressed(true);
+
+        let response = get_with(&handler, "/app.js", "Accept-Encoding: gzip, br;q=0.5\r\n");
+        assert_eq!(response.headers().get("Content-Encoding"), Some("gzip"));
+        assert_eq!(
+            response.headers().get("Content-Type"),
+            Some("text/javascript; charset=utf-8")
+        );
+        assert_eq!(response.into_body().into_bytes().unwrap(), b"gzipped");
+
+        let response = get(&handler, "/app.js");
+        assert!(!response.headers().contains("Content-Encoding"));
+        assert_eq!(response.headers().get("Vary"), Some("Accept-Encoding"));
+        assert_eq!(response.into_body().into_bytes().unwrap(), b"plain");
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn answers_conditional_and_range_requests() {
+        let root = std::env::temp_dir().join(format!("static-files-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(root.join("fonts")).unwrap();
+        fs::write(root.join("video.bin"), b"0123456789").unwrap();
+        fs::write(root.join("fonts/a.woff2"), b"font").unwrap();
+
+        let handler = StaticFiles::new(&root)
+            .cache_control("/fonts/**", "max-age=31536000, immutable")
+            .cache_control("*.bin", "no-cache");
+
+        let response = get(&handler, "/video.bin");
+        assert_eq!(response.headers().get("Accept-Ranges"), Some("bytes"));
+        assert_eq!(response.headers().get("Cache-Control"), Some("no-cache"));
+        let etag = response.headers().get("ETag").unwrap().to_owned();
+        let last_modified = response.headers().get("Last-Modified").unwrap().to_owned();
+
+        let response = get_with(
+            &handler,
+            "/video.bin",
+            &format!("If-None-Match: {etag}\r\n"),
+        );
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers().get("ETag"), Some(etag.as_str()));
+        let response = get_with(
+            &handler,
+            "/video.bin",
+            &format!("If-Modified-Since: {last_modified}\r\n"),
+        );
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        let response = get_with(
+            &handler,
+            "/video.bin",
+            &format!("Range: bytes=2-4\r\nIf-Range: {etag}\r\n"),
+        );
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            response.headers().get("Content-Range"),
+            Some("bytes 2-4/10")
+        );
+        assert_eq!(response.into_body().into_bytes().unwrap(), b"234");
+
+        let response = get_with(
+            &handler,
+            "/video.bin",
+            "Range: bytes=-3\r\nIf-Range: \"old\"\r\n",
+        );
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.into_body().into_bytes().unwrap(), b"0123456789");
+
+        let response = get_with(&handler, "/video.bin", "Range: bytes=10-\r\n");
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers().get("Content-Range"), Some("bytes */10"));
+
+        let response = get(&handler, "/fonts/a.woff2");
+        assert_eq!(
+            response.headers().get("Cache-Control"),
+            Some("max-age=31536000, immutable")
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn matches_globs() {
+        assert!(glob_match(b"*.css", b"site.css"));
+        assert!(!glob_match(b"*.css", b"css/site.js"));
+        assert!(glob_match(b"img/*", b"img/a.png"));
+        assert!(!glob_match(b"img/*", b"img/icons/a.png"));
+        assert!(glob_match(b"img/**", b"img/icons/a.png"));
+        assert!(glob_match(b"v?/app.js", b"v2/app.js"));
+    }
+
+    #[test]
+    fn lists_enabled_directories_without_an_index() {
+        let root = std::env::temp_dir().join(format!("static-files-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(root.join("pub/sub")).unwrap();
+        fs::create_dir_all(root.join("private")).unwrap();
+        fs::write(root.join("pub/b.txt"), "bb").unwrap();
+        fs::write(root.join("pub/a <&>.css"), "aaaa").unwrap();
+        fs::write(root.join("pub/.hidden"), "").unwrap();
+
+        let handler = StaticFiles::new(&root).autoindex("/pub");
+
+        let response = get(&handler, "/private/");
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = get(&handler, "/pub/");
+        assert_eq!(response.status(), StatusCode::OK);
+        let page = String::from_utf8(response.into_body().into_bytes().unwrap()).unwrap();
+        assert!(page.contains("<title>Index of /pub/</title>"));
+        assert!(page.contains("<a href=\"a%20%3C%26%3E.css\">a &lt;&amp;&gt;.css</a>"));
+        assert!(page.contains("<td>text/css</td>"));
+        assert!(!page.contains(".hidden"));
+        let order = ["sub/", "a &lt;", "b.txt"].map(|name| page.find(name).unwrap());
+        assert!(order.is_sorted(), "{page}");
+
+        let response = get(&handler, "/pub/?format=json&sort=size&order=desc");
+        assert_eq!(
+            response.headers().get("Content-Type"),
+            Some("application/json")
+        );
+        let listing: serde_json::Value =
+            serde_json::from_slice(&response.into_body().into_bytes().unwrap()).unwrap();
+        assert_eq!(listing["path"], "/pub/");
+        let names: Vec<_> = listing["entries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| {
+                (
+                    entry["name"].as_str().unwrap(),
+                    entry["size"].as_u64().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(names, [("sub", 0), ("a <&>.css", 4), ("b.txt", 2)]);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
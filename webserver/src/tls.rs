@@ -0,0 +1,140 @@
+//! HTTPS support with rustls, available with the `tls` cargo feature.
+//!
+//! Certificates are picked by the server name the client sends (SNI), with a
+//! default certificate for clients that send none or an unknown one.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use rustls::{
+    crypto::{CryptoProvider, ring},
+    pki_types::{
+        CertificateDer, PrivateKeyDer,
+        pem::{self, PemObject},
+    },
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+};
+
+use crate::config::SniCertificate;
+
+/// Why the TLS configuration could not be built.
+#[derive(Debug)]
+pub enum TlsError {
+    /// A certificate or key file could not be read or holds no PEM data.
+    Pem { path: PathBuf, error: pem::Error },
+    /// The key does not belong to the certificate or is not supported.
+    Key { path: PathBuf, error: rustls::Error },
+    /// Neither a default nor any SNI certificate was given.
+    NoCertificates,
+    /// rustls refused the configuration.
+    Config(rustls::Error),
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsError::Pem { path, error } => {
+                write!(f, "cannot load {}: {error}", path.display())
+            }
+            TlsError::Key { path, error } => {
+                write!(f, "unusable private key {}: {error}", path.display())
+            }
+            TlsError::NoCertificates => write!(f, "no TLS certificate configured"),
+            TlsError::Config(error) => write!(f, "invalid TLS configuration: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for TlsError {}
+
+/// Builds the rustls configuration for the HTTPS listener.
+///
+/// `default` is a `(certificate, key)` pair of PEM files used when no SNI
+/// entry matches. SNI server names may start with `*.` to match any single
+/// subdomain label.
+pub fn server_config(
+    default: Option<(&Path, &Path)>,
+    sni: &[SniCertificate],
+) -> Result<Arc<rustls::ServerConfig>, TlsError> {
+    if default.is_none() && sni.is_empty() {
+        return Err(TlsError::NoCertificates);
+    }
+    let provider = Arc::new(ring::default_provider());
+
+    let mut resolver = SniResolver::default();
+    if let Some((cert, key)) = default {
+        resolver.default = Some(load_certified_key(cert, key, &provider)?);
+    }
+    for entry in sni {
+        let certified_key = load_certified_key(&entry.cert, &entry.key, &provider)?;
+        resolver
+            .by_name
+            .insert(entry.server_name.to_ascii_lowercase(), certified_key);
+    }
+
+    let mut config = rustls::ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(TlsError::Config)?
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(resolver));
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(Arc::new(config))
+}
+
+fn load_certified_key(
+    cert: &Path,
+    key: &Path,
+    provider: &CryptoProvider,
+) -> Result<Arc<CertifiedKey>, TlsError> {
+    let pem_error = |path: &Path| {
+        let path = path.to_owned();
+        move |error| TlsError::Pem { path, error }
+    };
+
+    let chain = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(pem_error(cert))?;
+    if chain.is_empty() {
+        return Err(pem_error(cert)(pem::Error::NoItemsFound));
+    }
+    let private_key = PrivateKeyDer::from_pem_file(key).map_err(pem_error(key))?;
+
+    CertifiedKey::from_der(chain, private_key, provider)
+        .map(Arc::new)
+        .map_err(|error| TlsError::Key {
+            path: key.to_owned(),
+            error,
+        })
+}
+
+/// Picks the certificate for the server name in the ClientHello.
+#[derive(Debug, Default)]
+struct SniResolver {
+    by_name: HashMap<String, Arc<CertifiedKey>>,
+    default: Option<Arc<CertifiedKey>>,
+}
+
+impl SniResolver {
+    fn lookup(&self, server_name: &str) -> Option<Arc<CertifiedKey>> {
+        let name = server_name.to_ascii_lowercase();
+        if let Some(certified_key) = self.by_name.get(&name) {
+            return Some(Arc::clone(certified_key));
+        }
+        let (_, parent) = name.split_once('.')?;
+        self.by_name.get(&format!("*.{parent}")).cloned()
+    }
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        client_hello
+            .server_name()
+            .and_then(|name| self.lookup(name))
+            .or_else(|| self.default.clone())
+    }
+}
@@ -0,0 +1,72 @@
+//! Percent-encoding, percent-decoding and query string helpers.
+
+/// Encodes `input` for a path segment or query component: every byte but
+/// ASCII letters, digits and `-._~` becomes a `%XX` escape.
+pub fn percent_encode(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            out.push(byte as char);
+        } else {
+            out.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    out
+}
+
+/// Decodes `%XX` escapes in `input`.
+///
+/// Returns `None` if an escape is malformed or the result is not valid UTF-8.
+pub fn percent_decode(input: &str) -> Option<String> {
+    decode(input, false)
+}
+
+/// Decodes an `application/x-www-form-urlencoded` component, where `+`
+/// stands for a space.
+pub fn form_decode(input: &str) -> Option<String> {
+    decode(input, true)
+}
+
+/// Splits a query string into decoded `(name, value)` pairs.
+///
+/// Pairs that fail to decode are skipped. A name without `=` gets an empty value.
+pub fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .filter_map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Some((form_decode(name)?, form_decode(value)?))
+        })
+        .collect()
+}
+
+fn decode(input: &str, plus_as_space: bool) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = bytes.get(i + 1..i + 3)?;
+                if !hex.iter().all(u8::is_ascii_hexdigit) {
+                    return None;
+                }
+                let hex = std::str::from_utf8(hex).ok()?;
+                out.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            b'+' if plus_as_space => {
+                out.push(b' ');
+                i += 1;
+            }
+            byte => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8(out).ok()
+}
@@ -0,0 +1,181 @@
+//! Name-based virtual hosting: one handler per host name.
+
+use std::collections::HashMap;
+
+use crate::{
+    handler::Handler, request::Request, response::Response, server::strip_port, status::StatusCode,
+};
+
+/// Dispatches requests to a handler by the host name they are for.
+///
+/// The name comes from the `Host` header, or from the request-target when
+/// it is in absolute form, and is compared without port and case. Exact
+/// names win over wildcards; `*.example.com` matches every subdomain of
+/// `example.com` at any depth, but not `example.com` itself, and the longest
+/// matching wildcard wins. Requests for other names, and HTTP/1.0 requests
+/// without `Host`, go to the default host, or get `421 Misdirected Request`
+/// if there is none.
+///
+/// # Examples
+///
+/// ```
+/// use webserver::{Request, Response, StatusCode, VirtualHosts};
+///
+/// let hosts = VirtualHosts::new()
+///     .host("example.com", |_: &mut Request| Response::text(StatusCode::OK, "home"))
+///     .host("*.example.com", |_: &mut Request| Response::text(StatusCode::OK, "team"))
+///     .default_host(|_: &mut Request| Response::text(StatusCode::OK, "elsewhere"));
+/// ```
+#[derive(Default)]
+pub struct VirtualHosts {
+    exact: HashMap<String, Box<dyn Handler>>,
+    /// Keyed by the suffix a wildcard matches, e.g. `.example.com`.
+    wildcards: Vec<(String, Box<dyn Handler>)>,
+    default: Option<Box<dyn Handler>>,
+}
+
+impl VirtualHosts {
+    /// Creates a dispatcher without hosts.
+    pub fn new() -> VirtualHosts {
+        VirtualHosts::default()
+    }
+
+    /// Serves requests for `name`, e.g. `example.com` or `*.example.com`,
+    /// with `handler`. Registering a name again replaces its handler.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` has a `*` anywhere but in a leading `*.` label.
+    pub fn host<H: Handler + 'static>(mut self, name: &str, handler: H) -> VirtualHosts {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        let (wildcard, rest) = match name.strip_prefix('*') {
+            Some(suffix) => (true, suffix),
+            None => (false, name.as_str()),
+        };
+        assert!(
+            !rest.contains('*') && (!wildcard || rest.starts_with('.')),
+            "host name {name:?} may only start with a `*.` wildcard"
+        );
+
+        if wildcard {
+            self.wildcards.retain(|(suffix, _)| suffix != rest);
+            self.wildcards.push((rest.to_owned(), Box::new(handler)));
+        } else {
+            self.exact.insert(name, Box::new(handler));
+        }
+        self
+    }
+
+    /// Serves requests for names no other host matches with `handler`.
+    pub fn default_host<H: Handler + 'static>(mut self, handler: H) -> VirtualHosts {
+        self.default = Some(Box::new(handler));
+        self
+    }
+
+    fn find(&self, host: &str) -> Option<&dyn Handler> {
+        if let Some(handler) = self.exact.get(host) {
+            return Some(&**handler);
+        }
+        self.wildcards
+            .iter()
+            .filter(|(suffix, _)| host.ends_with(suffix.as_str()))
+            .max_by_key(|(suffix, _)| suffix.len())
+            .map(|(_, handler)| &**handler)
+    }
+
+    /// The handler for the host `request` is for, or the default one.
+    fn site(&self, request: &Request) -> Option<&dyn Handler> {
+        host_name(request)
+            .and_then(|host| self.find(&host))
+            .or(self.default.as_deref())
+    }
+}
+
+impl Handler for VirtualHosts {
+    fn handle(&self, request: &mut Request) -> Response {
+        match self.site(request) {
+            Some(handler) => handler.handle(request),
+            None => Response::error(StatusCode::MISDIRECTED_REQUEST),
+        }
+    }
+
+    fn streams_body(&self, request: &Request) -> bool {
+        self.site(request)
+            .is_some_and(|handler| handler.streams_body(request))
+    }
+}
+
+/// The lowercase host name the request is for, without port.
+fn host_name(request: &Request) -> Option<String> {
+    // An absolute-form target overrides `Host` (RFC 9112 3.2.2).
+    let authority = match request.target().split_once("://") {
+        Some((_, rest)) => rest.split(['/', '?']).next(),
+        None => request.header("Host"),
+    }?;
+    let host = strip_port(authority).trim_end_matches('.');
+    (!host.is_empty()).then(|| host.to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::ParseLimits;
+    use std::io::Cursor;
+
+    fn request(target: &str, host: Option<&str>) -> Request {
+        let raw = match host {
+            Some(host) => format!("GET {target} HTTP/1.1\r\nHost: {host}\r\n\r\n"),
+            None => format!("GET {target} HTTP/1.0\r\n\r\n"),
+        };
+        Request::parse(&mut Cursor::new(raw), &ParseLimits::default()).unwrap()
+    }
+
+    fn named(name: &'static str) -> impl Handler {
+        move |_: &mut Request| Response::text(StatusCode::OK, name)
+    }
+
+    fn served_by(hosts: &VirtualHosts, mut request: Request) -> String {
+        let response = hosts.handle(&mut request);
+        String::from_utf8(response.into_body().into_bytes().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn prefers_exact_names_then_longer_wildcards() {
+        let hosts = VirtualHosts::new()
+            .host("Example.com", named("apex"))
+            .host("*.example.com", named("any"))
+            .host("*.eu.example.com", named("eu"))
+            .host("www.example.com", named("www"))
+            .default_host(named("default"));
+
+        for (host, expected) in [
+            ("example.com:8080", "apex"),
+            ("WWW.example.com.", "www"),
+            ("shop.example.com", "any"),
+            ("a.b.eu.example.com", "eu"),
+            ("example.org", "default"),
+            ("[::1]:80", "default"),
+        ] {
+            assert_eq!(
+                served_by(&hosts, request("/", Some(host))),
+                expected,
+                "{host}"
+            );
+        }
+        assert_eq!(served_by(&hosts, request("/", None)), "default");
+        assert_eq!(
+            served_by(
+                &hosts,
+                request("http://www.example.com/x", Some("example.org"))
+            ),
+            "www"
+        );
+    }
+
+    #[test]
+    fn misdirects_without_a_default() {
+        let hosts = VirtualHosts::new().host("example.com", named("apex"));
+        let response = hosts.handle(&mut request("/", Some("example.org")));
+        assert_eq!(response.status(), StatusCode::MISDIRECTED_REQUEST);
+    }
+}
@@ -0,0 +1,544 @@
+//! WebSocket connections (RFC 6455), switched to from an HTTP request.
+//!
+//! A [`WebSocketHandler`] answers the opening handshake and runs a session
+//! function for every connection, on a thread of its own rather than a pool
+//! worker. The session receives [`Message`]s from a [`WebSocket`] and can
+//! send from other threads through a [`Sender`]. Pings, fragmented messages
+//! and the closing handshake are handled along the way.
+//!
+//! # Examples
+//!
+//! ```
+//! use webserver::{Method, Router, websocket::{WebSocket, WebSocketHandler}};
+//!
+//! let echo = WebSocketHandler::new(|mut socket: WebSocket| {
+//!     while let Ok(Some(message)) = socket.recv() {
+//!         if socket.send(message).is_err() {
+//!             break;
+//!         }
+//!     }
+//! });
+//! let router = Router::new().route(Method::Get, "/echo", echo);
+//! ```
+
+use std::{
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    net::Shutdown,
+    sync::{
+        Arc, Mutex, MutexGuard, PoisonError,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+
+use crate::{
+    handler::Handler,
+    request::{Method, Request, Version},
+    response::Response,
+    status::StatusCode,
+    stream::Stream,
+};
+
+/// Appended to the client's key to prove the server speaks WebSocket.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// How long a waiting reader holds the connection before letting senders in.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Time allowed for the rest of a frame once it started, and for each write.
+const FRAME_TIMEOUT: Duration = Duration::from_secs(10);
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+const CLOSE_NORMAL: u16 = 1000;
+const CLOSE_GOING_AWAY: u16 = 1001;
+const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+const CLOSE_INVALID_DATA: u16 = 1007;
+const CLOSE_TOO_BIG: u16 = 1009;
+
+/// A complete message, however many frames it arrived in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+impl From<String> for Message {
+    fn from(text: String) -> Self {
+        Message::Text(text)
+    }
+}
+
+impl From<&str> for Message {
+    fn from(text: &str) -> Self {
+        Message::Text(text.to_owned())
+    }
+}
+
+impl From<Vec<u8>> for Message {
+    fn from(bytes: Vec<u8>) -> Self {
+        Message::Binary(bytes)
+    }
+}
+
+/// Accepts WebSocket handshakes and runs `session` for each connection.
+///
+/// Requests that are not a WebSocket handshake get `426 Upgrade Required`,
+/// malformed handshakes `400 Bad Request`. Register it for `GET`.
+pub struct WebSocketHandler<F> {
+    session: Arc<F>,
+    max_message_size: usize,
+}
+
+impl<F> WebSocketHandler<F>
+where
+    F: Fn(WebSocket) + Send + Sync + 'static,
+{
+    /// Runs `session` with every connection, until it returns.
+    pub fn new(session: F) -> WebSocketHandler<F> {
+        WebSocketHandler {
+            session: Arc::new(session),
+            max_message_size: 16 * 1024 * 1024,
+        }
+    }
+
+    /// Largest message accepted, in bytes; bigger ones close the connection
+    /// with status 1009. Defaults to 16 MiB.
+    pub fn max_message_size(mut self, bytes: usize) -> WebSocketHandler<F> {
+        self.max_message_size = bytes;
+        self
+    }
+}
+
+impl<F> Handler for WebSocketHandler<F>
+where
+    F: Fn(WebSocket) + Send + Sync + 'static,
+{
+    fn handle(&self, request: &mut Request) -> Response {
+        let accept = match handshake(request) {
+            Ok(accept) => accept,
+            Err(response) => return response,
+        };
+        let session = Arc::clone(&self.session);
+        let max_message_size = self.max_message_size;
+        Response::new(StatusCode::SWITCHING_PROTOCOLS)
+            .with_header("Upgrade", "websocket")
+            .with_header("Connection", "Upgrade")
+            .with_header("Sec-WebSocket-Accept", accept)
+            .with_upgrade(move |reader| session(WebSocket::new(reader, max_message_size)))
+    }
+}
+
+/// Checks the opening handshake and returns the `Sec-WebSocket-Accept`
+/// value, or the response refusing it.
+fn handshake(request: &Request) -> Result<String, Response> {
+    let headers = request.headers();
+    if !headers.has_token("Upgrade", "websocket") {
+        return Err(Response::error(StatusCode::UPGRADE_REQUIRED)
+            .with_header("Upgrade", "websocket")
+            .with_header("Connection", "Upgrade"));
+    }
+    if request.method() != Method::Get
+        || request.version() != Version::Http11
+        || !headers.has_token("Connection", "upgrade")
+    {
+        return Err(Response::error(StatusCode::BAD_REQUEST));
+    }
+    if request.header("Sec-WebSocket-Version") != Some("13") {
+        return Err(Response::error(StatusCode::UPGRADE_REQUIRED)
+            .with_header("Sec-WebSocket-Version", "13"));
+    }
+    match request.header("Sec-WebSocket-Key") {
+        Some(key) if STANDARD.decode(key).is_ok_and(|nonce| nonce.len() == 16) => {
+            Ok(accept_key(key))
+        }
+        _ => Err(Response::error(StatusCode::BAD_REQUEST)),
+    }
+}
+
+/// The `Sec-WebSocket-Accept` value answering a `Sec-WebSocket-Key`.
+pub fn accept_key(key: &str) -> String {
+    let mut sha1 = sha1_smol::Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(GUID.as_bytes());
+    STANDARD.encode(sha1.digest().bytes())
+}
+
+/// One side of a WebSocket connection.
+///
+/// [`recv`](WebSocket::recv) answers pings and the closing handshake on its
+/// own, so keep calling it even when only sending. Dropping the socket
+/// closes the connection, also for its [`Sender`]s.
+pub struct WebSocket {
+    shared: Arc<Shared>,
+    max_message_size: usize,
+    /// The opcode and data of a fragmented message so far.
+    fragments: Option<(u8, Vec<u8>)>,
+}
+
+/// Sends messages on a [`WebSocket`] from another thread.
+#[derive(Clone)]
+pub struct Sender {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    stream: Mutex<BufReader<Stream>>,
+    /// Senders waiting for the stream; the reader steps aside for them.
+    waiting: AtomicUsize,
+    /// Set once a close frame went out; no more frames may follow it.
+    closed: AtomicBool,
+}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+enum FrameError {
+    Io(io::Error),
+    /// The client broke the protocol; close with this status.
+    Close(u16, &'static str),
+}
+
+impl From<io::Error> for FrameError {
+    fn from(e: io::Error) -> Self {
+        FrameError::Io(e)
+    }
+}
+
+impl WebSocket {
+    fn new(reader: BufReader<Stream>, max_message_size: usize) -> WebSocket {
+        let _ = reader
+            .get_ref()
+            .tcp()
+            .set_write_timeout(Some(FRAME_TIMEOUT));
+        WebSocket {
+            shared: Arc::new(Shared {
+                stream: Mutex::new(reader),
+                waiting: AtomicUsize::new(0),
+                closed: AtomicBool::new(false),
+            }),
+            max_message_size,
+            fragments: None,
+        }
+    }
+
+    /// Waits for the next message. Returns `None` once the connection has
+    /// closed, cleanly or not.
+    ///
+    /// Protocol violations close the connection with the matching status
+    /// and are returned as [`ErrorKind::InvalidData`] errors.
+    pub fn recv(&mut self) -> io::Result<Option<Message>> {
+        loop {
+            let frame = match self.read_frame() {
+                Ok(Some(frame)) => frame,
+                Ok(None) => {
+                    // Gone without a closing handshake, or the server is
+                    // shutting down.
+                    let _ = self.shared.close(CLOSE_GOING_AWAY, "");
+                    return Ok(None);
+                }
+                Err(FrameError::Io(e)) => return Err(e),
+                Err(FrameError::Close(code, reason)) => return Err(self.fail(code, reason)),
+            };
+
+            match frame.opcode {
+                OP_PING => {
+                    if !self.shared.closed.load(Ordering::SeqCst) {
+                        self.shared.write(OP_PONG, &frame.payload)?;
+                    }
+                }
+                OP_PONG => {}
+                OP_CLOSE => {
+                    let code = match frame.payload.as_slice() {
+                        [] => CLOSE_NORMAL,
+                        [high, low, reason @ ..] => {
+                            let code = u16::from_be_bytes([*high, *low]);
+                            if !is_valid_close_code(code) {
+                                return Err(self.fail(CLOSE_PROTOCOL_ERROR, "invalid close code"));
+                            }
+                            if std::str::from_utf8(reason).is_err() {
+                                return Err(self.fail(CLOSE_INVALID_DATA, "invalid close reason"));
+                            }
+                            code
+                        }
+                        [_] => return Err(self.fail(CLOSE_PROTOCOL_ERROR, "malformed close frame")),
+                    };
+                    self.shared.close(code, "")?;
+                    return Ok(None);
+                }
+                OP_TEXT | OP_BINARY if self.fragments.is_some() => {
+                    return Err(self.fail(CLOSE_PROTOCOL_ERROR, "expected a continuation frame"));
+                }
+                OP_TEXT | OP_BINARY if frame.fin => {
+                    return self.message(frame.opcode, frame.payload).map(Some);
+                }
+                OP_TEXT | OP_BINARY => self.fragments = Some((frame.opcode, frame.payload)),
+                OP_CONTINUATION => {
+                    let Some((opcode, mut data)) = self.fragments.take() else {
+                        return Err(
+                            self.fail(CLOSE_PROTOCOL_ERROR, "unexpected continuation frame")
+                        );
+                    };
+                    if data.len() + frame.payload.len() > self.max_message_size {
+                        return Err(self.fail(CLOSE_TOO_BIG, "message too big"));
+                    }
+                    data.extend_from_slice(&frame.payload);
+                    if frame.fin {
+                        return self.message(opcode, data).map(Some);
+                    }
+                    self.fragments = Some((opcode, data));
+                }
+                _ => return Err(self.fail(CLOSE_PROTOCOL_ERROR, "unknown opcode")),
+            }
+        }
+    }
+
+    /// Sends a message.
+    pub fn send(&self, message: impl Into<Message>) -> io::Result<()> {
+        self.shared.send(message.into())
+    }
+
+    /// Starts the closing handshake with `code` (1000 for a normal close)
+    /// and `reason`. Keep calling [`recv`](WebSocket::recv) until it returns
+    /// `None` to let the client confirm.
+    pub fn close(&self, code: u16, reason: &str) -> io::Result<()> {
+        self.shared.close(code, reason)
+    }
+
+    /// A handle for sending from other threads, e.g. to push updates.
+    pub fn sender(&self) -> Sender {
+        Sender {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+
+    /// Reads the next frame, letting senders at the stream while waiting.
+    fn read_frame(&self) -> Result<Option<Frame>, FrameError> {
+        loop {
+            while self.shared.waiting.load(Ordering::SeqCst) > 0 {
+                thread::yield_now();
+            }
+            let mut stream = self.shared.lock();
+            stream
+                .get_ref()
+                .tcp()
+                .set_read_timeout(Some(POLL_INTERVAL))?;
+            match stream.fill_buf() {
+                Ok([]) => return Ok(None),
+                Ok(_) => {
+                    stream
+                        .get_ref()
+                        .tcp()
+                        .set_read_timeout(Some(FRAME_TIMEOUT))?;
+                    return parse_frame(&mut *stream, self.max_message_size).map(Some);
+                }
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted
+                    ) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    fn message(&self, opcode: u8, data: Vec<u8>) -> io::Result<Message> {
+        if opcode == OP_BINARY {
+            return Ok(Message::Binary(data));
+        }
+        String::from_utf8(data)
+            .map(Message::Text)
+            .map_err(|_| self.fail(CLOSE_INVALID_DATA, "text message is not valid UTF-8"))
+    }
+
+    /// Closes the connection with `code` and returns the error to report.
+    fn fail(&self, code: u16, reason: &'static str) -> io::Error {
+        let _ = self.shared.close(code, reason);
+        io::Error::new(ErrorKind::InvalidData, reason)
+    }
+}
+
+impl Drop for WebSocket {
+    fn drop(&mut self) {
+        let _ = self.shared.close(CLOSE_NORMAL, "");
+        let _ = self.shared.lock().get_ref().tcp().shutdown(Shutdown::Both);
+    }
+}
+
+impl Sender {
+    /// Sends a message. Fails once the connection is closing or closed.
+    pub fn send(&self, message: impl Into<Message>) -> io::Result<()> {
+        self.shared.send(message.into())
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, BufReader<Stream>> {
+        self.stream.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn send(&self, message: Message) -> io::Result<()> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(io::Error::new(
+                ErrorKind::NotConnected,
+                "the WebSocket is closed",
+            ));
+        }
+        match message {
+            Message::Text(text) => self.write(OP_TEXT, text.as_bytes()),
+            Message::Binary(bytes) => self.write(OP_BINARY, &bytes),
+        }
+    }
+
+    /// Sends a close frame, unless one went out already.
+    fn close(&self, code: u16, reason: &str) -> io::Result<()> {
+        if self.closed.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        let mut payload = code.to_be_bytes().to_vec();
+        // Control frames carry at most 125 bytes.
+        let mut end = reason.len().min(123);
+        while !reason.is_char_boundary(end) {
+            end -= 1;
+        }
+        payload.extend_from_slice(&reason.as_bytes()[..end]);
+        self.write(OP_CLOSE, &payload)
+    }
+
+    /// Writes one unmasked, unfragmented frame.
+    fn write(&self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        let mut frame = Vec::with_capacity(payload.len() + 10);
+        frame.push(0x80 | opcode);
+        match payload.len() {
+            len @ 0..=125 => frame.push(len as u8),
+            len @ 126..=0xFFFF => {
+                frame.push(126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(payload);
+
+        self.waiting.fetch_add(1, Ordering::SeqCst);
+        let mut stream = self.lock();
+        self.waiting.fetch_sub(1, Ordering::SeqCst);
+        let stream = stream.get_mut();
+        stream.write_all(&frame)?;
+        stream.flush()
+    }
+}
+
+/// Reads one frame sent by a client, unmasking it.
+fn parse_frame<R: Read>(reader: &mut R, max_size: usize) -> Result<Frame, FrameError> {
+    let mut head = [0; 2];
+    reader.read_exact(&mut head)?;
+    let fin = head[0] & 0x80 != 0;
+    let opcode = head[0] & 0x0F;
+    if head[0] & 0x70 != 0 {
+        return Err(FrameError::Close(CLOSE_PROTOCOL_ERROR, "reserved bits set"));
+    }
+    if head[1] & 0x80 == 0 {
+        return Err(FrameError::Close(
+            CLOSE_PROTOCOL_ERROR,
+            "client frames must be masked",
+        ));
+    }
+
+    let len = match head[1] & 0x7F {
+        126 => {
+            let mut len = [0; 2];
+            reader.read_exact(&mut len)?;
+            u64::from(u16::from_be_bytes(len))
+        }
+        127 => {
+            let mut len = [0; 8];
+            reader.read_exact(&mut len)?;
+            u64::from_be_bytes(len)
+        }
+        len => u64::from(len),
+    };
+    if opcode >= OP_CLOSE && (!fin || len > 125) {
+        return Err(FrameError::Close(
+            CLOSE_PROTOCOL_ERROR,
+            "malformed control frame",
+        ));
+    }
+    if len > max_size as u64 {
+        return Err(FrameError::Close(CLOSE_TOO_BIG, "message too big"));
+    }
+
+    let mut mask = [0; 4];
+    reader.read_exact(&mut mask)?;
+    let mut payload = vec![0; len as usize];
+    reader.read_exact(&mut payload)?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    Ok(Frame {
+        fin,
+        opcode,
+        payload,
+    })
+}
+
+/// Status codes a close frame may carry (RFC 6455, section 7.4).
+fn is_valid_close_code(code: u16) -> bool {
+    matches!(code, 1000..=1003 | 1007..=1011 | 3000..=4999)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn masked(first: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [1, 2, 3, 4];
+        let mut frame = vec![first, 0x80 | payload.len() as u8];
+        frame.extend(mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        frame
+    }
+
+    #[test]
+    fn computes_the_accept_key() {
+        // The example from RFC 6455, section 1.3.
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn parses_masked_frames_and_rejects_bad_ones() {
+        let frame = parse_frame(&mut &masked(0x81, b"Hello")[..], 1024)
+            .ok()
+            .unwrap();
+        assert!(frame.fin);
+        assert_eq!((frame.opcode, frame.payload), (OP_TEXT, b"Hello".to_vec()));
+
+        let unmasked = [0x81, 0x01, b'x'];
+        let fragmented_ping = masked(OP_PING, b"");
+        let too_big = masked(0x82, &[0; 100]);
+        for (raw, expected) in [
+            (&unmasked[..], CLOSE_PROTOCOL_ERROR),
+            (&fragmented_ping, CLOSE_PROTOCOL_ERROR),
+            (&too_big, CLOSE_TOO_BIG),
+        ] {
+            match parse_frame(&mut &raw[..], 64) {
+                Err(FrameError::Close(code, _)) => assert_eq!(code, expected),
+                _ => panic!("{raw:?} accepted"),
+            }
+        }
+    }
+}
@@ -1,17 +1,55 @@
 use std::{
     panic::{AssertUnwindSafe, catch_unwind},
-    sync::{Arc, Mutex, mpsc::Receiver},
+    sync::{
+        Arc, Mutex, PoisonError,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        mpsc::Receiver,
+    },
     thread,
     time::Instant,
 };
 
 use uuid::Uuid;
 
+use crate::log::{debug, error};
+
 pub(crate) struct Job {
     pub(crate) id: Uuid,
     pub(crate) closure: Box<dyn FnOnce() + Send + 'static>,
 }
 
+/// Counters shared by a pool and its workers, read by
+/// [`Metrics`](crate::metrics::Metrics).
+#[derive(Debug, Default)]
+pub(crate) struct PoolStats {
+    pub(crate) workers: AtomicUsize,
+    /// Jobs sent but not yet taken by a worker.
+    pub(crate) queued: AtomicUsize,
+    /// Workers running a job.
+    pub(crate) busy: AtomicUsize,
+    pub(crate) completed: AtomicU64,
+    pub(crate) panicked: AtomicU64,
+    /// The job each worker is running and since when, by worker id.
+    pub(crate) jobs: Mutex<Vec<Option<(Uuid, Instant)>>>,
+}
+
+impl PoolStats {
+    pub(crate) fn new(workers: usize) -> PoolStats {
+        PoolStats {
+            workers: AtomicUsize::new(workers),
+            jobs: Mutex::new(vec![None; workers]),
+            ..PoolStats::default()
+        }
+    }
+
+    fn set_job(&self, worker: usize, job: Option<(Uuid, Instant)>) {
+        let mut jobs = self.jobs.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(slot) = jobs.get_mut(worker) {
+            *slot = job;
+        }
+    }
+}
+
 pub(crate) struct Worker {
     pub(crate) id: usize,
     pub(crate) thread: thread::JoinHandle<()>,
@@ -21,6 +59,7 @@ impl Worker {
     pub(crate) fn new(
         id: usize,
         receiver: Arc<Mutex<Receiver<Job>>>,
+        stats: Arc<PoolStats>,
     ) -> Result<Worker, std::io::Error> {
         let builder = thread::Builder::new();
 
@@ -29,23 +68,24 @@ impl Worker {
                 let job = {
                     let job_receiver = receiver.lock().unwrap_or_else(|poisoned| {
                         // Log the issue and continue with potentially corrupted data
-                        eprintln!("Mutex was poisoned, continuing anyway");
+                        error!("Mutex was poisoned, continuing anyway");
                         poisoned.into_inner()
                     });
 
-                    let job = match job_receiver.recv() {
+                    match job_receiver.recv() {
                         Ok(job) => job,
                         Err(_) => {
-                            println!("Worker {id} disconnected, shutting down");
+                            debug!("Worker {id} disconnected, shutting down");
                             break 'outer;
                         }
-                    };
-
-                    job
+                    }
                 }; // drop mutext guard
+                stats.queued.fetch_sub(1, Ordering::Relaxed);
+                stats.busy.fetch_add(1, Ordering::Relaxed);
+                stats.set_job(id, Some((job.id, Instant::now())));
 
-                if let Err(_) = catch_unwind(AssertUnwindSafe(|| {
-                    println!("Worker.{id}: {} Taken ", job.id);
+                if catch_unwind(AssertUnwindSafe(|| {
+                    debug!("Worker.{id}: {} Taken ", job.id);
                     let start = Instant::now();
 
                     (job.closure)();
@@ -59,10 +99,17 @@ impl Worker {
                         _ => format!("{:.2}s", execution_time.as_secs_f64()),
                     };
 
-                    println!("Worker.{id}: {} Done in {}", job.id, formatted_time);
-                })) {
-                    eprintln!("Worker.{id}: Job panicked, but worker continues");
+                    debug!("Worker.{id}: {} Done in {}", job.id, formatted_time);
+                }))
+                .is_err()
+                {
+                    error!("Worker.{id}: Job panicked, but worker continues");
+                    stats.panicked.fetch_add(1, Ordering::Relaxed);
+                } else {
+                    stats.completed.fetch_add(1, Ordering::Relaxed);
                 }
+                stats.set_job(id, None);
+                stats.busy.fetch_sub(1, Ordering::Relaxed);
             }
         })?;
 
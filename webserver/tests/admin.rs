@@ -0,0 +1,173 @@
+use std::{
+    io::{Cursor, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    thread,
+    time::{Duration, Instant},
+};
+
+use webserver::{
+    Listener, ParseLimits, Request, Response, Server, StatusCode,
+    admin::Health,
+    connection::ConnectionConfig,
+    log,
+    middleware::{BasicAuth, Middleware},
+};
+
+/// Sends one request with `Connection: close` and returns the response.
+fn send(address: SocketAddr, method: &str, path: &str, body: &str) -> String {
+    let mut client = TcpStream::connect(address).unwrap();
+    write!(
+        client,
+        "{method} {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    )
+    .unwrap();
+    let mut response = String::new();
+    client.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn admin_listener_reports_and_shuts_down() {
+    let app = TcpListener::bind("127.0.0.1:0").unwrap();
+    let admin = TcpListener::bind("127.0.0.1:0").unwrap();
+    let admin_addr = admin.local_addr().unwrap();
+    let server = Server::from_listeners(
+        vec![Listener::Http(app), Listener::Admin(admin)],
+        1,
+        ConnectionConfig::default(),
+        |_: &mut Request| {
+            thread::sleep(Duration::from_millis(300));
+            Response::text(StatusCode::OK, "slow")
+        },
+    )
+    .unwrap();
+    let app_addr = server.local_addr().unwrap();
+    let running = thread::spawn(move || {
+        server.run().unwrap();
+        server.shutdown(Duration::from_secs(5))
+    });
+
+    // The only worker is busy, yet the admin listener answers.
+    let slow = thread::spawn(move || send(app_addr, "GET", "/", ""));
+    thread::sleep(Duration::from_millis(100));
+    let workers = send(admin_addr, "GET", "/workers", "");
+    assert!(workers.contains("\"state\":\"busy\""), "{workers}");
+    assert!(workers.contains("\"open\":2"), "{workers}");
+
+    assert!(send(admin_addr, "GET", "/readyz", "").starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(send(admin_addr, "GET", "/config", "").starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+    let previous = log::level();
+    let response = send(admin_addr, "PUT", "/log-level", "warn");
+    assert!(response.ends_with("\r\n\r\nwarn\n"), "{response}");
+    assert_eq!(log::level(), log::Level::Warn);
+    let response = send(admin_addr, "PUT", "/log-level", "loud");
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    log::set_level(previous);
+
+    let response = send(admin_addr, "POST", "/shutdown", "");
+    assert!(response.starts_with("HTTP/1.1 202 Accepted\r\n"));
+    assert!(slow.join().unwrap().ends_with("slow"));
+    assert_eq!(running.join().unwrap().aborted, 0);
+}
+
+#[test]
+fn readiness_fails_once_shutdown_begins() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let server = Server::from_listener(
+        listener,
+        1,
+        ConnectionConfig::default(),
+        |_: &mut Request| Response::text(StatusCode::OK, "app"),
+    )
+    .unwrap();
+    let health = Health::new(server.handle());
+    let app = |_: &mut Request| Response::text(StatusCode::OK, "app");
+    let get = |path: &str| {
+        let raw = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        let mut request = Request::parse(&mut Cursor::new(raw), &ParseLimits::default()).unwrap();
+        health.handle(&mut request, &app).status()
+    };
+
+    assert_eq!(get("/readyz"), StatusCode::OK);
+    server.handle().shutdown();
+    assert_eq!(get("/readyz"), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(get("/healthz"), StatusCode::OK);
+    assert_eq!(get("/other"), StatusCode::OK);
+}
+
+#[test]
+fn admin_auth_and_connection_cap() {
+    let app = TcpListener::bind("127.0.0.1:0").unwrap();
+    let admin = TcpListener::bind("127.0.0.1:0").unwrap();
+    let admin_addr = admin.local_addr().unwrap();
+    let server = Server::from_listeners(
+        vec![Listener::Http(app), Listener::Admin(admin)],
+        1,
+        ConnectionConfig::default(),
+        |_: &mut Request| Response::text(StatusCode::OK, "app"),
+    )
+    .unwrap()
+    .max_connections(1)
+    .max_admin_connections(2)
+    .admin_auth(BasicAuth::new("admin").user("ops", "secret"));
+    let app_addr = server.local_addr().unwrap();
+    let running = thread::spawn(move || {
+        server.run().unwrap();
+        server.shutdown(Duration::from_secs(5))
+    });
+
+    let response = send(admin_addr, "POST", "/shutdown", "");
+    assert!(
+        response.starts_with("HTTP/1.1 401 Unauthorized\r\n"),
+        "{response}"
+    );
+
+    // Idle connections hold their admin threads until one is turned away.
+    let mut held = Vec::new();
+    loop {
+        let mut client = TcpStream::connect(admin_addr).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        let mut response = String::new();
+        if client.read_to_string(&mut response).is_ok() {
+            assert!(
+                response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"),
+                "{response}"
+            );
+            break;
+        }
+        held.push(client);
+        assert!(held.len() <= 2, "the cap is not enforced");
+    }
+    // They don't take the only app slot.
+    assert!(send(app_addr, "GET", "/", "").ends_with("\r\n\r\napp"));
+    drop(held);
+
+    // Retried until the admin threads of the dropped connections are gone.
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let response = TcpStream::connect(admin_addr).and_then(|mut client| {
+            client.write_all(
+                b"POST /shutdown HTTP/1.1\r\nHost: localhost\r\nAuthorization: Basic b3BzOnNlY3JldA==\r\nConnection: close\r\nContent-Length: 0\r\n\r\n",
+            )?;
+            let mut response = String::new();
+            client.read_to_string(&mut response)?;
+            Ok(response)
+        });
+        if let Ok(response) = &response
+            && !response.starts_with("HTTP/1.1 503 ")
+        {
+            assert!(
+                response.starts_with("HTTP/1.1 202 Accepted\r\n"),
+                "{response}"
+            );
+            break;
+        }
+        assert!(Instant::now() < deadline, "admin slots were not released");
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(running.join().unwrap().aborted, 0);
+}
@@ -0,0 +1,303 @@
+mod common;
+
+use std::{
+    env, fs,
+    io::{Read, Write},
+    os::unix::{fs::PermissionsExt, net::UnixListener},
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
+
+use webserver::{Cgi, FastCgi, Router, connection::ConnectionConfig};
+
+/// Writes an executable shell script to a fresh temporary directory.
+fn script(body: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("cgi-{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("script.sh");
+    fs::write(&path, format!("#!/bin/sh\n{body}")).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
+
+fn send(router: Router, request: &str) -> String {
+    let mut stream = common::serve_one_connection(router, ConnectionConfig::default());
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn runs_programs_with_meta_variables_and_body() {
+    let program = script(
+        "echo 'Status: 201 Created'\n\
+         echo 'Content-Type: text/plain'\n\
+         echo\n\
+         echo \"$REQUEST_METHOD $SCRIPT_NAME $PATH_INFO $QUERY_STRING $HTTP_X_TOKEN\"\n\
+         head -c \"$CONTENT_LENGTH\"\n\
+         echo 'to the log' >&2\n",
+    );
+    let router = Router::new().any("/bin/*path", Cgi::new(&program).script_name("/bin"));
+    let response = send(
+        router,
+        "POST /bin/report/2024?full=1 HTTP/1.1\r\nHost: localhost\r\nX-Token: t\r\n\
+         Connection: close\r\nContent-Length: 5\r\n\r\nhello",
+    );
+
+    assert!(
+        response.starts_with("HTTP/1.1 201 Created\r\n"),
+        "{response}"
+    );
+    assert!(response.contains("\r\nContent-Type: text/plain\r\n"));
+    assert!(response.ends_with("\r\n\r\nPOST /bin /report/2024 full=1 t\nhello"));
+
+    fs::remove_dir_all(program.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn slow_and_broken_programs_are_gateway_errors() {
+    let slow = script("sleep 5\necho 'Content-Type: text/plain'\necho\n");
+    let broken = script("echo 'no header line'\n");
+    let router = || {
+        Router::new()
+            .any("/slow", Cgi::new(&slow).timeout(Duration::from_millis(200)))
+            .any("/broken", Cgi::new(&broken))
+    };
+
+    let response = send(
+        router(),
+        "GET /slow HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+    );
+    assert!(
+        response.starts_with("HTTP/1.1 504 Gateway Timeout\r\n"),
+        "{response}"
+    );
+    let response = send(
+        router(),
+        "GET /broken HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+    );
+    assert!(
+        response.starts_with("HTTP/1.1 502 Bad Gateway\r\n"),
+        "{response}"
+    );
+
+    for program in [slow, broken] {
+        fs::remove_dir_all(program.parent().unwrap()).unwrap();
+    }
+}
+
+#[test]
+fn oversized_output_is_a_gateway_error() {
+    // Writes forever unless it is killed.
+    let chatty = script("echo 'Content-Type: text/plain'\necho\nyes\n");
+    let router = Router::new().any(
+        "/chatty",
+        Cgi::new(&chatty)
+            .timeout(Duration::from_secs(5))
+            .max_response_size(64 * 1024),
+    );
+    let started = Instant::now();
+    let response = send(
+        router,
+        "GET /chatty HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+    );
+    assert!(
+        response.starts_with("HTTP/1.1 502 Bad Gateway\r\n"),
+        "{response:.40}"
+    );
+    assert!(started.elapsed() < Duration::from_secs(3));
+
+    fs::remove_dir_all(chatty.parent().unwrap()).unwrap();
+}
+
+/// Reads one FastCGI record: its type and content.
+fn read_record(stream: &mut impl Read) -> (u8, Vec<u8>) {
+    let mut header = [0; 8];
+    stream.read_exact(&mut header).unwrap();
+    let mut content = vec![0; usize::from(u16::from_be_bytes([header[4], header[5]]))];
+    stream.read_exact(&mut content).unwrap();
+    stream
+        .read_exact(&mut vec![0; usize::from(header[6])])
+        .unwrap();
+    (header[1], content)
+}
+
+fn write_record(stream: &mut impl Write, kind: u8, content: &[u8]) {
+    let [length_high, length_low] = (content.len() as u16).to_be_bytes();
+    stream
+        .write_all(&[1, kind, 0, 1, length_high, length_low, 0, 0])
+        .unwrap();
+    stream.write_all(content).unwrap();
+}
+
+#[test]
+fn talks_to_fastcgi_responders() {
+    let socket = env::temp_dir().join(format!("fcgi-{}.sock", uuid::Uuid::new_v4()));
+    let listener = UnixListener::bind(&socket).unwrap();
+    let responder = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let (mut params, mut stdin) = (Vec::new(), Vec::new());
+        let mut begun = false;
+        loop {
+            match read_record(&mut stream) {
+                (1, _) => begun = true,
+                (4, content) => params.extend(content),
+                (5, content) if content.is_empty() => break,
+                (5, content) => stdin.extend(content),
+                (kind, _) => panic!("unexpected record type {kind}"),
+            }
+        }
+        assert!(begun);
+
+        // Every name and value here is shorter than 128 bytes.
+        let mut variables = Vec::new();
+        let mut rest = &params[..];
+        while let [name_length, value_length, tail @ ..] = rest {
+            let (name, tail) = tail.split_at(usize::from(*name_length));
+            let (value, tail) = tail.split_at(usize::from(*value_length));
+            variables.push(format!(
+                "{}={}",
+                String::from_utf8_lossy(name),
+                String::from_utf8_lossy(value)
+            ));
+            rest = tail;
+        }
+        let find = |name: &str| {
+            variables
+                .iter()
+                .find_map(|variable| variable.strip_prefix(&format!("{name}=")))
+                .unwrap_or_default()
+                .to_owned()
+        };
+        let body = format!(
+            "{} {} {} {}",
+            find("PATH_INFO"),
+            find("SCRIPT_FILENAME"),
+            find("CONTENT_LENGTH"),
+            String::from_utf8(stdin).unwrap()
+        );
+        write_record(&mut stream, 7, b"warning from the app\n");
+        write_record(
+            &mut stream,
+            6,
+            format!("Content-Type: text/plain\r\n\r\n{body}").as_bytes(),
+        );
+        write_record(&mut stream, 6, &[]);
+        write_record(&mut stream, 3, &[0; 8]);
+    });
+
+    let router = Router::new().any(
+        "/app/*path",
+        FastCgi::new(&socket)
+            .script_name("/app")
+            .param("SCRIPT_FILENAME", "/srv/index.php"),
+    );
+    let response = send(
+        router,
+        "PUT /app/users/7 HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+         Content-Length: 4\r\n\r\nbody",
+    );
+    responder.join().unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(response.ends_with("\r\n\r\n/users/7 /srv/index.php 4 body"));
+
+    fs::remove_file(socket).unwrap();
+}
+
+#[test]
+fn oversized_fastcgi_answers_are_gateway_errors() {
+    let socket = env::temp_dir().join(format!("fcgi-{}.sock", uuid::Uuid::new_v4()));
+    let listener = UnixListener::bind(&socket).unwrap();
+    let responder = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        loop {
+            match read_record(&mut stream) {
+                (5, content) if content.is_empty() => break,
+                _ => {}
+            }
+        }
+        write_record(&mut stream, 6, b"Content-Type: text/plain\r\n\r\n");
+        // The client may hang up before all of it is written.
+        for _ in 0..4 {
+            let [length_high, length_low] = 60_000u16.to_be_bytes();
+            let record = [
+                &[1, 6, 0, 1, length_high, length_low, 0, 0][..],
+                &[b'x'; 60_000],
+            ];
+            if stream.write_all(&record.concat()).is_err() {
+                return;
+            }
+        }
+        let _ = stream.write_all(&[1, 3, 0, 1, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    });
+
+    let router = Router::new().any("/app", FastCgi::new(&socket).max_response_size(100_000));
+    let response = send(
+        router,
+        "GET /app HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+    );
+    responder.join().unwrap();
+
+    assert!(
+        response.starts_with("HTTP/1.1 502 Bad Gateway\r\n"),
+        "{response}"
+    );
+
+    fs::remove_file(socket).unwrap();
+}
+
+#[test]
+fn fastcgi_responders_may_answer_before_reading_the_body() {
+    let socket = env::temp_dir().join(format!("fcgi-{}.sock", uuid::Uuid::new_v4()));
+    let listener = UnixListener::bind(&socket).unwrap();
+    let answer = "x".repeat(60_000);
+    let responder = thread::spawn({
+        let answer = answer.clone();
+        move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            loop {
+                match read_record(&mut stream) {
+                    (4, content) if content.is_empty() => break,
+                    _ => {}
+                }
+            }
+            // Far more than the socket buffers hold, while the body is
+            // still waiting to be read.
+            write_record(&mut stream, 6, b"Content-Type: text/plain\r\n\r\n");
+            for _ in 0..20 {
+                write_record(&mut stream, 6, answer.as_bytes());
+            }
+            write_record(&mut stream, 3, &[0; 8]);
+        }
+    });
+
+    let router = Router::new().any(
+        "/upload",
+        FastCgi::new(&socket).timeout(Duration::from_secs(5)),
+    );
+    let body = "b".repeat(4 * 1024 * 1024);
+    let mut stream = common::serve_one_connection(router, ConnectionConfig::default());
+    let started = Instant::now();
+    write!(
+        stream,
+        "POST /upload HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+         Content-Length: {}\r\n\r\n{body}",
+        body.len()
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    responder.join().unwrap();
+
+    assert!(
+        response.starts_with("HTTP/1.1 200 OK\r\n"),
+        "{response:.40}"
+    );
+    assert!(response.ends_with(&answer));
+    assert!(started.elapsed() < Duration::from_secs(3));
+
+    fs::remove_file(socket).unwrap();
+}
@@ -0,0 +1,27 @@
+use std::{
+    net::{TcpListener, TcpStream},
+    thread,
+};
+
+use uuid::Uuid;
+use webserver::{
+    Handler,
+    connection::{ConnectionConfig, handle_connection},
+};
+
+/// Serves a single connection with `handler` on a background thread and
+/// returns the client side.
+pub fn serve_one_connection<H: Handler + 'static>(
+    handler: H,
+    config: ConnectionConfig,
+) -> TcpStream {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        handle_connection(Uuid::new_v4(), stream, &handler, &config);
+    });
+
+    TcpStream::connect(address).unwrap()
+}
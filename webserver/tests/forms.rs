@@ -0,0 +1,140 @@
+mod common;
+
+use std::io::{Read, Write};
+
+use webserver::{
+    Method, Request, Response, Router, StatusCode,
+    connection::ConnectionConfig,
+    form::{Form, FormLimits},
+    handler::StreamingBody,
+};
+
+/// Answers with the uploaded file's name, size, location and first line.
+fn upload(request: &mut Request) -> Response {
+    let limits = FormLimits {
+        max_parts: 2,
+        memory_threshold: 8,
+        ..FormLimits::default()
+    };
+    let form = match Form::parse(request, &limits) {
+        Ok(form) => form,
+        Err(e) => return Response::error(e.status()),
+    };
+    let Some(file) = form.file("report") else {
+        return Response::error(StatusCode::BAD_REQUEST);
+    };
+    let mut contents = String::new();
+    file.open().unwrap().read_to_string(&mut contents).unwrap();
+    Response::text(
+        StatusCode::OK,
+        format!(
+            "{} by {}: {} bytes {}, {}",
+            file.file_name().unwrap(),
+            form.field("author").unwrap(),
+            file.len(),
+            if file.path().is_some() {
+                "on disk"
+            } else {
+                "in memory"
+            },
+            contents.lines().next().unwrap()
+        ),
+    )
+}
+
+#[test]
+fn accepts_uploads_and_refuses_bad_forms() {
+    let router = Router::new().post("/upload", upload);
+    let mut stream = common::serve_one_connection(router, ConnectionConfig::default());
+
+    let body = "--b0undary\r\n\
+         Content-Disposition: form-data; name=\"author\"\r\n\r\n\
+         ada\r\n--b0undary\r\n\
+         Content-Disposition: form-data; name=\"report\"; filename=\"q3.csv\"\r\n\
+         Content-Type: text/csv\r\n\r\n\
+         month,total\r\njuly,12\r\n\r\n--b0undary--\r\n";
+    let extra_part = "--b0undary\r\nContent-Disposition: form-data; name=\"x\"\r\n\r\n\r\n";
+    let too_many = format!("{extra_part}{extra_part}{body}");
+    let mut requests = String::new();
+    for (content_type, body) in [
+        ("multipart/form-data; boundary=b0undary", body),
+        ("multipart/form-data; boundary=b0undary", &too_many),
+        ("text/csv", "month,total\r\n"),
+    ] {
+        requests.push_str(&format!(
+            "POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Type: {content_type}\r\n\
+             Content-Length: {}\r\n\r\n{body}",
+            body.len()
+        ));
+    }
+    requests.push_str("GET /nothing HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    stream.write_all(requests.as_bytes()).unwrap();
+
+    let mut responses = String::new();
+    stream.read_to_string(&mut responses).unwrap();
+    let statuses: Vec<_> = responses
+        .split("HTTP/1.1 ")
+        .skip(1)
+        .map(|response| &response[..3])
+        .collect();
+    assert_eq!(statuses, ["200", "413", "415", "404"]);
+    assert!(
+        responses.contains("\r\n\r\nq3.csv by ada: 22 bytes on disk, month,total"),
+        "{responses}"
+    );
+}
+
+#[test]
+fn streams_uploads_past_the_connection_body_limit() {
+    let streamed = |request: &mut Request| {
+        let limits = FormLimits {
+            max_size: 256 * 1024,
+            memory_threshold: 1024,
+            ..FormLimits::default()
+        };
+        match Form::parse(request, &limits) {
+            Ok(form) => {
+                let file = form.file("backup").unwrap();
+                let on_disk = std::fs::metadata(file.path().unwrap()).unwrap().len();
+                Response::text(StatusCode::OK, format!("{} {on_disk}", file.len()))
+            }
+            Err(e) => Response::error(e.status()),
+        }
+    };
+    let router = Router::new().route(Method::Post, "/backup", StreamingBody(streamed));
+    let config = ConnectionConfig {
+        max_body_size: 16 * 1024,
+        ..ConnectionConfig::default()
+    };
+    let mut stream = common::serve_one_connection(router, config);
+
+    let upload = |size: usize| {
+        format!(
+            "--b0undary\r\n\
+             Content-Disposition: form-data; name=\"backup\"; filename=\"db.dump\"\r\n\r\n\
+             {}\r\n--b0undary--\r\n",
+            "x".repeat(size)
+        )
+    };
+    let mut requests = String::new();
+    for body in [upload(200 * 1024), upload(300 * 1024)] {
+        requests.push_str(&format!(
+            "POST /backup HTTP/1.1\r\nHost: localhost\r\n\
+             Content-Type: multipart/form-data; boundary=b0undary\r\n\
+             Content-Length: {}\r\n\r\n{body}",
+            body.len()
+        ));
+    }
+    stream.write_all(requests.as_bytes()).unwrap();
+
+    let mut responses = String::new();
+    stream.read_to_string(&mut responses).unwrap();
+    let statuses: Vec<_> = responses
+        .split("HTTP/1.1 ")
+        .skip(1)
+        .map(|response| &response[..3])
+        .collect();
+    // The connection is closed after the second, whose body is left unread.
+    assert_eq!(statuses, ["200", "413"], "{responses}");
+    assert!(responses.contains("\r\n\r\n204800 204800"), "{responses}");
+}
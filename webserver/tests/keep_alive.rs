@@ -0,0 +1,61 @@
+mod common;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+use webserver::{Request, Response, Router, StatusCode, connection::ConnectionConfig};
+
+fn serve_one_connection(config: ConnectionConfig) -> TcpStream {
+    let router = Router::new().get("/:name", |request: &mut Request| {
+        Response::text(StatusCode::OK, request.param("name").unwrap().to_owned())
+    });
+    common::serve_one_connection(router, config)
+}
+
+#[test]
+fn pipelined_requests_are_answered_in_order() {
+    let mut stream = serve_one_connection(ConnectionConfig::default());
+    stream
+        .write_all(b"GET /a HTTP/1.1\r\nHost: localhost\r\n\r\nGET /b HTTP/1.1\r\nHost: localhost\r\n\r\nGET /c HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    let bodies: Vec<&str> = response
+        .split("HTTP/1.1 200 OK")
+        .skip(1)
+        .map(|part| part.rsplit("\r\n\r\n").next().unwrap())
+        .collect();
+    assert_eq!(bodies, ["a", "b", "c"]);
+    assert!(response.ends_with("Connection: close\r\nContent-Length: 1\r\n\r\nc"));
+}
+
+#[test]
+fn closes_after_max_requests() {
+    let config = ConnectionConfig {
+        max_requests: 2,
+        ..ConnectionConfig::default()
+    };
+    let mut stream = serve_one_connection(config);
+    stream
+        .write_all(b"GET /a HTTP/1.1\r\nHost: localhost\r\n\r\nGET /b HTTP/1.1\r\nHost: localhost\r\n\r\nGET /c HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    assert_eq!(response.matches("HTTP/1.1 200 OK").count(), 2);
+    assert!(response.ends_with("Connection: close\r\nContent-Length: 1\r\n\r\nb"));
+}
+
+#[test]
+fn http10_closes_by_default() {
+    let mut stream = serve_one_connection(ConnectionConfig::default());
+    stream.write_all(b"GET /a HTTP/1.0\r\n\r\n").unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    assert!(response.contains("Connection: close\r\n"));
+}
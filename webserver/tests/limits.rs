@@ -0,0 +1,81 @@
+mod common;
+
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+    time::{Duration, Instant},
+};
+
+use webserver::{Request, Response, Server, StatusCode, connection::ConnectionConfig};
+
+#[test]
+fn cuts_off_headers_trickling_in() {
+    let config = ConnectionConfig {
+        header_timeout: Duration::from_millis(300),
+        ..ConnectionConfig::default()
+    };
+    let app = |_: &mut Request| Response::text(StatusCode::OK, "ok");
+    let mut stream = common::serve_one_connection(app, config);
+
+    // Every byte arrives after a pause longer than the per-read timeout.
+    let started = Instant::now();
+    for byte in b"GET / HTTP/1.1\r\nX-Slow: "
+        .iter()
+        .chain([b'a'; 64].iter())
+    {
+        if stream.write_all(&[*byte]).is_err() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(
+        response.starts_with("HTTP/1.1 408 Request Timeout\r\n"),
+        "{response}"
+    );
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn refuses_connections_over_the_limit() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let server = Server::from_listener(
+        listener,
+        2,
+        ConnectionConfig::default(),
+        |_: &mut Request| Response::text(StatusCode::OK, "ok"),
+    )
+    .unwrap()
+    .max_connections(1);
+    let address = server.local_addr().unwrap();
+    let handle = server.handle();
+    let running = thread::spawn(move || {
+        server.run().unwrap();
+        server.shutdown(Duration::from_secs(1))
+    });
+
+    // Held open by keep-alive.
+    let mut first = TcpStream::connect(address).unwrap();
+    first
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut buf = [0; 12];
+    first.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"HTTP/1.1 200");
+
+    let mut second = TcpStream::connect(address).unwrap();
+    let mut response = String::new();
+    second.read_to_string(&mut response).unwrap();
+    assert!(
+        response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"),
+        "{response}"
+    );
+    assert!(response.contains("\r\nRetry-After: 1\r\n"));
+
+    handle.shutdown();
+    drop(first);
+    running.join().unwrap();
+}
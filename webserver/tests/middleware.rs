@@ -0,0 +1,36 @@
+mod common;
+
+use std::io::{Read, Write};
+
+use webserver::{
+    Request, Response, StatusCode,
+    connection::ConnectionConfig,
+    middleware::{Chain, RequestId, Timing},
+};
+
+#[test]
+fn request_ids_follow_the_connection() {
+    let handler = |request: &mut Request| {
+        let id = request.header("X-Request-Id").unwrap_or("none").to_owned();
+        Response::text(StatusCode::OK, id)
+    };
+    let app = Chain::new(handler).with(RequestId::new()).with(Timing);
+    let mut client = common::serve_one_connection(app, ConnectionConfig::default());
+
+    client
+        .write_all(b"GET /a HTTP/1.1\r\nHost: localhost\r\n\r\nGET /b HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut responses = String::new();
+    client.read_to_string(&mut responses).unwrap();
+
+    let ids: Vec<_> = responses
+        .lines()
+        .filter_map(|line| line.strip_prefix("X-Request-Id: "))
+        .collect();
+    assert_eq!(ids.len(), 2);
+    let (connection, first) = ids[0].rsplit_once('-').unwrap();
+    assert_eq!(first, "1");
+    assert_eq!(ids[1], format!("{connection}-2"));
+    assert!(responses.ends_with(ids[1]));
+    assert_eq!(responses.matches("X-Response-Time: ").count(), 2);
+}
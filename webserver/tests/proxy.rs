@@ -0,0 +1,153 @@
+mod common;
+
+use std::{
+    io::{BufRead, BufReader, Cursor, Read, Write},
+    net::{SocketAddr, TcpListener},
+    thread,
+    time::Duration,
+};
+
+use webserver::{
+    Handler, ParseLimits, Proxy, Request, Router, StatusCode, connection::ConnectionConfig,
+};
+
+/// An upstream answering every request with `201 Created`, naming itself in
+/// `X-Upstream` and echoing the request head and body.
+fn upstream(name: &'static str) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut head = String::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim_end().is_empty() {
+                    break;
+                }
+                head.push_str(&line);
+            }
+            let length = head
+                .lines()
+                .find_map(|line| line.strip_prefix("Content-Length: "))
+                .map_or(0, |length| length.parse().unwrap());
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+
+            let echo = format!("{head}\r\n{}", String::from_utf8(body).unwrap());
+            write!(
+                stream,
+                "HTTP/1.1 201 Created\r\nX-Upstream: {name}\r\nContent-Length: {}\r\n\r\n{echo}",
+                echo.len()
+            )
+            .unwrap();
+        }
+    });
+    address
+}
+
+/// An address nothing listens on.
+fn closed_port() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+fn get(path: &str) -> Request {
+    let raw = format!("GET {path} HTTP/1.1\r\nHost: example.com\r\n\r\n");
+    Request::parse(&mut Cursor::new(raw), &ParseLimits::default()).unwrap()
+}
+
+#[test]
+fn forwards_requests_and_streams_responses() {
+    let proxy = Proxy::new([upstream("a")]).strip_prefix("/api");
+    let router = Router::new().any("/api/*path", proxy);
+    let mut stream = common::serve_one_connection(router, ConnectionConfig::default());
+    stream
+        .write_all(b"POST /api/items?x=1 HTTP/1.1\r\nHost: example.com:8080\r\nConnection: close\r\nContent-Length: 5\r\n\r\nhello")
+        .unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(
+        response.starts_with("HTTP/1.1 201 Created\r\n"),
+        "{response}"
+    );
+    assert!(response.contains("\r\nX-Upstream: a\r\n"));
+    for forwarded in [
+        "POST /items?x=1 HTTP/1.1\r\n",
+        "\r\nHost: example.com:8080\r\n",
+        "\r\nX-Forwarded-For: 127.0.0.1\r\n",
+        "\r\nForwarded: for=127.0.0.1;host=\"example.com:8080\";proto=http\r\n",
+        "\r\nConnection: close\r\n\r\nhello",
+    ] {
+        assert!(response.contains(forwarded), "{forwarded:?} in {response}");
+    }
+}
+
+#[test]
+fn takes_turns_and_skips_failed_upstreams() {
+    let proxy = Proxy::new([upstream("a"), closed_port(), upstream("b")]);
+
+    let served: Vec<_> = (0..4)
+        .map(|_| {
+            let response = proxy.handle(&mut get("/"));
+            assert_eq!(response.status(), StatusCode::CREATED);
+            response.headers().get("X-Upstream").unwrap().to_owned()
+        })
+        .collect();
+    // The second request finds the closed port and moves on to "b"; after
+    // that the closed port is left out.
+    assert_eq!(served, ["a", "b", "b", "a"]);
+
+    let down = Proxy::new([closed_port()]);
+    assert_eq!(down.handle(&mut get("/")).status(), StatusCode::BAD_GATEWAY);
+}
+
+#[test]
+fn times_out_slow_upstreams() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let silent = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let _held: Vec<_> = listener.incoming().collect();
+    });
+
+    let proxy = Proxy::new([silent]).timeout(Duration::from_millis(100));
+    assert_eq!(
+        proxy.handle(&mut get("/")).status(),
+        StatusCode::GATEWAY_TIMEOUT
+    );
+}
+
+#[test]
+fn does_not_complete_bodies_cut_off_upstream() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut head = String::new();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        while !head.ends_with("\r\n\r\n") {
+            reader.read_line(&mut head).unwrap();
+        }
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhalf")
+            .unwrap();
+    });
+
+    let router = Router::new().any("/*path", Proxy::new([address]));
+    let mut stream = common::serve_one_connection(router, ConnectionConfig::default());
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(response.contains("\r\n\r\n4\r\nhalf\r\n"));
+    // No last chunk: the client can tell the body is incomplete.
+    assert!(!response.ends_with("0\r\n\r\n"), "{response}");
+}
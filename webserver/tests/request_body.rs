@@ -0,0 +1,72 @@
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+use webserver::{Request, Response, Router, StatusCode, connection::ConnectionConfig};
+
+fn serve_one_connection() -> TcpStream {
+    let router = Router::new().post("/echo", |request: &mut Request| {
+        Response::text(StatusCode::OK, request.body().to_vec())
+    });
+    let config = ConnectionConfig {
+        max_body_size: 16,
+        ..ConnectionConfig::default()
+    };
+    common::serve_one_connection(router, config)
+}
+
+#[test]
+fn sends_100_continue_before_reading_the_body() {
+    let stream = serve_one_connection();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    (&stream)
+        .write_all(b"POST /echo HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\nContent-Length: 5\r\nConnection: close\r\n\r\n")
+        .unwrap();
+
+    let mut interim = String::new();
+    reader.read_line(&mut interim).unwrap();
+    assert_eq!(interim, "HTTP/1.1 100 Continue\r\n");
+
+    (&stream).write_all(b"hello").unwrap();
+    let mut response = String::new();
+    reader.read_to_string(&mut response).unwrap();
+    assert!(response.ends_with("\r\nHTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\nConnection: close\r\nContent-Length: 5\r\n\r\nhello"));
+}
+
+#[test]
+fn waits_for_bodies_sent_a_round_trip_after_100_continue() {
+    let stream = serve_one_connection();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    (&stream)
+        .write_all(b"POST /echo HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\nContent-Length: 5\r\nConnection: close\r\n\r\n")
+        .unwrap();
+
+    let mut interim = String::new();
+    reader.read_line(&mut interim).unwrap();
+    assert_eq!(interim, "HTTP/1.1 100 Continue\r\n");
+
+    // Far longer than the per-read timeout, as on a slow link.
+    thread::sleep(Duration::from_millis(100));
+    (&stream).write_all(b"hel").unwrap();
+    thread::sleep(Duration::from_millis(100));
+    (&stream).write_all(b"lo").unwrap();
+    let mut response = String::new();
+    reader.read_to_string(&mut response).unwrap();
+    assert!(response.contains("\r\nHTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(response.ends_with("\r\n\r\nhello"));
+}
+
+#[test]
+fn refuses_bodies_over_the_limit() {
+    let mut stream = serve_one_connection();
+    stream
+        .write_all(b"POST /echo HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n20\r\n0123456789abcdef0123456789abcdef\r\n0\r\n\r\n")
+        .unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 413 Content Too Large\r\n"));
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use std::{
+    env, fs,
+    io::{Read, Write},
+    path::Path,
+};
+
+use webserver::{
+    Request, Response, Router, StatusCode,
+    connection::ConnectionConfig,
+    middleware::{Chain, Sessions},
+    session::FileStore,
+};
+
+const KEY: &[u8; 32] = b"an example key of thirty-two b..";
+
+/// Serves one request on a fresh server keeping sessions in `dir`, as if
+/// the server had been restarted in between.
+fn get(dir: &Path, path: &str, cookie: Option<&str>) -> String {
+    let router = Router::new()
+        .get("/login/:user", |request: &mut Request| {
+            let session = request.session().unwrap();
+            session.regenerate();
+            session.insert("user", request.param("user").unwrap());
+            Response::new(StatusCode::NO_CONTENT)
+        })
+        .get("/whoami", |request: &mut Request| {
+            let user = request.session().unwrap().get::<String>("user");
+            Response::text(StatusCode::OK, user.unwrap_or_else(|| "nobody".to_owned()))
+        })
+        .get("/logout", |request: &mut Request| {
+            request.session().unwrap().destroy();
+            Response::new(StatusCode::NO_CONTENT)
+        });
+    let app = Chain::new(router).with(Sessions::new(FileStore::new(dir).unwrap(), *KEY));
+    let mut stream = common::serve_one_connection(app, ConnectionConfig::default());
+
+    let cookie = cookie.map_or_else(String::new, |cookie| format!("Cookie: {cookie}\r\n"));
+    write!(
+        stream,
+        "GET {path} HTTP/1.1\r\nHost: localhost\r\n{cookie}Connection: close\r\n\r\n"
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+/// The `name=value` part of the `Set-Cookie` header.
+fn set_cookie(response: &str) -> Option<&str> {
+    let line = response
+        .lines()
+        .find_map(|line| line.strip_prefix("Set-Cookie: "))?;
+    line.split(';').next()
+}
+
+#[test]
+fn sessions_survive_restarts_until_logout() {
+    let dir = env::temp_dir().join(format!("sessions-{}", uuid::Uuid::new_v4()));
+
+    let response = get(&dir, "/login/ada", None);
+    assert!(
+        response.contains("; Max-Age=86400; HttpOnly; SameSite=Lax\r\n"),
+        "{response}"
+    );
+    let cookie = set_cookie(&response).unwrap().to_owned();
+    assert!(cookie.starts_with("session="));
+    assert!(get(&dir, "/whoami", Some(&cookie)).ends_with("\r\n\r\nada"));
+    assert!(get(&dir, "/whoami", None).ends_with("\r\n\r\nnobody"));
+
+    let response = get(&dir, "/logout", Some(&cookie));
+    assert_eq!(set_cookie(&response), Some("session="));
+    assert!(get(&dir, "/whoami", Some(&cookie)).ends_with("\r\n\r\nnobody"));
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,101 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use webserver::{
+    Request, Response, Router, Server, ShutdownHandle, ShutdownReport, StatusCode,
+    connection::ConnectionConfig,
+};
+
+fn start(deadline: Duration) -> (TcpStream, ShutdownHandle, JoinHandle<ShutdownReport>) {
+    let router = Router::new()
+        .get("/fast", |_: &mut Request| {
+            Response::text(StatusCode::OK, "fast")
+        })
+        .get("/slow/:ms", |request: &mut Request| {
+            let ms = request.param("ms").unwrap().parse().unwrap();
+            thread::sleep(Duration::from_millis(ms));
+            Response::text(StatusCode::OK, "slow")
+        });
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let server = Server::from_listener(listener, 2, ConnectionConfig::default(), router).unwrap();
+    let client = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+    let handle = server.handle();
+
+    let thread = thread::spawn(move || {
+        server.run().unwrap();
+        server.shutdown(deadline)
+    });
+    (client, handle, thread)
+}
+
+#[test]
+fn lets_requests_in_flight_finish() {
+    let (mut client, handle, server) = start(Duration::from_secs(5));
+    client.write_all(b"GET /slow/200 HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    thread::sleep(Duration::from_millis(50));
+
+    handle.shutdown();
+    let mut response = String::new();
+    client.read_to_string(&mut response).unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("Connection: close\r\n"));
+    assert_eq!(
+        server.join().unwrap(),
+        ShutdownReport {
+            completed: 1,
+            aborted: 0
+        }
+    );
+}
+
+#[test]
+fn closes_idle_keep_alive_connections() {
+    let (mut client, handle, server) = start(Duration::from_secs(5));
+    client.write_all(b"GET /fast HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let mut reader = BufReader::new(client);
+    let mut head = String::new();
+    while !head.ends_with("\r\n\r\n") {
+        assert!(reader.read_line(&mut head).unwrap() > 0, "{head}");
+    }
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+    let length = head
+        .lines()
+        .find_map(|line| line.strip_prefix("Content-Length: "))
+        .unwrap();
+    let mut body = vec![0; length.parse().unwrap()];
+    reader.read_exact(&mut body).unwrap();
+    assert_eq!(body, b"fast");
+
+    let start = Instant::now();
+    handle.shutdown();
+    assert_eq!(reader.read(&mut [0; 512]).unwrap(), 0);
+    assert_eq!(server.join().unwrap().aborted, 0);
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn aborts_requests_past_the_deadline() {
+    let (mut client, handle, server) = start(Duration::from_millis(100));
+    client
+        .write_all(b"GET /slow/1500 HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    thread::sleep(Duration::from_millis(50));
+
+    handle.shutdown();
+    let mut response = Vec::new();
+    let _ = client.read_to_end(&mut response);
+
+    assert!(response.is_empty());
+    assert_eq!(
+        server.join().unwrap(),
+        ShutdownReport {
+            completed: 0,
+            aborted: 1
+        }
+    );
+}
@@ -0,0 +1,93 @@
+mod common;
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+use webserver::{
+    Request, Response,
+    connection::ConnectionConfig,
+    sse::{self, Event},
+};
+
+#[test]
+fn streams_events_and_notices_clients_leaving() {
+    let (done_tx, done) = mpsc::channel();
+    let app = move |request: &mut Request| -> Response {
+        let first = sse::last_event_id(request)
+            .and_then(|id| id.parse::<u64>().ok())
+            .map_or(0, |id| id + 1);
+        let (sender, stream) = sse::channel();
+        let done_tx = done_tx.clone();
+        thread::spawn(move || {
+            for id in first..first + 2 {
+                let event = Event::new(format!("tick {id}")).id(id.to_string());
+                sender.send(event).unwrap();
+            }
+            // Once the client is gone, the stream is dropped and sending fails.
+            while sender.send(Event::new("idle")).is_ok() {
+                thread::sleep(Duration::from_millis(20));
+            }
+            done_tx.send(()).unwrap();
+        });
+        stream.keep_alive(Duration::from_millis(20)).into_response()
+    };
+    let mut stream = common::serve_one_connection(app, ConnectionConfig::default());
+    stream
+        .write_all(b"GET /events HTTP/1.1\r\nHost: localhost\r\nLast-Event-ID: 41\r\n\r\n")
+        .unwrap();
+
+    let mut reader = BufReader::new(stream);
+    let mut head = String::new();
+    while !head.ends_with("\r\n\r\n") {
+        reader.read_line(&mut head).unwrap();
+    }
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+    assert!(head.contains("\r\nContent-Type: text/event-stream\r\n"));
+    assert!(head.contains("\r\nCache-Control: no-cache\r\n"));
+    assert!(head.contains("\r\nTransfer-Encoding: chunked\r\n"));
+
+    // Chunk sizes and events alternate line by line.
+    let mut events = String::new();
+    while !events.contains("tick 43") {
+        reader.read_line(&mut events).unwrap();
+    }
+    assert!(events.contains("id: 42\ndata: tick 42\n"), "{events}");
+    assert!(events.contains("id: 43\ndata: tick 43\n"), "{events}");
+
+    drop(reader);
+    done.recv_timeout(Duration::from_secs(5))
+        .expect("the sender should see the client leave");
+}
+
+#[test]
+fn releases_clients_that_stop_reading() {
+    let (done_tx, done) = mpsc::channel();
+    let app = move |_: &mut Request| -> Response {
+        let (sender, stream) = sse::channel();
+        let done_tx = done_tx.clone();
+        thread::spawn(move || {
+            let data = "x".repeat(64 * 1024);
+            while sender.send(Event::new(data.clone())).is_ok() {}
+            done_tx.send(()).unwrap();
+        });
+        stream.into_response()
+    };
+    let config = ConnectionConfig {
+        write_timeout: Duration::from_millis(200),
+        ..ConnectionConfig::default()
+    };
+    let mut stream = common::serve_one_connection(app, config);
+    stream
+        .write_all(b"GET /events HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+
+    // The connection stays open, but nothing is read once the send and
+    // receive buffers are full.
+    done.recv_timeout(Duration::from_secs(10))
+        .expect("the stuck write should time out");
+    drop(stream);
+}
@@ -0,0 +1,41 @@
+mod common;
+
+use std::io::{Read, Write};
+
+use webserver::{Body, Request, Response, StatusCode, connection::ConnectionConfig};
+
+fn counting(_: &mut Request) -> Response {
+    let lines = (1..=3).map(|n| format!("line {n}\n").into_bytes());
+    Response::text(StatusCode::OK, Body::chunks(lines))
+}
+
+#[test]
+fn streams_are_chunked_for_http11() {
+    let mut stream = common::serve_one_connection(counting, ConnectionConfig::default());
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    assert!(response.contains("Transfer-Encoding: chunked\r\n"));
+    assert!(!response.contains("Content-Length"));
+    assert!(
+        response.ends_with("\r\n\r\n7\r\nline 1\n\r\n7\r\nline 2\n\r\n7\r\nline 3\n\r\n0\r\n\r\n")
+    );
+}
+
+#[test]
+fn streams_end_with_the_connection_for_http10() {
+    let mut stream = common::serve_one_connection(counting, ConnectionConfig::default());
+    stream
+        .write_all(b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n")
+        .unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    assert!(response.contains("Connection: close\r\n"));
+    assert!(response.ends_with("\r\n\r\nline 1\nline 2\nline 3\n"));
+}
@@ -0,0 +1,127 @@
+#![cfg(feature = "tls")]
+
+use std::{
+    env, fs,
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+};
+
+use rustls::{
+    ClientConfig, ClientConnection, RootCertStore, StreamOwned, crypto::ring, pki_types::ServerName,
+};
+use webserver::{
+    Listener, Request, Response, Router, Server, StatusCode, config::SniCertificate,
+    connection::ConnectionConfig, tls,
+};
+
+struct Certificate {
+    cert: PathBuf,
+    key: PathBuf,
+    der: rustls::pki_types::CertificateDer<'static>,
+}
+
+fn certificate(dir: &Path, name: &str) -> Certificate {
+    let generated = rcgen::generate_simple_self_signed(vec![name.to_owned()]).unwrap();
+    let cert = dir.join(format!("{name}.pem"));
+    let key = dir.join(format!("{name}.key"));
+    fs::write(&cert, generated.cert.pem()).unwrap();
+    fs::write(&key, generated.signing_key.serialize_pem()).unwrap();
+    Certificate {
+        cert,
+        key,
+        der: generated.cert.der().clone(),
+    }
+}
+
+/// Starts a server with a redirecting HTTP listener and an HTTPS listener
+/// whose default certificate is for `localhost` and whose SNI certificate is
+/// for `other.test`. Returns both certificates and the two addresses.
+fn start() -> (Certificate, Certificate, SocketAddr, SocketAddr) {
+    let dir = env::temp_dir().join(format!("webserver-tls-{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&dir).unwrap();
+    let localhost = certificate(&dir, "localhost");
+    let other = certificate(&dir, "other.test");
+    let sni = [SniCertificate {
+        server_name: "other.test".to_owned(),
+        cert: other.cert.clone(),
+        key: other.key.clone(),
+    }];
+    let tls = tls::server_config(Some((&localhost.cert, &localhost.key)), &sni).unwrap();
+    fs::remove_dir_all(dir).unwrap();
+
+    let https = TcpListener::bind("127.0.0.1:0").unwrap();
+    let https_port = https.local_addr().unwrap().port();
+    let listeners = vec![
+        Listener::RedirectToHttps {
+            listener: TcpListener::bind("127.0.0.1:0").unwrap(),
+            https_port,
+        },
+        Listener::Https {
+            listener: https,
+            tls,
+        },
+    ];
+    let router = Router::new().get("/hello", |_: &mut Request| {
+        Response::text(StatusCode::OK, "hello over TLS")
+    });
+    let server = Server::from_listeners(listeners, 2, ConnectionConfig::default(), router).unwrap();
+    let addrs = server.local_addrs().unwrap();
+    thread::spawn(move || server.run().unwrap());
+
+    (localhost, other, addrs[0], addrs[1])
+}
+
+fn https_get(address: SocketAddr, server_name: &str, trusted: &Certificate) -> String {
+    let mut roots = RootCertStore::empty();
+    roots.add(trusted.der.clone()).unwrap();
+    let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let name = ServerName::try_from(server_name.to_owned()).unwrap();
+    let connection = ClientConnection::new(Arc::new(config), name).unwrap();
+    let mut stream = StreamOwned::new(connection, TcpStream::connect(address).unwrap());
+
+    stream
+        .write_all(b"GET /hello HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn serves_https_with_sni_certificates() {
+    let (localhost, other, _, https) = start();
+
+    let response = https_get(https, "localhost", &localhost);
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with("hello over TLS"));
+
+    let response = https_get(https, "other.test", &other);
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+}
+
+#[test]
+fn redirects_plain_http() {
+    let (_, _, http, https) = start();
+    let mut client = TcpStream::connect(http).unwrap();
+    client
+        .write_all(
+            b"GET /hello?x=1 HTTP/1.1\r\nHost: example.com:8080\r\nConnection: close\r\n\r\n",
+        )
+        .unwrap();
+    let mut response = String::new();
+    client.read_to_string(&mut response).unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 301 Moved Permanently\r\n"));
+    let location = format!(
+        "Location: https://example.com:{}/hello?x=1\r\n",
+        https.port()
+    );
+    assert!(response.contains(&location), "{response}");
+}
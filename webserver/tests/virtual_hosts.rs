@@ -0,0 +1,86 @@
+mod common;
+
+use std::{
+    env, fs,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use webserver::{ServerConfig, config::VirtualHost, connection::ConnectionConfig};
+
+/// A site directory with an `index.html` saying `name`.
+fn site(base: &Path, name: &str) -> PathBuf {
+    let root = base.join(name);
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("index.html"), name).unwrap();
+    root
+}
+
+#[test]
+fn serves_each_host_its_own_site() {
+    let base = env::temp_dir().join(format!("virtual-hosts-{}", uuid::Uuid::new_v4()));
+    let errors = base.join("errors");
+    fs::create_dir_all(&errors).unwrap();
+    fs::write(errors.join("404.html"), "no such page on docs").unwrap();
+
+    let config = ServerConfig {
+        document_root: site(&base, "fallback"),
+        virtual_host: vec![
+            VirtualHost {
+                names: vec!["docs.test".to_owned()],
+                document_root: site(&base, "docs"),
+                autoindex: Vec::new(),
+                error_pages: Some(errors),
+                proxy: Vec::new(),
+                cgi: Vec::new(),
+                fastcgi: Vec::new(),
+            },
+            VirtualHost {
+                names: vec!["*.apps.test".to_owned()],
+                document_root: site(&base, "apps"),
+                autoindex: vec!["/".to_owned()],
+                error_pages: None,
+                proxy: Vec::new(),
+                cgi: Vec::new(),
+                fastcgi: Vec::new(),
+            },
+        ],
+        ..ServerConfig::default()
+    };
+    config.validate().unwrap();
+
+    let mut stream =
+        common::serve_one_connection(config.virtual_hosts(), ConnectionConfig::default());
+    stream
+        .write_all(
+            b"GET / HTTP/1.1\r\nHost: docs.test\r\n\r\n\
+              GET / HTTP/1.1\r\nHost: billing.apps.test:8080\r\n\r\n\
+              GET / HTTP/1.1\r\nHost: other.test\r\n\r\n\
+              GET /missing HTTP/1.1\r\nHost: DOCS.test\r\n\r\n\
+              GET / HTTP/1.1\r\n\r\n",
+        )
+        .unwrap();
+
+    let mut responses = String::new();
+    stream.read_to_string(&mut responses).unwrap();
+    let served: Vec<(&str, &str)> = responses
+        .split("HTTP/1.1 ")
+        .skip(1)
+        .map(|response| {
+            let (head, body) = response.split_once("\r\n\r\n").unwrap();
+            (&head[..3], body)
+        })
+        .collect();
+    assert_eq!(
+        served,
+        [
+            ("200", "docs"),
+            ("200", "apps"),
+            ("200", "fallback"),
+            ("404", "no such page on docs"),
+            ("400", "400 Bad Request"),
+        ]
+    );
+
+    fs::remove_dir_all(base).unwrap();
+}
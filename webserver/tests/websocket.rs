@@ -0,0 +1,136 @@
+mod common;
+
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+    time::Duration,
+};
+
+use webserver::{
+    Method, Request, Response, Router, Server, StatusCode,
+    connection::ConnectionConfig,
+    websocket::{WebSocket, WebSocketHandler},
+};
+
+fn echo() -> WebSocketHandler<impl Fn(WebSocket) + Send + Sync + 'static> {
+    WebSocketHandler::new(|mut socket: WebSocket| {
+        socket.send("welcome").unwrap();
+        while let Ok(Some(message)) = socket.recv() {
+            socket.send(message).unwrap();
+        }
+    })
+}
+
+/// Sends the opening handshake and returns the response head.
+fn handshake(stream: &mut TcpStream) -> String {
+    stream
+        .write_all(
+            b"GET /ws HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+              Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+              Sec-WebSocket-Version: 13\r\n\r\n",
+        )
+        .unwrap();
+    // Byte by byte, so no frame sent right after the head gets buffered.
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        let mut byte = [0; 1];
+        stream.read_exact(&mut byte).unwrap();
+        head.push(byte[0]);
+    }
+    String::from_utf8(head).unwrap()
+}
+
+fn send_frame(stream: &mut TcpStream, first: u8, payload: &[u8]) {
+    let mask = [0x37, 0xfa, 0x21, 0x3d];
+    let mut frame = vec![first, 0x80 | payload.len() as u8];
+    frame.extend(mask);
+    frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+    stream.write_all(&frame).unwrap();
+}
+
+fn read_frame(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+    let mut head = [0; 2];
+    stream.read_exact(&mut head).unwrap();
+    assert_eq!(head[1] & 0x80, 0, "server frames are not masked");
+    let mut payload = vec![0; usize::from(head[1])];
+    stream.read_exact(&mut payload).unwrap();
+    (head[0], payload)
+}
+
+#[test]
+fn echoes_fragmented_messages_and_answers_pings() {
+    let router = Router::new().route(Method::Get, "/ws", echo());
+    let mut stream = common::serve_one_connection(router, ConnectionConfig::default());
+
+    let head = handshake(&mut stream);
+    assert!(
+        head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"),
+        "{head}"
+    );
+    assert!(head.contains("\r\nSec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+    assert_eq!(read_frame(&mut stream), (0x81, b"welcome".to_vec()));
+
+    send_frame(&mut stream, 0x01, b"Hel");
+    send_frame(&mut stream, 0x89, b"ping");
+    send_frame(&mut stream, 0x80, b"lo");
+    assert_eq!(read_frame(&mut stream), (0x8A, b"ping".to_vec()));
+    assert_eq!(read_frame(&mut stream), (0x81, b"Hello".to_vec()));
+
+    send_frame(&mut stream, 0x88, &1000u16.to_be_bytes());
+    assert_eq!(
+        read_frame(&mut stream),
+        (0x88, 1000u16.to_be_bytes().to_vec())
+    );
+    assert_eq!(stream.read(&mut [0; 1]).unwrap(), 0);
+}
+
+#[test]
+fn refuses_plain_requests() {
+    let router = Router::new().route(Method::Get, "/ws", echo());
+    let mut stream = common::serve_one_connection(router, ConnectionConfig::default());
+    stream
+        .write_all(b"GET /ws HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 426 Upgrade Required\r\n"));
+    assert!(response.contains("\r\nUpgrade: websocket\r\n"));
+}
+
+#[test]
+fn sessions_do_not_hold_up_workers() {
+    let router = Router::new()
+        .route(Method::Get, "/ws", echo())
+        .get("/", |_: &mut Request| Response::text(StatusCode::OK, "ok"));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let server = Server::from_listener(listener, 1, ConnectionConfig::default(), router).unwrap();
+    let address = server.local_addr().unwrap();
+    let handle = server.handle();
+    let running = thread::spawn(move || {
+        server.run().unwrap();
+        server.shutdown(Duration::from_secs(2))
+    });
+
+    let mut socket = TcpStream::connect(address).unwrap();
+    handshake(&mut socket);
+    assert_eq!(read_frame(&mut socket), (0x81, b"welcome".to_vec()));
+
+    // The only worker is free again for plain requests.
+    let mut plain = TcpStream::connect(address).unwrap();
+    plain
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    plain.read_to_string(&mut response).unwrap();
+    assert!(response.ends_with("\r\n\r\nok"), "{response}");
+
+    // Shutdown tells the client the server is going away.
+    handle.shutdown();
+    assert_eq!(
+        read_frame(&mut socket),
+        (0x88, 1001u16.to_be_bytes().to_vec())
+    );
+    assert_eq!(running.join().unwrap().aborted, 0);
+}